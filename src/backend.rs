@@ -0,0 +1,95 @@
+use std::error::Error;
+use std::io::{self, IsTerminal as _};
+use std::path::{Path, PathBuf};
+
+use tracing::debug;
+
+use crate::hooks::Stage;
+use crate::{confirm, display_relative, exec, logging, paths, Logger, SwapOptions, SwapPlan};
+
+/// What a swap needs from a version control system other than plain git. Git worktrees go
+/// through the full pipeline of `run_swap` (stashes, journal, hooks); a backend only finds
+/// the two worktrees and trades their working copies, and [`swap`] does the rest.
+pub(crate) trait Backend {
+    /// Name of the system for messages, e.g. "jj".
+    fn name(&self) -> &'static str;
+
+    /// The directory shared by all worktrees of the repository, which holds the lock, the
+    /// configuration and the hook scripts as `<git-common-dir>` does for git.
+    fn state_dir(&self) -> Result<PathBuf, Box<dyn Error>>;
+
+    /// The branch checked out in the worktree at `dir`.
+    fn current_branch(&self, dir: &Path) -> Result<String, Box<dyn Error>>;
+
+    /// The worktree that has `branch` checked out.
+    fn worktree_for_branch(&self, branch: &str) -> Result<PathBuf, Box<dyn Error>>;
+
+    /// Gives each worktree of `plan` the other one's branch, uncommitted changes included.
+    fn swap(&self, plan: &SwapPlan) -> Result<(), Box<dyn Error>>;
+}
+
+/// Swaps `dest_dir` with the worktree that has `src_branch` checked out, in the same steps
+/// as a git swap: find both sides, ask unless `--yes`, run the pre-swap hooks, trade, run
+/// `--exec`, print the summary and run the post-swap hooks.
+pub(crate) fn swap(
+    backend: &dyn Backend,
+    dest_dir: &Path,
+    src_branch: &str,
+    options: &SwapOptions,
+    logger: &Logger,
+) -> Result<(), Box<dyn Error>> {
+    debug!(
+        "Using the {} backend for '{}'.",
+        backend.name(),
+        dest_dir.display()
+    );
+    let _step = logging::step(
+        1,
+        &format!(
+            "Fetching branch for destination directory '{}'...",
+            dest_dir.display()
+        ),
+    );
+    let dest_branch = backend.current_branch(dest_dir)?;
+    let _step = logging::step(
+        2,
+        &format!("Fetching directory for source branch '{src_branch}'..."),
+    );
+    let src_dir = backend.worktree_for_branch(src_branch)?;
+    if paths::same_path(dest_dir, &src_dir) {
+        return Err("Source and destination directories are the same. Nothing to swap.".into());
+    }
+    let plan = SwapPlan {
+        dest_dir: dest_dir.to_path_buf(),
+        dest_branch,
+        src_dir,
+        src_branch: src_branch.to_string(),
+    };
+    if !options.yes && !logging::ndjson() && io::stdin().is_terminal() {
+        eprintln!(
+            "{} {} ⇄ {} {}; uncommitted changes move with their {} commits",
+            display_relative(&plan.dest_dir),
+            plan.dest_branch,
+            plan.src_branch,
+            display_relative(&plan.src_dir),
+            backend.name()
+        );
+        if !confirm("Swap?") {
+            return Err(
+                "Swap cancelled; nothing was changed. Pass --yes to swap without asking.".into(),
+            );
+        }
+    }
+    options.hooks.run(Stage::PreSwap, &plan)?;
+    let _step = logging::step(3, "Swapping working copies between workspaces...");
+    backend.swap(&plan)?;
+    let exec_report = exec::run_in_worktrees(
+        &options.exec,
+        &[&plan.dest_dir, &plan.src_dir],
+        options.serial,
+    );
+    logger.summary(&plan, false);
+    logger.report("Exec", &exec_report);
+    options.hooks.run(Stage::PostSwap, &plan)?;
+    Ok(())
+}
@@ -0,0 +1,208 @@
+use std::error::Error;
+use std::fs;
+use std::io::{self, IsTerminal as _, Read as _};
+
+use tracing::{error, info};
+
+use crate::{confirm, interrupt, logging, run_swap, Cli, Logger};
+
+/// One line of a batch file: swap `branch` into the worktree at `dir`.
+#[derive(Debug, PartialEq, Eq)]
+struct BatchSwap {
+    /// Line number in the batch file, for messages.
+    line: usize,
+    dir: String,
+    branch: String,
+}
+
+/// What became of one swap of the batch.
+enum Outcome {
+    Swapped,
+    Failed(String),
+    /// Not attempted after an earlier failure or an interrupt.
+    Skipped,
+}
+
+/// Runs the swaps listed in `file` (`-` for stdin) one after another, with the swap flags
+/// of `cli`. The whole file is read and checked first, and with a terminal the list is
+/// confirmed once instead of before every swap. The first failure stops the batch unless
+/// `keep_going`; Ctrl-C always does, after the running swap has been unwound.
+pub(crate) fn run(cli: &Cli, file: &str, keep_going: bool) -> Result<(), Box<dyn Error>> {
+    let input = if file == "-" {
+        let mut input = String::new();
+        io::stdin()
+            .read_to_string(&mut input)
+            .map_err(|err| format!("Failed to read swaps from stdin: {err}"))?;
+        input
+    } else {
+        fs::read_to_string(file).map_err(|err| format!("Failed to read '{file}': {err}"))?
+    };
+    let swaps = parse(&input)?;
+    if swaps.is_empty() {
+        return Err(format!("No swaps listed in '{file}'.").into());
+    }
+    if !cli.yes && !logging::ndjson() && io::stdin().is_terminal() {
+        eprintln!("{}, in this order:", count(swaps.len()));
+        for swap in &swaps {
+            eprintln!("  {} ⇄ {}", swap.dir, swap.branch);
+        }
+        if !confirm("Run them?") {
+            return Err(
+                "Batch cancelled; nothing was changed. Pass --yes to run it without asking.".into(),
+            );
+        }
+    }
+
+    let logger = Logger::new(cli.verbosity());
+    let mut outcomes = Vec::new();
+    let mut stopped = false;
+    for swap in &swaps {
+        if stopped {
+            outcomes.push(Outcome::Skipped);
+            continue;
+        }
+        let mut swap_cli = cli.clone();
+        swap_cli.command = None;
+        swap_cli.destination_worktree_dir = Some(swap.dir.clone());
+        swap_cli.source_branch_name = Some(swap.branch.clone());
+        // The batch was confirmed as a whole, and no single destination is the one to cd into.
+        swap_cli.yes = true;
+        swap_cli.cd_file = None;
+        let outcome = match run_swap(swap_cli) {
+            Ok(()) => Outcome::Swapped,
+            Err(err) => {
+                error!("Line {}: {err}", swap.line);
+                let first_line = err
+                    .to_string()
+                    .lines()
+                    .next()
+                    .unwrap_or_default()
+                    .to_string();
+                stopped = !keep_going;
+                Outcome::Failed(first_line)
+            }
+        };
+        stopped |= interrupt::requested();
+        info!(
+            event = "batch-swap",
+            line = swap.line,
+            dir = swap.dir.as_str(),
+            branch = swap.branch.as_str(),
+            outcome = outcome.label(),
+            "Line {}: {}",
+            swap.line,
+            outcome.label()
+        );
+        outcomes.push(outcome);
+    }
+
+    let lines: Vec<String> = swaps
+        .iter()
+        .zip(&outcomes)
+        .map(|(swap, outcome)| match outcome {
+            Outcome::Failed(reason) => {
+                format!("{} ⇄ {}: failed: {reason}", swap.dir, swap.branch)
+            }
+            outcome => format!("{} ⇄ {}: {}", swap.dir, swap.branch, outcome.label()),
+        })
+        .collect();
+    logger.report("Batch", &lines);
+    let failed = outcomes
+        .iter()
+        .filter(|outcome| matches!(outcome, Outcome::Failed(_)))
+        .count();
+    let skipped = outcomes
+        .iter()
+        .filter(|outcome| matches!(outcome, Outcome::Skipped))
+        .count();
+    let total = count(swaps.len());
+    if interrupt::requested() {
+        return Err(format!("Interrupted; {} of {total} not run.", count(skipped)).into());
+    }
+    match (failed, skipped) {
+        (0, _) => Ok(()),
+        (failed, 0) => Err(format!("{failed} of {total} failed.").into()),
+        (_, skipped) => Err(format!(
+            "Stopped at the failed swap; {} not run. Pass --keep-going to run the rest after a failure.",
+            count(skipped)
+        )
+        .into()),
+    }
+}
+
+/// "1 swap" or "3 swaps".
+fn count(swaps: usize) -> String {
+    match swaps {
+        1 => "1 swap".to_string(),
+        swaps => format!("{swaps} swaps"),
+    }
+}
+
+impl Outcome {
+    fn label(&self) -> &'static str {
+        match self {
+            Outcome::Swapped => "swapped",
+            Outcome::Failed(_) => "failed",
+            Outcome::Skipped => "skipped",
+        }
+    }
+}
+
+/// Reads `<dir> <branch>` lines, skipping blank lines and `#` comments. The branch is the
+/// last word, so directories may contain spaces. Every malformed line is reported at once.
+fn parse(input: &str) -> Result<Vec<BatchSwap>, Box<dyn Error>> {
+    let mut swaps = Vec::new();
+    let mut report = Vec::new();
+    for (index, line) in input.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.rsplit_once(char::is_whitespace) {
+            Some((dir, branch)) => swaps.push(BatchSwap {
+                line: line_number,
+                dir: dir.trim_end().to_string(),
+                branch: branch.to_string(),
+            }),
+            None => report.push(format!("  line {line_number}: '{line}'")),
+        }
+    }
+    if !report.is_empty() {
+        return Err(format!(
+            "Expected `<dir> <branch>` on every line; nothing was swapped:\n{}",
+            report.join("\n")
+        )
+        .into());
+    }
+    Ok(swaps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, BatchSwap};
+
+    #[test]
+    fn reads_one_swap_per_line() {
+        let swaps = parse("# sprint 12\n../api feature/login\n\n  ../my web  main  \n").unwrap();
+        assert_eq!(
+            swaps,
+            [
+                BatchSwap {
+                    line: 2,
+                    dir: "../api".to_string(),
+                    branch: "feature/login".to_string(),
+                },
+                BatchSwap {
+                    line: 4,
+                    dir: "../my web".to_string(),
+                    branch: "main".to_string(),
+                },
+            ]
+        );
+        let err = parse("../api main\n../web\nrelease\n")
+            .unwrap_err()
+            .to_string();
+        assert!(err.ends_with("  line 2: '../web'\n  line 3: 'release'"));
+    }
+}
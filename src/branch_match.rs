@@ -0,0 +1,147 @@
+use std::error::Error;
+use std::io::{self, BufRead as _, IsTerminal as _, Write as _};
+
+use tracing::info;
+
+use crate::snapshot::RepoSnapshot;
+use crate::{display_relative, info_log, logging, Logger};
+
+/// The source branch to swap with: `query` itself when a worktree or local branch has that
+/// exact name, or else the checked-out branch it abbreviates. Several matches are offered
+/// as a numbered list when someone can answer, and reported as an error otherwise. Without
+/// any match `query` is returned unchanged, so the usual "no such branch" errors apply.
+pub(crate) fn resolve(
+    snapshot: &RepoSnapshot,
+    dest_branch: &str,
+    query: &str,
+    logger: &Logger,
+) -> Result<String, Box<dyn Error>> {
+    if snapshot.worktree_entry(query).is_some()
+        || snapshot.local_branches.iter().any(|name| name == query)
+    {
+        return Ok(query.to_string());
+    }
+    let checked_out: Vec<&str> = snapshot
+        .worktrees
+        .iter()
+        .filter_map(|entry| entry.branch.as_deref())
+        .filter(|branch| *branch != dest_branch)
+        .collect();
+    let found = matches(query, &checked_out);
+    let branch = match found.as_slice() {
+        [] => return Ok(query.to_string()),
+        [branch] => branch.to_string(),
+        _ => match choose(snapshot, query, &found) {
+            Some(branch) => branch.to_string(),
+            None if interactive() => {
+                return Err(format!("No branch chosen for '{query}'; nothing was changed.").into())
+            }
+            None => {
+                let names = found.join(", ");
+                return Err(format!(
+                    "'{query}' matches several checked-out branches: {names}.\nPass the full branch name."
+                )
+                .into());
+            }
+        },
+    };
+    info!(
+        event = "branch-matched",
+        query = query,
+        branch = branch.as_str(),
+        "Matched '{query}' to '{branch}'."
+    );
+    info_log!(logger, "Using branch '{branch}' for '{query}'.");
+    Ok(branch)
+}
+
+/// The branches that `query` abbreviates, from the first of these rules that finds any,
+/// ignoring case: the name starts with `query`; each `/`-separated part of `query` starts
+/// the corresponding part of the name (`feat/login` for `feature/login-form`); the name
+/// contains `query`.
+fn matches<'a>(query: &str, branches: &[&'a str]) -> Vec<&'a str> {
+    let query = query.to_lowercase();
+    let rules: [&dyn Fn(&str) -> bool; 3] = [
+        &|name| name.starts_with(&query),
+        &|name| {
+            let parts: Vec<&str> = name.split('/').collect();
+            let wanted: Vec<&str> = query.split('/').collect();
+            wanted.len() <= parts.len()
+                && wanted
+                    .iter()
+                    .zip(&parts)
+                    .all(|(wanted, part)| part.starts_with(wanted))
+        },
+        &|name| name.contains(&query),
+    ];
+    for rule in rules {
+        let found: Vec<&str> = branches
+            .iter()
+            .copied()
+            .filter(|branch| rule(&branch.to_lowercase()))
+            .collect();
+        if !found.is_empty() {
+            return found;
+        }
+    }
+    Vec::new()
+}
+
+/// Whether a numbered list can be answered: stdin is a terminal and stdout is not taken
+/// by NDJSON for a frontend.
+fn interactive() -> bool {
+    !logging::ndjson() && io::stdin().is_terminal()
+}
+
+/// Lists `found` with the worktree holding each branch and asks for a number. `None` when
+/// nobody can answer or the answer picks nothing.
+fn choose<'a>(snapshot: &RepoSnapshot, query: &str, found: &[&'a str]) -> Option<&'a str> {
+    if !interactive() {
+        return None;
+    }
+    eprintln!("'{query}' matches several checked-out branches:");
+    let width = found.iter().map(|branch| branch.chars().count()).max();
+    let width = width.unwrap_or(0);
+    for (number, branch) in found.iter().enumerate() {
+        let dir = snapshot
+            .worktree_entry(branch)
+            .map(|entry| display_relative(&entry.path))
+            .unwrap_or_default();
+        eprintln!("  {}) {branch:width$}  {dir}", number + 1);
+    }
+    eprint!("Swap with which one? [1-{}, Enter to cancel] ", found.len());
+    let _ = io::stderr().flush();
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer).ok()?;
+    let number: usize = answer.trim().parse().ok()?;
+    found.get(number.checked_sub(1)?).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matches;
+
+    #[test]
+    fn prefers_prefixes_over_substrings() {
+        let branches = [
+            "main",
+            "feature/login-form",
+            "feature/logout",
+            "JIRA-1234-fix-login-timeout",
+        ];
+        assert_eq!(matches("feat/login", &branches), ["feature/login-form"]);
+        assert_eq!(
+            matches("feature/log", &branches),
+            ["feature/login-form", "feature/logout"]
+        );
+        assert_eq!(
+            matches("jira-1234", &branches),
+            ["JIRA-1234-fix-login-timeout"]
+        );
+        assert_eq!(
+            matches("login", &branches),
+            ["feature/login-form", "JIRA-1234-fix-login-timeout"]
+        );
+        assert!(matches("release", &branches).is_empty());
+    }
+}
@@ -0,0 +1,154 @@
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tracing::debug;
+
+use crate::holding::Holding;
+use crate::{git_args, run_git_success};
+
+/// Untracked or ignored files matching `carry_files` that were moved out of a worktree
+/// before the swap and wait in a holding directory until they reach their branch's new home.
+pub(crate) struct CarriedFiles {
+    origin: PathBuf,
+    held: Option<Holding>,
+}
+
+/// Takes the matching files out of both worktrees, given by their top levels, holding them
+/// under `base`. When the second worktree fails, the first one gets its files back.
+pub(crate) fn take_both(
+    dest_dir: &Path,
+    src_dir: &Path,
+    patterns: &[String],
+    base: &Path,
+) -> Result<(CarriedFiles, CarriedFiles), Box<dyn Error>> {
+    let dest = take(dest_dir, patterns, &base.join("dest"))?;
+    match take(src_dir, patterns, &base.join("src")) {
+        Ok(src) => Ok((dest, src)),
+        Err(err) => {
+            let _ = dest.put_back();
+            Err(err)
+        }
+    }
+}
+
+/// Delivers each worktree's files to the other one and returns one line per worktree that
+/// received files.
+pub(crate) fn exchange(dest: &CarriedFiles, src: &CarriedFiles) -> Vec<String> {
+    [(dest, &src.origin), (src, &dest.origin)]
+        .into_iter()
+        .filter_map(|(carried, target)| {
+            let held = carried.held.as_ref()?;
+            match deliver(held, target) {
+                Ok(summary) => summary,
+                Err(err) => Some(format!(
+                    "'{}': failed to carry files ({err}); the rest are still in '{}'",
+                    target.display(),
+                    held.dir().display()
+                )),
+            }
+        })
+        .collect()
+}
+
+/// Moves the files of `dir` that match `patterns` (globs relative to the worktree root) into
+/// `holding`.
+fn take(dir: &Path, patterns: &[String], holding: &Path) -> Result<CarriedFiles, Box<dyn Error>> {
+    let mut carried = CarriedFiles {
+        origin: dir.to_path_buf(),
+        held: None,
+    };
+    if patterns.is_empty() {
+        return Ok(carried);
+    }
+    let mut args = git_args!["ls-files", "--others", "-z", "--"];
+    args.extend(pathspecs(patterns));
+    let output = run_git_success(Some(dir), args, "Failed to list files to carry.")?;
+    let paths: Vec<String> = output
+        .stdout
+        .split('\0')
+        .filter(|path| !path.is_empty())
+        .map(str::to_string)
+        .collect();
+    debug!(
+        "Carrying {} file(s) out of '{}'...",
+        paths.len(),
+        dir.display()
+    );
+    carried.held = Some(Holding::take(dir, paths, holding, move_file)?);
+    Ok(carried)
+}
+
+impl CarriedFiles {
+    /// Moves the files back to the worktree they came from, after a failed swap.
+    pub(crate) fn put_back(&self) -> Result<(), Box<dyn Error>> {
+        match &self.held {
+            Some(held) => held.put_back(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Moves the held files into `target`, which now holds their branch, and describes the
+/// result. A file that already exists in `target` with different content is kept, and the
+/// incoming copy is written next to it with an `.incoming` suffix.
+fn deliver(held: &Holding, target: &Path) -> Result<Option<String>, Box<dyn Error>> {
+    if held.paths().is_empty() {
+        return Ok(None);
+    }
+    let collisions = held.deliver(target)?;
+    let mut summary = format!(
+        "'{}': {} file(s) carried from '{}'",
+        target.display(),
+        held.paths().len(),
+        held.origin().display()
+    );
+    if !collisions.is_empty() {
+        summary.push_str(&format!(
+            "; already present, incoming copies saved as: {}",
+            collisions.join(", ")
+        ));
+    }
+    Ok(Some(summary))
+}
+
+/// Moves a file, falling back to copy-and-delete when `from` and `to` are on different
+/// file systems.
+fn move_file(from: &Path, to: &Path) -> Result<(), Box<dyn Error>> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::rename(from, to).is_err() {
+        fs::copy(from, to).map_err(|err| {
+            format!(
+                "Failed to move '{}' to '{}': {err}",
+                from.display(),
+                to.display()
+            )
+        })?;
+        fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+fn pathspecs(patterns: &[String]) -> Vec<OsString> {
+    patterns
+        .iter()
+        .map(|pattern| OsString::from(format!(":(glob){pattern}")))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::pathspecs;
+
+    #[test]
+    fn matches_patterns_as_globs_from_the_root() {
+        let patterns = [".env".to_string(), "local.settings/*".to_string()];
+        assert_eq!(
+            pathspecs(&patterns),
+            [":(glob).env", ":(glob)local.settings/*"]
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+
+use tracing::warn;
+
+use crate::{git_args, run_git_success, StashRecord};
+
+/// Refuses to apply `stash` to `dir` on a case-insensitive file system (`core.ignorecase`)
+/// when a path it touches differs only in case from one in the worktree, as after a rename
+/// from `Foo.rs` to `foo.rs` on one of the branches: git would write both to the same file.
+/// Returns the report used as the reason the stash was not applied. With `force`, only warns.
+pub(crate) fn check(dir: &Path, stash: &StashRecord, force: bool) -> Result<(), String> {
+    let collisions = match find(dir, &stash.hash) {
+        Ok(collisions) => collisions,
+        Err(err) => {
+            warn!(
+                "Skipping the case collision check in '{}': {err}",
+                dir.display()
+            );
+            return Ok(());
+        }
+    };
+    if collisions.is_empty() {
+        return Ok(());
+    }
+    let listing: Vec<String> = collisions
+        .iter()
+        .map(|(incoming, existing)| format!("  {incoming} (stash) vs {existing}"))
+        .collect();
+    let listing = listing.join("\n");
+    if force {
+        warn!(
+            "Applying stash {} to '{}' despite paths that differ only in case:\n{listing}",
+            stash.hash,
+            dir.display()
+        );
+        return Ok(());
+    }
+    Err(format!(
+        "Not applied: on this case-insensitive file system, these paths would overwrite each other:\n{listing}\nRename one side to match, then apply the stash by hand; or pass --force to apply it anyway."
+    ))
+}
+
+/// Paths the stash touches next to the worktree paths they collide with, when git treats
+/// `dir` as case-insensitive.
+fn find(dir: &Path, hash: &str) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let ignorecase = run_git_success(
+        Some(dir),
+        git_args!["config", "--bool", "--default", "false", "core.ignorecase"],
+        "Failed to read core.ignorecase.",
+    )?;
+    if ignorecase.stdout.trim() != "true" {
+        return Ok(Vec::new());
+    }
+    let touched = run_git_success(
+        Some(dir),
+        git_args![
+            "stash",
+            "show",
+            "--include-untracked",
+            "--name-only",
+            "--no-renames",
+            hash
+        ],
+        "Failed to list the paths of the stash.",
+    )?;
+    let present = run_git_success(
+        Some(dir),
+        git_args!["ls-files", "--cached", "--others", "--exclude-standard"],
+        "Failed to list the worktree's files.",
+    )?;
+    let touched: Vec<&str> = touched.stdout.lines().collect();
+    let present: Vec<&str> = present.stdout.lines().collect();
+    Ok(collisions(&touched, &present))
+}
+
+/// Pairs each of `incoming` whose path, or one of its directories, matches one of
+/// `existing` when case is ignored but not when it is compared exactly.
+fn collisions(incoming: &[&str], existing: &[&str]) -> Vec<(String, String)> {
+    let mut folded: HashMap<String, &str> = HashMap::new();
+    for path in existing {
+        for prefix in prefixes(path) {
+            folded.entry(prefix.to_lowercase()).or_insert(prefix);
+        }
+    }
+    let mut found = Vec::new();
+    for path in incoming {
+        let collision = prefixes(path).find_map(|prefix| {
+            let other = folded.get(&prefix.to_lowercase())?;
+            (*other != prefix).then(|| (prefix.to_string(), other.to_string()))
+        });
+        if let Some(collision) = collision.filter(|collision| !found.contains(collision)) {
+            found.push(collision);
+        }
+    }
+    found
+}
+
+/// `a`, `a/b` and `a/b/c` for `a/b/c`.
+fn prefixes(path: &str) -> impl Iterator<Item = &str> {
+    path.match_indices('/')
+        .map(|(index, _)| &path[..index])
+        .chain([path])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::collisions;
+
+    #[test]
+    fn finds_paths_that_differ_only_in_case() {
+        let existing = ["Foo.rs", "src/Main.rs", "docs/guide.md", "README.md"];
+        let incoming = [
+            "foo.rs",
+            "Src/lib.rs",
+            "docs/guide.md",
+            "src/Main.rs",
+            "new.txt",
+        ];
+        assert_eq!(
+            collisions(&incoming, &existing),
+            [
+                ("foo.rs".to_string(), "Foo.rs".to_string()),
+                ("Src".to_string(), "src".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,50 @@
+use std::error::Error;
+use std::path::Path;
+
+use tracing::warn;
+
+use crate::status::{short_hash, swap_stashes};
+use crate::{confirm, drop_stash, find_stash_reference, git_common_dir, lock};
+
+/// Lists the swap stashes of the repository containing `dir` and drops them once the user
+/// confirms, or right away with `yes`.
+pub(crate) fn clean(dir: &Path, yes: bool) -> Result<(), Box<dyn Error>> {
+    // A running swap may be about to apply one of these stashes.
+    let _repo_lock = lock::RepoLock::acquire(&git_common_dir(dir)?)?;
+    let stashes = swap_stashes(dir)?;
+    if stashes.is_empty() {
+        println!("No swap stashes to clean.");
+        return Ok(());
+    }
+    println!("Swap stashes:");
+    for stash in &stashes {
+        println!("  {}", stash.describe());
+    }
+    if !yes && !confirm(&format!("Drop {} swap stash(es)?", stashes.len())) {
+        println!("Nothing dropped. Pass --yes to drop without asking.");
+        return Ok(());
+    }
+    let mut failed = 0;
+    for stash in &stashes {
+        // Dropping shifts the stash@{n} references, so look each one up again by commit.
+        let result = find_stash_reference(dir, &stash.hash).and_then(|reference| {
+            let reference = reference.ok_or("it is no longer in the stash list")?;
+            drop_stash(dir, &reference)
+        });
+        match result {
+            Ok(()) => println!(
+                "Dropped {} (from '{}').",
+                short_hash(&stash.hash),
+                stash.branch
+            ),
+            Err(err) => {
+                failed += 1;
+                warn!("Could not drop {}: {err}", short_hash(&stash.hash));
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{failed} swap stash(es) could not be dropped.").into());
+    }
+    Ok(())
+}
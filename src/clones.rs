@@ -0,0 +1,396 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use tracing::debug;
+
+use crate::hooks::Stage;
+use crate::snapshot::RepoSnapshot;
+use crate::{
+    apply_and_drop_stash, carry_files, check_interrupt, confirm_swap, detach_worktree, drafts,
+    ensure_clean_worktrees, ensure_unlocked, exec, git_args, index_flags, interrupt, journal, lfs,
+    list_worktree_branches, lock, logging, paths, record_stash, report_kept_stashes,
+    report_slowest_step, run_git, run_git_success, sequencer, stash_in_parallel, status,
+    switch_fast, switch_worktree, timings, unwind_failed_step, Logger, StashRecord, SwapOptions,
+    SwapPlan,
+};
+
+const INCOMING_BRANCH_REF: &str = "refs/swap-worktree/incoming-branch";
+const INCOMING_STASH_REF: &str = "refs/swap-worktree/incoming-stash";
+const OUTGOING_STASH_REF: &str = "refs/swap-worktree/outgoing-stash";
+
+/// Swaps branch state between `dest_dir` and the worktree of a separate clone that has
+/// `src_branch` checked out. Commits and stashes travel between the clones through
+/// temporary refs under `refs/swap-worktree/`, which are removed once the swap finishes.
+pub(crate) fn swap_across_clones(
+    dest_dir: &Path,
+    dest_branch: &str,
+    dest_snapshot: &RepoSnapshot,
+    clone_arg: &str,
+    src_branch: &str,
+    options: &SwapOptions,
+    logger: &Logger,
+) -> Result<(), Box<dyn Error>> {
+    let clone_dir = clone_dir(clone_arg)?;
+    let clone_snapshot = RepoSnapshot::read(&clone_dir)?;
+
+    let _step = logging::step(
+        2,
+        &format!(
+            "Fetching directory for source branch '{src_branch}' in clone '{}'...",
+            clone_dir.display()
+        ),
+    );
+    let src_dir = clone_snapshot.worktree_for_branch(src_branch)?;
+    debug!("Found source directory: '{}'", src_dir.display());
+    let src_common_dir = &clone_snapshot.common_dir;
+    if paths::same_path(&dest_snapshot.common_dir, src_common_dir) {
+        return Err(format!(
+            "'{}' and '{}' belong to the same repository. Run without --across-clones to swap linked worktrees.",
+            dest_dir.display(),
+            src_dir.display()
+        )
+        .into());
+    }
+    let _clone_lock = lock::RepoLock::acquire(src_common_dir)?;
+    ensure_shared_history(dest_dir, dest_branch, &src_dir, src_branch)?;
+    sequencer::ensure_no_operations_in_progress(
+        &[(&src_dir, &sequencer::operations_in_progress(&src_dir)?)],
+        options.force,
+    )?;
+    ensure_unlocked(&dest_snapshot.worktrees, &[dest_dir], options.ignore_locks)?;
+    ensure_unlocked(&clone_snapshot.worktrees, &[&src_dir], options.ignore_locks)?;
+    if options.no_stash {
+        ensure_clean_worktrees(&[(dest_dir, dest_branch), (&src_dir, src_branch)])?;
+    }
+
+    let hook_plan = SwapPlan {
+        dest_dir: dest_dir.to_path_buf(),
+        dest_branch: dest_branch.to_string(),
+        src_dir: src_dir.clone(),
+        src_branch: src_branch.to_string(),
+    };
+    confirm_swap(&hook_plan, options, &[])?;
+    interrupt::install()?;
+    let _journal = journal::Journal::begin(&dest_snapshot.common_dir, &hook_plan)?;
+    options.hooks.run(Stage::PreSwap, &hook_plan)?;
+    let lfs_active = lfs::prepare(dest_dir, &[dest_branch], options.lfs)?
+        | lfs::prepare(&src_dir, &[src_branch], options.lfs)?;
+
+    let dest_drafts = drafts::take(dest_dir)?;
+    let src_drafts = drafts::take(&src_dir)?;
+    let (dest_carried, src_carried) = carry_files::take_both(
+        &dest_snapshot.toplevel,
+        &src_dir,
+        &options.carry_files,
+        &dest_snapshot.common_dir.join("swap-worktree").join("carry"),
+    )?;
+    let (dest_flags, src_flags) = match index_flags::take_both(dest_dir, &src_dir) {
+        Ok(flags) => flags,
+        Err(err) => {
+            let _ = dest_carried.put_back();
+            let _ = src_carried.put_back();
+            return Err(err);
+        }
+    };
+
+    let _step = logging::step(3, "Fetching branches into temporary refs...");
+    let result = swap_with_temporary_refs(&hook_plan, options);
+    cleanup_temporary_refs(dest_dir);
+    cleanup_temporary_refs(&src_dir);
+    let stashes = match result {
+        Ok(stashes) => stashes,
+        Err(err) => {
+            let _ = index_flags::restore(dest_dir, &dest_flags);
+            let _ = index_flags::restore(&src_dir, &src_flags);
+            let _ = dest_carried.put_back();
+            let _ = src_carried.put_back();
+            return Err(err);
+        }
+    };
+    let carry_report = carry_files::exchange(&dest_carried, &src_carried);
+    let flags_report = index_flags::carry(dest_dir, &dest_flags, &src_dir, &src_flags);
+    let drafts_report = drafts::exchange(dest_dir, &dest_drafts, &src_dir, &src_drafts);
+    let lfs_report = if lfs_active {
+        lfs::finish(&[dest_dir, &src_dir], options.lfs, logger)
+    } else {
+        Vec::new()
+    };
+    let exec_report = exec::run_in_worktrees(&options.exec, &[dest_dir, &src_dir], options.serial);
+    let stat_report = if options.stat {
+        status::after_swap(&[dest_dir, &src_dir])
+    } else {
+        Vec::new()
+    };
+
+    debug!("Clone swap complete.");
+    logger.summary(&hook_plan, false);
+    logger.report("Carried files", &carry_report);
+    logger.report("Commit message drafts", &drafts_report);
+    logger.report("Index flags", &flags_report);
+    logger.report("Git LFS", &lfs_report);
+    logger.report("Exec", &exec_report);
+    logger.report("Worktree state", &stat_report);
+    if options.keep_stash {
+        report_kept_stashes(&stashes, logger);
+    }
+    report_slowest_step(options, logger);
+    timings::report(logger);
+    options.hooks.run(Stage::PostSwap, &hook_plan)?;
+    Ok(())
+}
+
+fn swap_with_temporary_refs(
+    plan: &SwapPlan,
+    options: &SwapOptions,
+) -> Result<Vec<StashRecord>, Box<dyn Error>> {
+    let SwapPlan {
+        dest_dir,
+        dest_branch,
+        src_dir,
+        src_branch,
+    } = plan;
+    fetch_ref(
+        dest_dir,
+        src_dir,
+        &format!("refs/heads/{src_branch}"),
+        INCOMING_BRANCH_REF,
+    )?;
+    fetch_ref(
+        src_dir,
+        dest_dir,
+        &format!("refs/heads/{dest_branch}"),
+        INCOMING_BRANCH_REF,
+    )?;
+    ensure_branch_can_be_updated(dest_dir, src_branch)?;
+    ensure_branch_can_be_updated(src_dir, dest_branch)?;
+    check_interrupt(plan, None, None, options)?;
+
+    let _step = logging::step(
+        4,
+        &format!(
+            "Stashing changes in both clones ({})...",
+            options.stash_scope()
+        ),
+    );
+    journal::record("step", "stashing");
+    let [dest_result, src_result] =
+        stash_in_parallel([(dest_dir, dest_branch), (src_dir, src_branch)], options);
+    let (dest_stash, src_stash) = match (dest_result, src_result) {
+        (Ok(dest_stash), Ok(src_stash)) => (dest_stash, src_stash),
+        (Err(err), src_result) => {
+            let src_stash = src_result.ok().flatten();
+            record_stash("src_stash", src_stash.as_ref());
+            return Err(unwind_failed_step(
+                err,
+                plan,
+                [None, src_stash.as_ref()],
+                options,
+                "Failed to stash destination clone.",
+            ));
+        }
+        (Ok(dest_stash), Err(err)) => {
+            record_stash("dest_stash", dest_stash.as_ref());
+            return Err(unwind_failed_step(
+                err,
+                plan,
+                [dest_stash.as_ref(), None],
+                options,
+                "Failed to stash source clone.",
+            ));
+        }
+    };
+    record_stash("dest_stash", dest_stash.as_ref());
+    record_stash("src_stash", src_stash.as_ref());
+    // From here on, a failure switches both clones back to their own branch and re-applies
+    // their own stash, as a failed swap of linked worktrees does.
+    let stashes = [dest_stash.as_ref(), src_stash.as_ref()];
+    let unwind = |err, reason: &str| unwind_failed_step(err, plan, stashes, options, reason);
+    let interrupted = || check_interrupt(plan, dest_stash.as_ref(), src_stash.as_ref(), options);
+    interrupted()?;
+    let transferred = src_stash
+        .iter()
+        .try_for_each(|stash| transfer_stash(src_dir, dest_dir, stash))
+        .and_then(|()| {
+            dest_stash
+                .iter()
+                .try_for_each(|stash| transfer_stash(dest_dir, src_dir, stash))
+        });
+    if let Err(err) = transferred {
+        return Err(unwind(err, "Failed to transfer stashes between clones."));
+    }
+    interrupted()?;
+
+    let _step = logging::step(5, "Swapping branches between clones...");
+    if let Err(err) =
+        detach_worktree(dest_dir, dest_branch).and_then(|()| detach_worktree(src_dir, src_branch))
+    {
+        return Err(unwind(err, "Failed to detach clones."));
+    }
+    interrupted()?;
+    if let Err(err) =
+        update_branch(dest_dir, src_branch).and_then(|()| update_branch(src_dir, dest_branch))
+    {
+        return Err(unwind(
+            err,
+            "Failed to update branches from the other clone.",
+        ));
+    }
+    interrupted()?;
+    let switch_env = options.switch_env();
+    if let Err(err) = switch_fast(dest_dir, src_branch, &switch_env, switch_worktree) {
+        return Err(unwind(err, "Failed to switch destination clone."));
+    }
+    journal::record("step", "switched-dest");
+    interrupted()?;
+    if let Err(err) = switch_fast(src_dir, dest_branch, &switch_env, switch_worktree) {
+        return Err(unwind(err, "Failed to switch source clone."));
+    }
+    journal::record("step", "switched");
+    interrupted()?;
+
+    let _step = logging::step(6, "Applying stashes to their new locations...");
+    apply_and_drop_stash(dest_dir, src_branch, src_stash.as_ref(), options);
+    apply_and_drop_stash(src_dir, dest_branch, dest_stash.as_ref(), options);
+    journal::finish();
+    Ok(dest_stash.into_iter().chain(src_stash).collect())
+}
+
+fn clone_dir(arg: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let dir = Path::new(arg);
+    if !dir.is_dir() {
+        return Err(format!("Source clone directory '{}' does not exist.", dir.display()).into());
+    }
+    Ok(paths::canonicalize(dir)?)
+}
+
+fn ensure_shared_history(
+    dest_dir: &Path,
+    dest_branch: &str,
+    src_dir: &Path,
+    src_branch: &str,
+) -> Result<(), Box<dyn Error>> {
+    let dest_roots = root_commits(dest_dir, dest_branch)?;
+    let src_roots = root_commits(src_dir, src_branch)?;
+    if dest_roots.is_disjoint(&src_roots) {
+        return Err(format!(
+            "'{}' and '{}' do not share any history; they do not look like clones of the same repository.",
+            dest_dir.display(),
+            src_dir.display()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn root_commits(dir: &Path, branch: &str) -> Result<BTreeSet<String>, Box<dyn Error>> {
+    let output = run_git_success(
+        Some(dir),
+        git_args!["rev-list", "--max-parents=0", branch],
+        "Failed to determine root commits.",
+    )?;
+    Ok(output.stdout.lines().map(str::to_string).collect())
+}
+
+fn fetch_ref(
+    repo_dir: &Path,
+    from_dir: &Path,
+    src_ref: &str,
+    dst_ref: &str,
+) -> Result<(), Box<dyn Error>> {
+    debug!(
+        "Fetching {src_ref} from '{}' into {dst_ref} of '{}'...",
+        from_dir.display(),
+        repo_dir.display()
+    );
+    run_git_success(
+        Some(repo_dir),
+        vec![
+            OsString::from("fetch"),
+            OsString::from("--quiet"),
+            OsString::from("--no-tags"),
+            from_dir.as_os_str().to_os_string(),
+            OsString::from(format!("+{src_ref}:{dst_ref}")),
+        ],
+        "Failed to fetch from the other clone.",
+    )?;
+    Ok(())
+}
+
+/// Refuses to move `branch` in `repo_dir` unless the fetched commit fast-forwards it and
+/// no worktree of that clone currently has it checked out.
+fn ensure_branch_can_be_updated(repo_dir: &Path, branch: &str) -> Result<(), Box<dyn Error>> {
+    if list_worktree_branches(repo_dir)?
+        .iter()
+        .any(|name| name == branch)
+    {
+        return Err(format!(
+            "Branch '{branch}' is already checked out in another worktree of '{}'.",
+            repo_dir.display()
+        )
+        .into());
+    }
+    let local_ref = format!("refs/heads/{branch}");
+    let exists = run_git(
+        Some(repo_dir),
+        git_args!["rev-parse", "--verify", "--quiet", &local_ref],
+    )?;
+    if !exists.status.success() {
+        return Ok(());
+    }
+    let ancestor = run_git(
+        Some(repo_dir),
+        git_args![
+            "merge-base",
+            "--is-ancestor",
+            &local_ref,
+            INCOMING_BRANCH_REF
+        ],
+    )?;
+    if !ancestor.status.success() {
+        return Err(format!(
+            "Branch '{branch}' in '{}' has commits that the other clone does not have. Synchronize the branch before swapping.",
+            repo_dir.display()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+pub(crate) fn transfer_stash(
+    from_dir: &Path,
+    to_dir: &Path,
+    stash: &StashRecord,
+) -> Result<(), Box<dyn Error>> {
+    run_git_success(
+        Some(from_dir),
+        git_args!["update-ref", OUTGOING_STASH_REF, &stash.hash],
+        "Failed to publish stash for transfer.",
+    )?;
+    fetch_ref(to_dir, from_dir, OUTGOING_STASH_REF, INCOMING_STASH_REF)
+}
+
+fn update_branch(repo_dir: &Path, branch: &str) -> Result<(), Box<dyn Error>> {
+    debug!(
+        "Updating '{branch}' in '{}' to the fetched commit...",
+        repo_dir.display()
+    );
+    run_git_success(
+        Some(repo_dir),
+        git_args![
+            "update-ref",
+            format!("refs/heads/{branch}"),
+            INCOMING_BRANCH_REF
+        ],
+        "Failed to update branch from the other clone.",
+    )?;
+    Ok(())
+}
+
+pub(crate) fn cleanup_temporary_refs(repo_dir: &Path) {
+    for reference in [INCOMING_BRANCH_REF, INCOMING_STASH_REF, OUTGOING_STASH_REF] {
+        let _ = run_git(Some(repo_dir), git_args!["update-ref", "-d", reference]);
+    }
+    debug!("Removed temporary refs from '{}'.", repo_dir.display());
+}
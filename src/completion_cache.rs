@@ -0,0 +1,131 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::paths::{self, normalize_path};
+use crate::{git_args, git_common_dir, run_git_success};
+
+/// Even with an unchanged key, entries older than this are refreshed, e.g. to notice a
+/// worktree on a drive that was unmounted in the meantime.
+const MAX_AGE: Duration = Duration::from_secs(30);
+
+/// Returns the git common dir and `git worktree list --porcelain` for the repository
+/// containing `dir`, the latter from `<git-common-dir>/swap-worktree/completion-cache`
+/// while nothing has changed. The cache is
+/// keyed by the modification times of the files git updates when worktrees are added,
+/// removed, locked, or switched to another branch.
+pub(crate) fn worktree_porcelain(dir: &Path) -> Result<(PathBuf, String), Box<dyn Error>> {
+    let common_dir = match locate_common_dir(dir) {
+        Some(common_dir) => common_dir,
+        None => git_common_dir(dir)?,
+    };
+    let cache_path = common_dir.join("swap-worktree").join("completion-cache");
+    let key = cache_key(&common_dir);
+    let fresh = fs::metadata(&cache_path)
+        .and_then(|metadata| metadata.modified())
+        .is_ok_and(|modified| modified.elapsed().is_ok_and(|age| age < MAX_AGE));
+    if fresh {
+        if let Ok(contents) = fs::read_to_string(&cache_path) {
+            if let Some(output) = cached_output(&contents, &key) {
+                return Ok((common_dir, output.to_string()));
+            }
+        }
+    }
+    let output = run_git_success(
+        Some(dir),
+        git_args!["worktree", "list", "--porcelain"],
+        "Failed to list worktrees.",
+    )?
+    .stdout;
+    // Completion must never fail because the cache cannot be written.
+    let _ = write_cache(&cache_path, &key, &output);
+    Ok((common_dir, output))
+}
+
+/// Finds the git common dir by reading `.git` files instead of spawning git, which is the
+/// slow part on network file systems. Returns `None` for layouts it does not recognise.
+fn locate_common_dir(dir: &Path) -> Option<PathBuf> {
+    for ancestor in dir.ancestors() {
+        let dot_git = ancestor.join(".git");
+        if dot_git.is_dir() {
+            return Some(dot_git);
+        }
+        if dot_git.is_file() {
+            let contents = fs::read_to_string(&dot_git).ok()?;
+            let git_dir = normalize_path(ancestor, contents.strip_prefix("gitdir:")?.trim());
+            let common_dir = match fs::read_to_string(git_dir.join("commondir")) {
+                Ok(common) => normalize_path(&git_dir, common.trim()),
+                Err(_) => git_dir,
+            };
+            // Every worktree must arrive at the same spelling, or they would keep
+            // overwriting each other's cache.
+            return paths::canonicalize(&common_dir).ok();
+        }
+    }
+    None
+}
+
+/// Modification times of the main `HEAD`, the `worktrees` directory, and every linked
+/// worktree's administrative directory and `HEAD`.
+fn cache_key(common_dir: &Path) -> String {
+    let mut paths = vec![common_dir.join("HEAD"), common_dir.join("worktrees")];
+    if let Ok(entries) = fs::read_dir(common_dir.join("worktrees")) {
+        let mut admin_dirs: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).collect();
+        admin_dirs.sort();
+        for admin_dir in admin_dirs {
+            paths.push(admin_dir.join("HEAD"));
+            paths.push(admin_dir);
+        }
+    }
+    paths
+        .iter()
+        .map(|path| {
+            let modified = fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |since| since.as_nanos());
+            format!("{}={modified}", path.display())
+        })
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// The cached output, when `contents` was written for `key`.
+fn cached_output<'a>(contents: &'a str, key: &str) -> Option<&'a str> {
+    let (stored_key, output) = contents.split_once('\n')?;
+    (stored_key == key).then_some(output)
+}
+
+fn write_cache(path: &Path, key: &str, output: &str) -> Result<(), Box<dyn Error>> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // Write under a unique name first so a concurrent completion never reads half a file.
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos());
+    let temporary = path.with_extension(format!("{}-{stamp}", std::process::id()));
+    fs::write(&temporary, format!("{key}\n{output}"))?;
+    fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::cached_output;
+
+    #[test]
+    fn uses_the_cache_only_for_a_matching_key() {
+        let contents = "/repo/.git/HEAD=1;/repo/.git/worktrees=2\nworktree /repo\nHEAD e1e1b70\n";
+        assert_eq!(
+            cached_output(contents, "/repo/.git/HEAD=1;/repo/.git/worktrees=2"),
+            Some("worktree /repo\nHEAD e1e1b70\n")
+        );
+        assert_eq!(
+            cached_output(contents, "/repo/.git/HEAD=1;/repo/.git/worktrees=3"),
+            None
+        );
+    }
+}
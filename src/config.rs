@@ -0,0 +1,259 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+
+/// Settings read from `config.toml`. The user-level file is loaded first and the
+/// repository-level file (under the git common dir) overrides it key by key.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct Config {
+    pub(crate) keep_stash: Option<bool>,
+    pub(crate) confirm: Option<bool>,
+    /// Bytes, from a number or a string such as `"200MB"`.
+    #[serde(deserialize_with = "deserialize_size")]
+    pub(crate) max_stash_size: Option<u64>,
+    /// Template for stash messages, see `status::stash_message`.
+    pub(crate) stash_message: Option<String>,
+    pub(crate) carry_config: Option<Vec<String>>,
+    pub(crate) carry_files: Option<Vec<String>>,
+    pub(crate) lock_retries: Option<u32>,
+    pub(crate) lock_retry_delay_ms: Option<u64>,
+    pub(crate) hooks: Option<HookCommands>,
+    pub(crate) profile: Option<BTreeMap<String, Profile>>,
+}
+
+/// A `[profile.<name>]` table: the destination and defaults used by `--profile <name>`.
+/// Command-line flags still win over every value here.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct Profile {
+    pub(crate) destination: Option<String>,
+    pub(crate) keep_stash: Option<bool>,
+    pub(crate) include_ignored: Option<bool>,
+    pub(crate) no_stash: Option<bool>,
+    pub(crate) exec: Option<Vec<String>>,
+    pub(crate) hooks: Option<HookCommands>,
+}
+
+/// The `[hooks]` table: shell commands run before and after the swap.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct HookCommands {
+    pub(crate) pre_swap: Option<String>,
+    pub(crate) post_swap: Option<String>,
+}
+
+impl Config {
+    pub(crate) fn load(common_dir: Option<&Path>) -> Result<Self, Box<dyn Error>> {
+        let mut config = Self::default();
+        if let Some(path) = user_config_path() {
+            config = config.merge(Self::read(&path)?);
+        }
+        if let Some(common_dir) = common_dir {
+            config = config.merge(Self::read(&repo_config_path(common_dir))?);
+        }
+        Ok(config)
+    }
+
+    fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+        let contents = fs::read_to_string(path)?;
+        toml::from_str(&contents)
+            .map_err(|err| format!("Invalid config file '{}': {err}", path.display()).into())
+    }
+
+    fn merge(self, other: Self) -> Self {
+        Self {
+            keep_stash: other.keep_stash.or(self.keep_stash),
+            confirm: other.confirm.or(self.confirm),
+            max_stash_size: other.max_stash_size.or(self.max_stash_size),
+            stash_message: other.stash_message.or(self.stash_message),
+            carry_config: other.carry_config.or(self.carry_config),
+            carry_files: other.carry_files.or(self.carry_files),
+            lock_retries: other.lock_retries.or(self.lock_retries),
+            lock_retry_delay_ms: other.lock_retry_delay_ms.or(self.lock_retry_delay_ms),
+            hooks: HookCommands::merge(self.hooks, other.hooks),
+            profile: match (self.profile, other.profile) {
+                (Some(mut base), Some(over)) => {
+                    for (name, profile) in over {
+                        let merged = match base.remove(&name) {
+                            Some(existing) => existing.merge(profile),
+                            None => profile,
+                        };
+                        base.insert(name, merged);
+                    }
+                    Some(base)
+                }
+                (base, over) => over.or(base),
+            },
+        }
+    }
+
+    /// Looks up `[profile.<name>]`, listing the defined profiles when it does not exist.
+    pub(crate) fn profile(&self, name: &str) -> Result<&Profile, Box<dyn Error>> {
+        let profiles = self.profile.as_ref();
+        profiles
+            .and_then(|profiles| profiles.get(name))
+            .ok_or_else(|| {
+                let defined: Vec<&str> = profiles
+                    .into_iter()
+                    .flat_map(|profiles| profiles.keys().map(String::as_str))
+                    .collect();
+                if defined.is_empty() {
+                    format!("Unknown profile '{name}': no profiles are configured.").into()
+                } else {
+                    format!(
+                        "Unknown profile '{name}'. Configured profiles: {}.",
+                        defined.join(", ")
+                    )
+                    .into()
+                }
+            })
+    }
+}
+
+impl Profile {
+    fn merge(self, other: Self) -> Self {
+        Self {
+            destination: other.destination.or(self.destination),
+            keep_stash: other.keep_stash.or(self.keep_stash),
+            include_ignored: other.include_ignored.or(self.include_ignored),
+            no_stash: other.no_stash.or(self.no_stash),
+            exec: other.exec.or(self.exec),
+            hooks: HookCommands::merge(self.hooks, other.hooks),
+        }
+    }
+
+    /// The destination directory with a leading `~/` expanded to the home directory.
+    pub(crate) fn destination(&self) -> Option<PathBuf> {
+        let destination = self.destination.as_deref()?;
+        match (destination.strip_prefix("~/"), env::var_os("HOME")) {
+            (Some(rest), Some(home)) => Some(PathBuf::from(home).join(rest)),
+            _ => Some(PathBuf::from(destination)),
+        }
+    }
+}
+
+impl HookCommands {
+    /// Merges two optional `[hooks]` tables key by key, `over` winning.
+    pub(crate) fn merge(base: Option<Self>, over: Option<Self>) -> Option<Self> {
+        match (base, over) {
+            (Some(base), Some(over)) => Some(Self {
+                pre_swap: over.pre_swap.or(base.pre_swap),
+                post_swap: over.post_swap.or(base.post_swap),
+            }),
+            (base, over) => over.or(base),
+        }
+    }
+}
+
+/// Parses a size such as `200MB`, `1.5 GB`, `500k`, or a plain number of bytes. Units are
+/// decimal and case-insensitive; `KiB`, `MiB`, and `GiB` are binary.
+pub(crate) fn parse_size(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{text}', expected e.g. 200MB"))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1_000,
+        "m" | "mb" => 1_000_000,
+        "g" | "gb" => 1_000_000_000,
+        "t" | "tb" => 1_000_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        other => return Err(format!("unknown size unit '{other}' in '{text}'")),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+fn deserialize_size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(u64),
+        Text(String),
+    }
+    match Size::deserialize(deserializer)? {
+        Size::Bytes(bytes) => Ok(Some(bytes)),
+        Size::Text(text) => parse_size(&text).map(Some).map_err(D::Error::custom),
+    }
+}
+
+fn user_config_path() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("swap-worktree").join("config.toml"))
+}
+
+fn repo_config_path(common_dir: &Path) -> PathBuf {
+    common_dir.join("swap-worktree").join("config.toml")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_size, Config};
+
+    #[test]
+    fn repository_values_override_user_values() {
+        let user: Config = toml::from_str("keep_stash = true").unwrap();
+        let repo: Config = toml::from_str("keep_stash = false").unwrap();
+        assert_eq!(user.merge(repo).keep_stash, Some(false));
+
+        let user: Config = toml::from_str("keep_stash = true").unwrap();
+        assert_eq!(user.merge(Config::default()).keep_stash, Some(true));
+    }
+
+    #[test]
+    fn parses_stash_size_limits() {
+        assert_eq!(parse_size("200MB"), Ok(200_000_000));
+        assert_eq!(parse_size("1.5 gb"), Ok(1_500_000_000));
+        assert_eq!(parse_size("2KiB"), Ok(2048));
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert!(parse_size("lots").is_err());
+        assert!(parse_size("5 parsecs").is_err());
+        let config: Config = toml::from_str("max_stash_size = \"200MB\"").unwrap();
+        assert_eq!(config.max_stash_size, Some(200_000_000));
+    }
+
+    #[test]
+    fn hooks_merge_per_key() {
+        let user: Config =
+            toml::from_str("[hooks]\npre-swap = \"make check\"\npost-swap = \"make dev\"").unwrap();
+        let repo: Config = toml::from_str("[hooks]\npost-swap = \"npm ci\"").unwrap();
+        let hooks = user.merge(repo).hooks.unwrap();
+        assert_eq!(hooks.pre_swap.as_deref(), Some("make check"));
+        assert_eq!(hooks.post_swap.as_deref(), Some("npm ci"));
+    }
+
+    #[test]
+    fn profiles_merge_per_name_and_key() {
+        let user: Config = toml::from_str(
+            "[profile.review]\ndestination = \"~/code/repo-review\"\nkeep_stash = true\n[profile.ci]\nno_stash = true",
+        )
+        .unwrap();
+        let repo: Config = toml::from_str("[profile.review]\nexec = [\"npm ci\"]").unwrap();
+        let config = user.merge(repo);
+        let review = config.profile("review").unwrap();
+        assert_eq!(review.destination.as_deref(), Some("~/code/repo-review"));
+        assert_eq!(review.keep_stash, Some(true));
+        assert_eq!(review.exec.as_deref(), Some(&["npm ci".to_string()][..]));
+        let err = config.profile("nope").unwrap_err().to_string();
+        assert!(err.contains("ci, review"), "{err}");
+    }
+}
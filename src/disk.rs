@@ -0,0 +1,56 @@
+use std::path::Path;
+
+/// Returns `true` when both paths live on the same block device and the kernel reports that
+/// device as rotational. Only Linux exposes this cheaply; other platforms always report `false`.
+pub(crate) fn shares_rotational_disk(a: &Path, b: &Path) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        let (Ok(meta_a), Ok(meta_b)) = (a.metadata(), b.metadata()) else {
+            return false;
+        };
+        if meta_a.dev() != meta_b.dev() {
+            return false;
+        }
+        is_rotational(meta_a.dev())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (a, b);
+        false
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn is_rotational(dev: u64) -> bool {
+    let (major, minor) = split_device_number(dev);
+    let device = format!("/sys/dev/block/{major}:{minor}");
+    // Partitions have no queue directory of their own; the flag lives on the parent disk.
+    for candidate in ["queue/rotational", "../queue/rotational"] {
+        if let Ok(flag) = std::fs::read_to_string(Path::new(&device).join(candidate)) {
+            return flag.trim() == "1";
+        }
+    }
+    false
+}
+
+/// Decodes a Linux `dev_t` into its major and minor numbers (see `gnu_dev_major`).
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn split_device_number(dev: u64) -> (u64, u64) {
+    let major = ((dev >> 8) & 0xfff) | ((dev >> 32) & !0xfff);
+    let minor = (dev & 0xff) | ((dev >> 12) & !0xff);
+    (major, minor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::split_device_number;
+
+    #[test]
+    fn splits_device_numbers() {
+        assert_eq!(split_device_number(0x0803), (8, 3));
+        assert_eq!(split_device_number(0x10300), (259, 0));
+        assert_eq!(split_device_number(0x0010_082c), (8, 300));
+    }
+}
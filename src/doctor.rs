@@ -0,0 +1,152 @@
+use std::error::Error;
+use std::path::Path;
+
+use crate::paths::normalize_path;
+use crate::status::{leftovers, swap_stashes};
+use crate::{
+    git_args, git_common_dir, list_worktrees, run_git, run_git_success, sequencer, WorktreeEntry,
+};
+
+/// Oldest git release whose worktree and stash behavior this tool relies on.
+const MIN_GIT_VERSION: (u32, u32) = (2, 37);
+
+/// Runs every pre-flight check against the repository containing `dir` and prints each
+/// problem with a suggested fix. Fails when any problem was found.
+pub(crate) fn run(dir: &Path) -> Result<(), Box<dyn Error>> {
+    let mut problems = Vec::new();
+
+    let version = run_git_success(None, git_args!["--version"], "Failed to run git.")?;
+    let version = version.stdout.trim();
+    match parse_git_version(version) {
+        Some(found) if found < MIN_GIT_VERSION => problems.push(format!(
+            "{version} is older than {}.{}; upgrade git",
+            MIN_GIT_VERSION.0, MIN_GIT_VERSION.1
+        )),
+        Some(_) => println!("{version}: ok"),
+        None => problems.push(format!(
+            "could not parse '{version}'; make sure git {}.{} or newer is on PATH",
+            MIN_GIT_VERSION.0, MIN_GIT_VERSION.1
+        )),
+    }
+
+    let worktrees = list_worktrees(dir)?;
+    println!("Checked {} worktree(s).", worktrees.len());
+    for entry in &worktrees {
+        problems.extend(check_worktree(entry)?);
+    }
+
+    let common_dir = git_common_dir(dir)?;
+    problems.extend(leftovers(&common_dir));
+    let refs = run_git_success(
+        Some(dir),
+        git_args!["for-each-ref", "--format=%(refname)", "refs/swap-worktree/"],
+        "Failed to list temporary refs.",
+    )?;
+    problems.extend(refs.stdout.lines().map(|name| {
+        format!("temporary ref '{name}' left by --across-clones; delete it with `git update-ref -d {name}`")
+    }));
+    let stashes = swap_stashes(dir)?.len();
+    if stashes > 0 {
+        problems.push(format!(
+            "{stashes} swap stash(es) in the stash list; review them with `swap-worktree status` and drop them with `swap-worktree clean`"
+        ));
+    }
+
+    if problems.is_empty() {
+        println!("No problems found.");
+        return Ok(());
+    }
+    println!("Problems:");
+    for problem in &problems {
+        println!("  - {problem}");
+    }
+    Err(format!("{} problem(s) found.", problems.len()).into())
+}
+
+/// Lists the problems of one worktree that make swaps involving it fail.
+pub(crate) fn check_worktree(entry: &WorktreeEntry) -> Result<Vec<String>, Box<dyn Error>> {
+    let path = entry.path.display();
+    let mut problems = Vec::new();
+    if let Some(reason) = entry.locked.as_deref() {
+        let reason = if reason.is_empty() {
+            "no reason given"
+        } else {
+            reason
+        };
+        problems.push(format!(
+            "'{path}' is locked ({reason}), so swaps refuse it; run `git worktree unlock {path}` once the lock is no longer needed, or pass --ignore-locks"
+        ));
+    }
+    if entry.is_stale() {
+        let reason = entry.prunable.as_deref().unwrap_or("directory is missing");
+        problems.push(format!(
+            "'{path}' is recorded as a worktree but is stale ({reason}); remount or restore it, or drop the record with `git worktree prune`"
+        ));
+        return Ok(problems);
+    }
+    if entry.head.is_none() {
+        return Ok(problems);
+    }
+    for operation in sequencer::operations_in_progress(&entry.path)? {
+        problems.push(format!(
+            "'{path}': {}; finish it with {}",
+            operation.description(),
+            operation.resolution()
+        ));
+    }
+    let lock = run_git_success(
+        Some(&entry.path),
+        git_args!["rev-parse", "--git-path", "index.lock"],
+        "Failed to locate the index lock.",
+    )?;
+    let lock = normalize_path(&entry.path, lock.stdout.trim());
+    if lock.exists() {
+        problems.push(format!(
+            "'{path}' has a leftover '{}'; delete it if no git process is running",
+            lock.display()
+        ));
+    }
+    if let Some(branch) = &entry.branch {
+        let exists = run_git(
+            Some(&entry.path),
+            git_args![
+                "rev-parse",
+                "--verify",
+                "--quiet",
+                format!("refs/heads/{branch}")
+            ],
+        )?;
+        if !exists.status.success() {
+            problems.push(format!(
+                "'{path}' is on branch '{branch}', which has no commits (deleted or never committed); commit on it or switch the worktree to an existing branch"
+            ));
+        }
+    }
+    Ok(problems)
+}
+
+/// Extracts `(major, minor)` from `git --version` output such as "git version 2.39.5".
+fn parse_git_version(output: &str) -> Option<(u32, u32)> {
+    let version = output.strip_prefix("git version ")?;
+    let mut parts = version.split(|c: char| !c.is_ascii_digit());
+    Some((parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_git_version;
+
+    #[test]
+    fn parses_git_versions() {
+        assert_eq!(parse_git_version("git version 2.39.5"), Some((2, 39)));
+        assert_eq!(
+            parse_git_version("git version 2.37.1 (Apple Git-137.1)"),
+            Some((2, 37))
+        );
+        assert_eq!(
+            parse_git_version("git version 2.45.1.windows.1"),
+            Some((2, 45))
+        );
+        assert_eq!(parse_git_version("not git"), None);
+    }
+}
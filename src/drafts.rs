@@ -0,0 +1,182 @@
+use std::error::Error;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use tracing::debug;
+
+use crate::{git_args, paths::normalize_path, run_git, run_git_success};
+
+/// Message files in a worktree's git dir that belong to the work on its branch rather than
+/// to the directory: the message of an interrupted `git commit`, and the one
+/// `git merge --squash` prepares for the next commit.
+const DRAFT_FILES: [&str; 2] = ["COMMIT_EDITMSG", "SQUASH_MSG"];
+
+/// Git leaves the message of every commit behind in this file, so it only counts as a draft
+/// when it says something else than the commit HEAD points to.
+const COMMIT_MESSAGE: &str = "COMMIT_EDITMSG";
+
+/// The commit message drafts of one worktree, read before the swap.
+pub(crate) struct Drafts {
+    git_dir: PathBuf,
+    files: Vec<(&'static str, Vec<u8>)>,
+}
+
+/// Reads the drafts in the git dir of `dir`. Nothing is changed until [`exchange`].
+/// A `COMMIT_EDITMSG` that only repeats the message of HEAD is left out.
+pub(crate) fn take(dir: &Path) -> Result<Drafts, Box<dyn Error>> {
+    let output = run_git_success(
+        Some(dir),
+        git_args!["rev-parse", "--absolute-git-dir"],
+        "Failed to find the git dir.",
+    )?;
+    let git_dir = normalize_path(dir, output.stdout.trim());
+    let mut files = Vec::new();
+    for name in DRAFT_FILES {
+        match fs::read(git_dir.join(name)) {
+            Ok(contents) if name == COMMIT_MESSAGE && is_last_commit_message(dir, &contents)? => {
+                debug!("{name} in '{}' is the message of HEAD.", dir.display());
+            }
+            Ok(contents) => files.push((name, contents)),
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(format!("Failed to read {name}: {err}").into()),
+        }
+    }
+    Ok(Drafts { git_dir, files })
+}
+
+/// Gives each worktree the drafts of the branch it now holds and returns one line per
+/// worktree that received any, or that failed to. Does nothing when neither side had drafts.
+pub(crate) fn exchange(
+    dest_dir: &Path,
+    dest: &Drafts,
+    src_dir: &Path,
+    src: &Drafts,
+) -> Vec<String> {
+    if dest.files.is_empty() && src.files.is_empty() {
+        return Vec::new();
+    }
+    [(dest_dir, dest, src), (src_dir, src, dest)]
+        .into_iter()
+        .filter_map(|(dir, own, incoming)| match replace(own, incoming) {
+            Ok(()) if incoming.files.is_empty() => None,
+            Ok(()) => Some(format!("'{}': {}", dir.display(), names(incoming))),
+            Err(err) => Some(format!("'{}': failed to move drafts: {err}", dir.display())),
+        })
+        .collect()
+}
+
+/// Replaces the drafts of `own` by `incoming`: writes the incoming ones and removes those
+/// that only `own` had. Files that are a draft on neither side are left alone.
+fn replace(own: &Drafts, incoming: &Drafts) -> Result<(), Box<dyn Error>> {
+    let find = |drafts: &Drafts, name: &str| drafts.files.iter().any(|(file, _)| *file == name);
+    for name in DRAFT_FILES {
+        let path = own.git_dir.join(name);
+        match incoming.files.iter().find(|(file, _)| *file == name) {
+            Some((_, contents)) => {
+                debug!("Writing {name} to '{}'...", own.git_dir.display());
+                fs::write(&path, contents)?;
+            }
+            None if find(own, name) => match fs::remove_file(&path) {
+                Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            },
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+/// Whether `contents` of `COMMIT_EDITMSG` is the message HEAD was committed with, once the
+/// comments git adds for the editor are gone.
+fn is_last_commit_message(dir: &Path, contents: &[u8]) -> Result<bool, Box<dyn Error>> {
+    let head = run_git(Some(dir), git_args!["log", "-1", "--format=%B", "HEAD"])?;
+    if !head.status.success() {
+        // No commits yet: whatever is in the file was never committed.
+        return Ok(false);
+    }
+    Ok(message_text(&String::from_utf8_lossy(contents)) == message_text(&head.stdout))
+}
+
+/// A commit message as git stores it: without comment lines, anything below the scissors
+/// line of `git commit -v`, trailing whitespace and surrounding blank lines.
+fn message_text(message: &str) -> String {
+    let lines: Vec<&str> = message
+        .lines()
+        .take_while(|line| {
+            !(line.starts_with('#') && line.ends_with(">8 ------------------------"))
+        })
+        .filter(|line| !line.starts_with('#'))
+        .map(str::trim_end)
+        .collect();
+    lines.join("\n").trim_matches('\n').to_string()
+}
+
+fn names(drafts: &Drafts) -> String {
+    let names: Vec<&str> = drafts.files.iter().map(|(name, _)| *name).collect();
+    names.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{exchange, message_text, replace, take, Drafts};
+    use std::path::Path;
+    use std::process::Command;
+
+    #[test]
+    fn replaces_drafts_with_the_incoming_ones() {
+        let git_dir =
+            std::env::temp_dir().join(format!("swap-worktree-drafts-{}", std::process::id()));
+        std::fs::create_dir_all(&git_dir).unwrap();
+        std::fs::write(git_dir.join("COMMIT_EDITMSG"), "old").unwrap();
+        std::fs::write(git_dir.join("SQUASH_MSG"), "squash").unwrap();
+        let own = Drafts {
+            git_dir: git_dir.clone(),
+            files: vec![("SQUASH_MSG", b"squash".to_vec())],
+        };
+        let incoming = Drafts {
+            git_dir: std::path::PathBuf::new(),
+            files: vec![("COMMIT_EDITMSG", b"Long draft\n\nWith a body\n".to_vec())],
+        };
+        let result = replace(&own, &incoming);
+        let message = std::fs::read_to_string(git_dir.join("COMMIT_EDITMSG"));
+        let squash = git_dir.join("SQUASH_MSG").exists();
+        std::fs::remove_dir_all(&git_dir).unwrap();
+        assert!(result.is_ok());
+        assert_eq!(message.unwrap(), "Long draft\n\nWith a body\n");
+        assert!(!squash);
+    }
+
+    #[test]
+    fn strips_what_git_adds_for_the_editor() {
+        let edited = "Fix login\n\nBody  \n# Please enter the commit message\n# ------------------------ >8 ------------------------\ndiff --git a/x b/x\n";
+        assert_eq!(message_text(edited), "Fix login\n\nBody");
+        assert_eq!(message_text("Fix login\n\nBody\n\n"), message_text(edited));
+    }
+
+    #[test]
+    fn reports_nothing_right_after_a_commit() {
+        let repo =
+            std::env::temp_dir().join(format!("swap-worktree-drafts-repo-{}", std::process::id()));
+        let git = |dir: &Path, args: &[&str]| {
+            let status = Command::new("git")
+                .current_dir(dir)
+                .args(["-c", "user.name=a", "-c", "user.email=a@a"])
+                .args(args)
+                .status()
+                .unwrap();
+            assert!(status.success(), "git {args:?}");
+        };
+        std::fs::create_dir_all(&repo).unwrap();
+        git(&repo, &["init", "-q", "-b", "main"]);
+        git(&repo, &["commit", "-q", "--allow-empty", "-m", "First"]);
+        let main = take(&repo);
+        std::fs::write(repo.join(".git").join("SQUASH_MSG"), "Squashed").unwrap();
+        let squashed = take(&repo);
+        std::fs::remove_dir_all(&repo).unwrap();
+        let (main, squashed) = (main.unwrap(), squashed.unwrap());
+        assert!(main.files.is_empty());
+        assert!(exchange(&repo, &main, &repo, &main).is_empty());
+        assert_eq!(squashed.files.len(), 1);
+    }
+}
@@ -0,0 +1,235 @@
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use tracing::{debug, warn};
+
+use crate::holding::Holding;
+use crate::{display_relative, git_args, run_git_success, SwapOptions, SwapPlan};
+
+/// What happens to git repositories nested in a worktree's untracked files, such as a
+/// dependency cloned into it. `git stash -u` skips them with "Ignoring path", so they would
+/// stay behind in the old worktree while the rest of the untracked files follow the branch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum EmbeddedRepoPolicy {
+    /// Refuse to swap while there are any (--force swaps anyway and leaves them in place)
+    #[default]
+    Abort,
+    /// Leave them in their worktree
+    Skip,
+    /// Move them out before stashing and into the worktree that takes their branch
+    Move,
+}
+
+/// The embedded repositories of one worktree and, with `--embedded-repos move`, the holding
+/// directory they wait in until their branch has its new home.
+pub(crate) struct EmbeddedRepos {
+    /// Top level of the worktree, which the paths are relative to.
+    origin: PathBuf,
+    paths: Vec<String>,
+    held: Option<Holding>,
+}
+
+/// Looks for embedded repositories in both worktrees of `plan`, before anything is changed.
+/// `dest_toplevel` is the top level of the destination, which may be given as one of its
+/// subdirectories. Fails under the `abort` policy when there are any, unless `--force` is
+/// given.
+pub(crate) fn find_both(
+    plan: &SwapPlan,
+    dest_toplevel: &Path,
+    options: &SwapOptions,
+) -> Result<(EmbeddedRepos, EmbeddedRepos), Box<dyn Error>> {
+    let untracked = !options.no_stash && !options.ignore_untracked;
+    let find_in = |dir: &Path| -> Result<EmbeddedRepos, Box<dyn Error>> {
+        Ok(EmbeddedRepos {
+            origin: dir.to_path_buf(),
+            paths: if untracked {
+                find(dir, options.include_ignored)?
+            } else {
+                Vec::new()
+            },
+            held: None,
+        })
+    };
+    let dest = find_in(dest_toplevel)?;
+    let src = find_in(&plan.src_dir)?;
+    if options.embedded_repos != EmbeddedRepoPolicy::Abort
+        || (dest.paths.is_empty() && src.paths.is_empty())
+    {
+        return Ok((dest, src));
+    }
+    let listing = [&dest, &src]
+        .iter()
+        .flat_map(|repos| {
+            repos
+                .paths
+                .iter()
+                .map(|path| format!("  {}", display_relative(&repos.origin.join(path))))
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    if !options.force {
+        return Err(format!(
+            "These untracked directories are git repositories, which stashing leaves behind; nothing was changed:\n{listing}\nPass --embedded-repos move to take them along with their branch, --embedded-repos skip to leave them where they are, or --force to swap anyway."
+        )
+        .into());
+    }
+    warn!("Leaving these embedded git repositories in their worktree:\n{listing}");
+    Ok((dest, src))
+}
+
+/// Moves the repositories of both worktrees under `base` with `--embedded-repos move`.
+/// When the second worktree fails, the first one gets its repositories back.
+pub(crate) fn take_both(
+    dest: &mut EmbeddedRepos,
+    src: &mut EmbeddedRepos,
+    policy: EmbeddedRepoPolicy,
+    base: &Path,
+) -> Result<(), Box<dyn Error>> {
+    if policy != EmbeddedRepoPolicy::Move {
+        return Ok(());
+    }
+    dest.take(&base.join("dest"))?;
+    if let Err(err) = src.take(&base.join("src")) {
+        let _ = dest.put_back();
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Delivers each worktree's moved repositories to the other one, and returns one line per
+/// worktree that had embedded repositories.
+pub(crate) fn exchange(dest: &EmbeddedRepos, src: &EmbeddedRepos) -> Vec<String> {
+    [(dest, &src.origin), (src, &dest.origin)]
+        .into_iter()
+        .filter_map(|(repos, target)| match repos.deliver(target) {
+            Ok(summary) => summary,
+            Err(err) => {
+                Some(format!(
+                "'{}': failed to move embedded repositories ({err}); the rest are still in '{}'",
+                target.display(),
+                repos.held.as_ref().map_or(repos.origin.as_path(), Holding::dir).display()
+            ))
+            }
+        })
+        .collect()
+}
+
+/// Untracked (and, with `include_ignored`, ignored) directories of the worktree with the top
+/// level `dir` that hold a `.git` of their own, relative to `dir` as `git status --porcelain`
+/// prints them. `git status --untracked-files=all` lists every untracked file on its own, but
+/// stops at a nested repository and lists it as a directory.
+fn find(dir: &Path, include_ignored: bool) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut args = git_args!["status", "--porcelain", "-z", "--untracked-files=all"];
+    if include_ignored {
+        args.push(OsString::from("--ignored=matching"));
+    }
+    let status = run_git_success(Some(dir), args, "Failed to read worktree status.")?;
+    let repos = nested_repositories(&status.stdout)
+        .into_iter()
+        .filter(|path| dir.join(path).join(".git").exists())
+        .map(|path| path.trim_end_matches('/').to_string())
+        .collect();
+    debug!("Embedded repositories in '{}': {repos:?}", dir.display());
+    Ok(repos)
+}
+
+/// The untracked and ignored directory entries of `git status --porcelain -z`.
+fn nested_repositories(status: &str) -> Vec<&str> {
+    status
+        .split('\0')
+        .filter_map(|entry| {
+            entry
+                .strip_prefix("?? ")
+                .or_else(|| entry.strip_prefix("!! "))
+        })
+        .filter(|path| path.ends_with('/'))
+        .collect()
+}
+
+impl EmbeddedRepos {
+    /// Moves the repositories into `holding`.
+    fn take(&mut self, holding: &Path) -> Result<(), Box<dyn Error>> {
+        if self.paths.is_empty() {
+            return Ok(());
+        }
+        self.held = Some(Holding::take(
+            &self.origin,
+            self.paths.clone(),
+            holding,
+            move_dir,
+        )?);
+        Ok(())
+    }
+
+    /// Moves the repositories back to the worktree they came from, after a failed swap.
+    pub(crate) fn put_back(&self) -> Result<(), Box<dyn Error>> {
+        match &self.held {
+            Some(held) => held.put_back(),
+            None => Ok(()),
+        }
+    }
+
+    /// Moves the repositories into `target`, which now holds their branch, and describes
+    /// the result. A repository whose path is taken in `target` is moved next to it with an
+    /// `.incoming` suffix. Repositories that were left in place are only listed.
+    fn deliver(&self, target: &Path) -> Result<Option<String>, Box<dyn Error>> {
+        if self.paths.is_empty() {
+            return Ok(None);
+        }
+        let Some(held) = &self.held else {
+            return Ok(Some(format!(
+                "'{}': left in place: {}",
+                self.origin.display(),
+                self.paths.join(", ")
+            )));
+        };
+        let collisions = held.deliver(target)?;
+        let mut summary = format!(
+            "'{}': moved from '{}': {}",
+            target.display(),
+            self.origin.display(),
+            self.paths.join(", ")
+        );
+        if !collisions.is_empty() {
+            summary.push_str(&format!(
+                "; already present, incoming repositories saved as: {}",
+                collisions.join(", ")
+            ));
+        }
+        Ok(Some(summary))
+    }
+}
+
+/// Renames a directory. Repositories are not copied across file systems: a failed rename
+/// leaves `from` untouched.
+fn move_dir(from: &Path, to: &Path) -> Result<(), Box<dyn Error>> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(from, to).map_err(|err| {
+        format!(
+            "Failed to move '{}' to '{}': {err}\nPass --embedded-repos skip to leave embedded repositories in place.",
+            from.display(),
+            to.display()
+        )
+        .into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::nested_repositories;
+
+    #[test]
+    fn lists_directory_entries_of_the_status() {
+        let status =
+            "?? dep/\0 M src/main.rs\0?? vendor/lib/\0?? vendor/z\0!! build/\0R  new.rs\0old.rs\0";
+        assert_eq!(
+            nested_repositories(status),
+            ["dep/", "vendor/lib/", "build/"]
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::process::Stdio;
+use std::thread;
+
+use tracing::{debug, info};
+
+use crate::hooks::shell_command;
+use crate::{display_relative, logging};
+
+/// Runs every `--exec` command in both worktrees and returns one line per command and
+/// worktree. The worktrees run side by side unless `serial` is set; failures are reported
+/// but never undo the swap.
+pub(crate) fn run_in_worktrees(commands: &[String], dirs: &[&Path], serial: bool) -> Vec<String> {
+    let mut report = Vec::new();
+    for command in commands {
+        debug!("Running `{command}` in both worktrees...");
+        let results: Vec<String> = if serial {
+            dirs.iter().map(|dir| run_prefixed(command, dir)).collect()
+        } else {
+            thread::scope(|scope| {
+                let handles: Vec<_> = dirs
+                    .iter()
+                    .map(|dir| scope.spawn(|| run_prefixed(command, dir)))
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().unwrap_or_default())
+                    .collect()
+            })
+        };
+        report.extend(results);
+    }
+    report
+}
+
+/// Runs `command` in `dir`, echoing every output line with the worktree as prefix.
+fn run_prefixed(command: &str, dir: &Path) -> String {
+    let prefix = format!("[{}]", display_relative(dir));
+    let child = shell_command(command)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(err) => return format!("'{}': `{command}` could not start: {err}", dir.display()),
+    };
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+    let status = thread::scope(|scope| {
+        scope.spawn(|| echo_lines(stdout, dir, &prefix, false));
+        scope.spawn(|| echo_lines(stderr, dir, &prefix, true));
+        child.wait()
+    });
+    match status {
+        Ok(status) if status.success() => format!("'{}': `{command}` succeeded", dir.display()),
+        Ok(status) => format!("'{}': `{command}` failed ({status})", dir.display()),
+        Err(err) => format!("'{}': `{command}` failed: {err}", dir.display()),
+    }
+}
+
+/// Passes each line on as an `output` event, which the console shows unless `--quiet` or
+/// `--porcelain` is given.
+fn echo_lines(pipe: Option<impl Read>, dir: &Path, prefix: &str, to_stderr: bool) {
+    let Some(pipe) = pipe else {
+        return;
+    };
+    let stream = if to_stderr { "stderr" } else { "stdout" };
+    for line in BufReader::new(pipe).lines().map_while(Result::ok) {
+        info!(
+            target: logging::OUTPUT_TARGET,
+            event = "output",
+            worktree = dir.to_str(),
+            stream,
+            line,
+            "{prefix} {line}"
+        );
+    }
+}
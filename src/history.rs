@@ -0,0 +1,177 @@
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write as _};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use tracing::warn;
+
+use crate::status::short_hash;
+use crate::{display_relative, git_common_dir, StashRecord, SwapPlan};
+
+/// One swap as recorded in `<git-common-dir>/swap-worktree/history`, one JSON object per line.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct HistoryEntry {
+    /// Seconds since the Unix epoch.
+    pub(crate) timestamp: u64,
+    pub(crate) dest_dir: PathBuf,
+    pub(crate) dest_branch: String,
+    pub(crate) src_dir: PathBuf,
+    pub(crate) src_branch: String,
+    pub(crate) dest_stash: Option<String>,
+    pub(crate) src_stash: Option<String>,
+    /// "swapped", or "failed: " followed by the first line of the error.
+    pub(crate) outcome: String,
+}
+
+impl HistoryEntry {
+    pub(crate) fn new(plan: &SwapPlan, stashes: &[StashRecord], outcome: String) -> Self {
+        let stash_from = |dir: &Path| {
+            stashes
+                .iter()
+                .find(|stash| stash.origin == dir)
+                .map(|stash| stash.hash.clone())
+        };
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or_default(),
+            dest_dir: plan.dest_dir.clone(),
+            dest_branch: plan.dest_branch.clone(),
+            src_dir: plan.src_dir.clone(),
+            src_branch: plan.src_branch.clone(),
+            dest_stash: stash_from(&plan.dest_dir),
+            src_stash: stash_from(&plan.src_dir),
+            outcome,
+        }
+    }
+
+    /// One listing line: time, both worktrees with their branch change, outcome and stashes.
+    fn describe(&self) -> String {
+        let mut line = format!(
+            "{}  {}: {} -> {}  {}: {} -> {}  {}",
+            format_timestamp(self.timestamp),
+            display_relative(&self.dest_dir),
+            self.dest_branch,
+            self.src_branch,
+            display_relative(&self.src_dir),
+            self.src_branch,
+            self.dest_branch,
+            self.outcome
+        );
+        let stashes: Vec<&str> = [&self.dest_stash, &self.src_stash]
+            .into_iter()
+            .flatten()
+            .map(|hash| short_hash(hash))
+            .collect();
+        if !stashes.is_empty() {
+            line.push_str(&format!("  stashes: {}", stashes.join(", ")));
+        }
+        line
+    }
+}
+
+/// Appends `entry` to the history of the repository with `common_dir`. A history that
+/// cannot be written only warns; the swap itself already happened.
+pub(crate) fn append(common_dir: &Path, entry: &HistoryEntry) {
+    let path = history_path(common_dir);
+    let result = serde_json::to_string(entry)
+        .map_err(Box::<dyn Error>::from)
+        .and_then(|line| {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+            writeln!(file, "{line}")?;
+            Ok(())
+        });
+    if let Err(err) = result {
+        warn!("Failed to record the swap in '{}': {err}", path.display());
+    }
+}
+
+/// Reads the history of the repository with `common_dir`, oldest first. Lines that cannot be
+/// parsed are skipped.
+pub(crate) fn read(common_dir: &Path) -> Result<Vec<HistoryEntry>, Box<dyn Error>> {
+    let path = history_path(common_dir);
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(format!("Failed to read '{}': {err}", path.display()).into()),
+    };
+    Ok(contents
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// The most recent successful swap of the repository containing `dir`, for `--last`.
+pub(crate) fn last_swap(dir: &Path) -> Result<HistoryEntry, Box<dyn Error>> {
+    read(&git_common_dir(dir)?)?
+        .into_iter()
+        .rev()
+        .find(|entry| entry.outcome == "swapped")
+        .ok_or_else(|| {
+            "No swap recorded in this repository yet; --last has nothing to swap back.".into()
+        })
+}
+
+/// Prints the recorded swaps of the repository containing `dir`, oldest first, limited to
+/// the most recent `last` when given.
+pub(crate) fn show(dir: &Path, last: Option<usize>) -> Result<(), Box<dyn Error>> {
+    let entries = read(&git_common_dir(dir)?)?;
+    if entries.is_empty() {
+        println!("No swaps recorded yet.");
+        return Ok(());
+    }
+    let skip = last.map_or(0, |last| entries.len().saturating_sub(last));
+    for entry in &entries[skip..] {
+        println!("{}", entry.describe());
+    }
+    Ok(())
+}
+
+fn history_path(common_dir: &Path) -> PathBuf {
+    common_dir.join("swap-worktree").join("history")
+}
+
+/// Formats seconds since the Unix epoch as "YYYY-MM-DD HH:MM UTC".
+pub(crate) fn format_timestamp(timestamp: u64) -> String {
+    let days = timestamp / 86_400;
+    let minutes = timestamp % 86_400 / 60;
+    // Civil-from-days conversion for the proleptic Gregorian calendar.
+    let shifted = days + 719_468;
+    let era = shifted / 146_097;
+    let day_of_era = shifted % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02} UTC",
+        minutes / 60,
+        minutes % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_timestamp;
+
+    #[test]
+    fn formats_timestamps_as_utc_dates() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00 UTC");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29 00:00 UTC");
+        assert_eq!(format_timestamp(1_791_812_700), "2026-10-12 13:45 UTC");
+    }
+}
@@ -0,0 +1,112 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Moves one path, creating the parent directories of `to`.
+pub(crate) type MovePath = fn(&Path, &Path) -> Result<(), Box<dyn Error>>;
+
+/// Untracked paths moved out of a worktree before the swap. They wait in a holding directory
+/// under the git common dir until their branch has its new home, or go back where they came
+/// from when the swap fails.
+pub(crate) struct Holding {
+    origin: PathBuf,
+    dir: PathBuf,
+    paths: Vec<String>,
+    move_path: MovePath,
+}
+
+impl Holding {
+    /// Moves `paths`, relative to `origin`, into `dir`. Refuses to reuse a holding directory
+    /// left behind by an earlier run. When a path cannot be moved, the ones already moved go
+    /// back; whatever cannot go back stays in `dir`.
+    pub(crate) fn take(
+        origin: &Path,
+        paths: Vec<String>,
+        dir: &Path,
+        move_path: MovePath,
+    ) -> Result<Self, Box<dyn Error>> {
+        if dir.exists() {
+            return Err(format!(
+                "'{}' already exists, probably from an interrupted swap. Move what it holds back into place and delete it before retrying.",
+                dir.display()
+            )
+            .into());
+        }
+        let held = Self {
+            origin: origin.to_path_buf(),
+            dir: dir.to_path_buf(),
+            paths,
+            move_path,
+        };
+        for (index, path) in held.paths.iter().enumerate() {
+            if let Err(err) = move_path(&origin.join(path), &dir.join(path)) {
+                let restored = held.paths[..index]
+                    .iter()
+                    .all(|moved| move_path(&dir.join(moved), &origin.join(moved)).is_ok());
+                if restored {
+                    held.clean_up();
+                }
+                return Err(err);
+            }
+        }
+        Ok(held)
+    }
+
+    /// The worktree the paths were taken from.
+    pub(crate) fn origin(&self) -> &Path {
+        &self.origin
+    }
+
+    pub(crate) fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub(crate) fn paths(&self) -> &[String] {
+        &self.paths
+    }
+
+    /// Moves the paths back to the worktree they came from, after a failed swap.
+    pub(crate) fn put_back(&self) -> Result<(), Box<dyn Error>> {
+        for path in &self.paths {
+            (self.move_path)(&self.dir.join(path), &self.origin.join(path))?;
+        }
+        self.clean_up();
+        Ok(())
+    }
+
+    /// Moves the paths into `target`, which now holds their branch, and returns the names of
+    /// those that were already present there. A file with the same content as the one in
+    /// `target` is dropped; anything else that is already present is kept, and the incoming
+    /// copy is moved next to it with an `.incoming` suffix.
+    pub(crate) fn deliver(&self, target: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut collisions = Vec::new();
+        for path in &self.paths {
+            let incoming = self.dir.join(path);
+            let mut destination = target.join(path);
+            if destination.exists() {
+                if destination.is_file()
+                    && incoming.is_file()
+                    && fs::read(&destination)? == fs::read(&incoming)?
+                {
+                    fs::remove_file(&incoming)?;
+                    continue;
+                }
+                let mut renamed = destination.into_os_string();
+                renamed.push(".incoming");
+                destination = PathBuf::from(renamed);
+                collisions.push(format!("{path}.incoming"));
+            }
+            (self.move_path)(&incoming, &destination)?;
+        }
+        self.clean_up();
+        Ok(collisions)
+    }
+
+    fn clean_up(&self) {
+        let _ = fs::remove_dir_all(&self.dir);
+        // Only succeeds once the other worktree's holding directory is gone as well.
+        if let Some(base) = self.dir.parent() {
+            let _ = fs::remove_dir(base);
+        }
+    }
+}
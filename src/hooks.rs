@@ -0,0 +1,104 @@
+use std::error::Error;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use tracing::{debug, warn};
+
+use crate::config::{Config, HookCommands, Profile};
+use crate::{logging, SwapPlan};
+
+/// When a hook runs relative to the swap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Stage {
+    PreSwap,
+    PostSwap,
+}
+
+impl Stage {
+    fn name(self) -> &'static str {
+        match self {
+            Self::PreSwap => "pre-swap",
+            Self::PostSwap => "post-swap",
+        }
+    }
+}
+
+/// Hook commands from the configuration plus hook scripts stored under
+/// `<git-common-dir>/swap-worktree/hooks/`.
+#[derive(Debug)]
+pub(crate) struct Hooks {
+    commands: HookCommands,
+    dir: PathBuf,
+}
+
+impl Hooks {
+    /// Hook commands from `config`, overridden key by key by the active profile's.
+    pub(crate) fn new(config: &Config, profile: Option<&Profile>, common_dir: &Path) -> Self {
+        let profile_hooks = profile.and_then(|profile| profile.hooks.clone());
+        Self {
+            commands: HookCommands::merge(config.hooks.clone(), profile_hooks).unwrap_or_default(),
+            dir: common_dir.join("swap-worktree").join("hooks"),
+        }
+    }
+
+    /// Runs the configured command and then the hook script for `stage`, if present. A
+    /// failing pre-swap hook is an error; a failing post-swap hook only warns, because the
+    /// swap has already happened.
+    pub(crate) fn run(&self, stage: Stage, plan: &SwapPlan) -> Result<(), Box<dyn Error>> {
+        let configured = match stage {
+            Stage::PreSwap => self.commands.pre_swap.as_deref(),
+            Stage::PostSwap => self.commands.post_swap.as_deref(),
+        };
+        let script = self.dir.join(stage.name());
+        let mut hooks = Vec::new();
+        if let Some(command) = configured {
+            hooks.push((command.to_string(), shell_command(command)));
+        }
+        if script.is_file() {
+            hooks.push((script.display().to_string(), Command::new(&script)));
+        }
+        for (label, mut cmd) in hooks {
+            debug!("Running {} hook: {label}", stage.name());
+            if logging::ndjson() || logging::porcelain() {
+                // Keep stdout to the progress events or porcelain lines.
+                cmd.stdout(io::stderr());
+            }
+            let status = cmd
+                .current_dir(&plan.dest_dir)
+                .env("SWAP_WORKTREE_HOOK", stage.name())
+                .env("SWAP_WORKTREE_DEST_DIR", &plan.dest_dir)
+                .env("SWAP_WORKTREE_DEST_BRANCH", &plan.dest_branch)
+                .env("SWAP_WORKTREE_SRC_DIR", &plan.src_dir)
+                .env("SWAP_WORKTREE_SRC_BRANCH", &plan.src_branch)
+                .status()
+                .map_err(|err| format!("Failed to run {} hook '{label}': {err}", stage.name()))?;
+            if status.success() {
+                continue;
+            }
+            if stage == Stage::PreSwap {
+                return Err(format!(
+                    "pre-swap hook '{label}' failed ({status}). Nothing was changed."
+                )
+                .into());
+            }
+            warn!("{} hook '{label}' failed ({status}).", stage.name());
+        }
+        Ok(())
+    }
+}
+
+/// Builds a command that runs `command` through the platform shell.
+pub(crate) fn shell_command(command: &str) -> Command {
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C");
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c");
+        cmd
+    };
+    cmd.arg(command);
+    cmd
+}
@@ -0,0 +1,171 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::ffi::OsString;
+use std::path::Path;
+
+use tracing::debug;
+
+use crate::{git_args, run_git_success};
+
+/// Paths of one worktree whose index entries carry the skip-worktree or assume-unchanged bit.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct IndexFlags {
+    skip_worktree: Vec<String>,
+    assume_unchanged: Vec<String>,
+}
+
+impl IndexFlags {
+    fn is_empty(&self) -> bool {
+        self.skip_worktree.is_empty() && self.assume_unchanged.is_empty()
+    }
+}
+
+/// Takes the flags of both worktrees. When the second worktree fails, the first one gets
+/// its bits back.
+pub(crate) fn take_both(
+    dest_dir: &Path,
+    src_dir: &Path,
+) -> Result<(IndexFlags, IndexFlags), Box<dyn Error>> {
+    let dest = take(dest_dir)?;
+    match take(src_dir) {
+        Ok(src) => Ok((dest, src)),
+        Err(err) => {
+            let _ = restore(dest_dir, &dest);
+            Err(err)
+        }
+    }
+}
+
+/// Records the flagged paths of `dir` and clears their bits, so that their local changes are
+/// stashed and travel with the branch like any other change.
+fn take(dir: &Path) -> Result<IndexFlags, Box<dyn Error>> {
+    let output = run_git_success(
+        Some(dir),
+        git_args!["ls-files", "-v", "-z"],
+        "Failed to read index flags.",
+    )?;
+    // Skip-worktree entries without a file on disk are excluded by sparse-checkout, which
+    // manages those bits itself.
+    let flags = parse_flagged(&output.stdout, |path| dir.join(path).exists());
+    if flags.is_empty() {
+        return Ok(flags);
+    }
+    debug!(
+        "Clearing skip-worktree/assume-unchanged bits on {} path(s) in '{}'...",
+        flags.skip_worktree.len() + flags.assume_unchanged.len(),
+        dir.display()
+    );
+    update_index(dir, "--no-skip-worktree", &flags.skip_worktree)?;
+    if let Err(err) = update_index(dir, "--no-assume-unchanged", &flags.assume_unchanged) {
+        let _ = update_index(dir, "--skip-worktree", &flags.skip_worktree);
+        return Err(err);
+    }
+    Ok(flags)
+}
+
+/// Sets the recorded bits on the worktree that now holds each branch and returns one line per
+/// worktree describing what was transferred. Does nothing when neither side had flags.
+pub(crate) fn carry(
+    dest_dir: &Path,
+    dest_flags: &IndexFlags,
+    src_dir: &Path,
+    src_flags: &IndexFlags,
+) -> Vec<String> {
+    if dest_flags.is_empty() && src_flags.is_empty() {
+        return Vec::new();
+    }
+    [(dest_dir, src_flags), (src_dir, dest_flags)]
+        .into_iter()
+        .map(|(dir, flags)| match restore(dir, flags) {
+            Ok(summary) => format!("'{}': {summary}", dir.display()),
+            Err(err) => format!("'{}': failed to restore index flags: {err}", dir.display()),
+        })
+        .collect()
+}
+
+/// Sets the recorded bits again on `dir` and describes the result. Paths that are not
+/// tracked on the branch now checked out in `dir` are listed instead of being flagged.
+pub(crate) fn restore(dir: &Path, flags: &IndexFlags) -> Result<String, Box<dyn Error>> {
+    if flags.is_empty() {
+        return Ok("no flagged paths".to_string());
+    }
+    let output = run_git_success(
+        Some(dir),
+        git_args!["ls-files", "-z"],
+        "Failed to list tracked files.",
+    )?;
+    let tracked: HashSet<&str> = output.stdout.split('\0').collect();
+    let (skip_worktree, mut untracked): (Vec<_>, Vec<_>) = flags
+        .skip_worktree
+        .iter()
+        .partition(|path| tracked.contains(path.as_str()));
+    let (assume_unchanged, missing): (Vec<_>, Vec<_>) = flags
+        .assume_unchanged
+        .iter()
+        .partition(|path| tracked.contains(path.as_str()));
+    untracked.extend(missing);
+    debug!(
+        "Setting skip-worktree/assume-unchanged bits in '{}'...",
+        dir.display()
+    );
+    update_index(dir, "--skip-worktree", &skip_worktree)?;
+    update_index(dir, "--assume-unchanged", &assume_unchanged)?;
+    let mut summary = format!(
+        "{} skip-worktree and {} assume-unchanged path(s) restored",
+        skip_worktree.len(),
+        assume_unchanged.len()
+    );
+    if !untracked.is_empty() {
+        let names: Vec<&str> = untracked.iter().map(|path| path.as_str()).collect();
+        summary.push_str(&format!(
+            "; not tracked on this branch: {}",
+            names.join(", ")
+        ));
+    }
+    Ok(summary)
+}
+
+fn update_index(dir: &Path, flag: &str, paths: &[impl AsRef<str>]) -> Result<(), Box<dyn Error>> {
+    if paths.is_empty() {
+        return Ok(());
+    }
+    let mut args = git_args!["update-index", flag, "--"];
+    args.extend(paths.iter().map(|path| OsString::from(path.as_ref())));
+    run_git_success(Some(dir), args, "Failed to update index flags.")?;
+    Ok(())
+}
+
+/// Parses `git ls-files -v -z` output. `S` marks skip-worktree and a lowercase tag marks
+/// assume-unchanged; `s` carries both bits.
+fn parse_flagged(listing: &str, exists: impl Fn(&str) -> bool) -> IndexFlags {
+    let mut flags = IndexFlags::default();
+    for entry in listing.split('\0') {
+        let Some((tag, path)) = entry.split_once(' ') else {
+            continue;
+        };
+        if tag.eq_ignore_ascii_case("s") && exists(path) {
+            flags.skip_worktree.push(path.to_string());
+        }
+        if tag.chars().all(|c| c.is_ascii_lowercase()) {
+            flags.assume_unchanged.push(path.to_string());
+        }
+    }
+    flags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_flagged, IndexFlags};
+
+    #[test]
+    fn parses_skip_worktree_and_assume_unchanged_tags() {
+        let listing = "H src/main.rs\0S config/local.toml\0h .env\0s both.txt\0S sparse/out.rs\0";
+        assert_eq!(
+            parse_flagged(listing, |path| !path.starts_with("sparse/")),
+            IndexFlags {
+                skip_worktree: vec!["config/local.toml".to_string(), "both.txt".to_string()],
+                assume_unchanged: vec![".env".to_string(), "both.txt".to_string()],
+            }
+        );
+    }
+}
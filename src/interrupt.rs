@@ -0,0 +1,43 @@
+use std::error::Error;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::lock;
+
+/// Set by the Ctrl-C handler; checked between swap steps.
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+/// Set once the handler is in place; `serve` runs many swaps in one process.
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// Installs a Ctrl-C handler that only records the request, so the running git command can
+/// finish and the swap can unwind cleanly. A second Ctrl-C exits immediately.
+pub(crate) fn install() -> Result<(), Box<dyn Error>> {
+    if INSTALLED.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+    ctrlc::set_handler(|| {
+        if REQUESTED.swap(true, Ordering::SeqCst) {
+            eprintln!("Interrupted again; exiting without restoring the worktrees.");
+            // `exit` runs no destructors; the journal stays for `recover`, the lock goes.
+            lock::release_all();
+            process::exit(130);
+        }
+        eprintln!("Interrupt received; finishing the current git command, then restoring both worktrees...");
+    })
+    .map_err(|err| format!("Failed to install the Ctrl-C handler: {err}").into())
+}
+
+/// Returns `true` once Ctrl-C has been pressed.
+pub(crate) fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Asks the running swap to unwind as if Ctrl-C had been pressed.
+pub(crate) fn request() {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Forgets an earlier request before the next swap starts.
+pub(crate) fn reset() {
+    REQUESTED.store(false, Ordering::SeqCst);
+}
@@ -0,0 +1,260 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Instant;
+
+use tracing::{debug, trace};
+
+use crate::backend::Backend;
+use crate::{paths, SwapPlan};
+
+/// Lists the bookmarks on a commit, space-separated, one commit per line.
+const BOOKMARKS_TEMPLATE: &str = r#"local_bookmarks.map(|b| b.name()).join(" ") ++ "\n""#;
+
+/// The working-copy commit, whether another workspace should `edit` it or start a `new`
+/// commit on its parents instead, and those parents.
+const WORKING_COPY_TEMPLATE: &str = r#"commit_id ++ " " ++ if(empty, if(description, "edit", "new"), "edit") ++ " " ++ parents.map(|c| c.commit_id()).join(" ") ++ "\n""#;
+
+/// A Jujutsu repository, where bookmarks play the part of branches and workspaces that of
+/// worktrees. Uncommitted changes need no stash: jj keeps them in each workspace's
+/// working-copy commit, so a swap has the workspaces trade those commits.
+pub(crate) struct Jj {
+    /// Root of the workspace the swap was started from.
+    root: PathBuf,
+}
+
+/// How a workspace takes over the other one's working copy.
+#[derive(Debug, PartialEq, Eq)]
+enum Target {
+    /// `jj edit` the working-copy commit, which carries uncommitted changes.
+    Edit(String),
+    /// `jj new` on the parents of an empty, undescribed working-copy commit, which jj
+    /// abandons as soon as its workspace moves away.
+    New(Vec<String>),
+}
+
+impl Jj {
+    /// The jj workspace containing `dir`: the nearest ancestor with a `.jj` directory.
+    pub(crate) fn detect(dir: &Path) -> Option<Self> {
+        dir.ancestors()
+            .find(|dir| dir.join(".jj").is_dir())
+            .map(|root| Self {
+                root: root.to_path_buf(),
+            })
+    }
+
+    /// Bookmarks on the nearest bookmarked ancestors of `revision`, the working copy
+    /// included, as seen from `dir`.
+    fn bookmarks(&self, dir: &Path, revision: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        let revset = format!("heads(::{revision} & bookmarks())");
+        let output = run_jj(
+            dir,
+            &[
+                "log",
+                "--no-graph",
+                "--ignore-working-copy",
+                "-r",
+                &revset,
+                "-T",
+                BOOKMARKS_TEMPLATE,
+            ],
+        )?;
+        Ok(output.split_whitespace().map(str::to_string).collect())
+    }
+}
+
+impl Backend for Jj {
+    fn name(&self) -> &'static str {
+        "jj"
+    }
+
+    /// `.jj/repo` of the default workspace. In other workspaces `.jj/repo` is a file with
+    /// the path of that directory, relative to their `.jj`.
+    fn state_dir(&self) -> Result<PathBuf, Box<dyn Error>> {
+        let dot_jj = self.root.join(".jj");
+        let repo = dot_jj.join("repo");
+        if repo.is_dir() {
+            return Ok(repo);
+        }
+        let target = fs::read_to_string(&repo)
+            .map_err(|err| format!("Failed to read '{}': {err}", repo.display()))?;
+        Ok(paths::canonicalize(&dot_jj.join(target.trim()))?)
+    }
+
+    fn current_branch(&self, dir: &Path) -> Result<String, Box<dyn Error>> {
+        match self.bookmarks(dir, "@")?.as_slice() {
+            [bookmark] => Ok(bookmark.clone()),
+            [] => Err(format!(
+                "'{}' has no bookmark on its working-copy commit or its ancestors.",
+                dir.display()
+            )
+            .into()),
+            bookmarks => Err(format!(
+                "'{}' sits on several bookmarks ({}); swap from a workspace with one.",
+                dir.display(),
+                bookmarks.join(", ")
+            )
+            .into()),
+        }
+    }
+
+    fn worktree_for_branch(&self, branch: &str) -> Result<PathBuf, Box<dyn Error>> {
+        let listing = run_jj(&self.root, &["workspace", "list", "--ignore-working-copy"])?;
+        for name in workspace_names(&listing) {
+            let revision = format!("{}@", quote(name));
+            let bookmarks = self.bookmarks(&self.root, &revision)?;
+            if !bookmarks.iter().any(|bookmark| bookmark == branch) {
+                continue;
+            }
+            let args = ["workspace", "root", "--ignore-working-copy", "--name", name];
+            let root = run_jj(&self.root, &args).map_err(|err| {
+                format!("Could not find the root of jj workspace '{name}'; `jj workspace root --name` needs a recent jj.\n{err}")
+            })?;
+            return Ok(paths::canonicalize(Path::new(root.trim()))?);
+        }
+        Err(format!("No jj workspace has bookmark '{branch}' checked out.").into())
+    }
+
+    fn swap(&self, plan: &SwapPlan) -> Result<(), Box<dyn Error>> {
+        // Reading the working copy snapshots it; snapshotting the source can rewrite the
+        // destination's commit when it descends from the source's, so that one is read again.
+        working_copy(&plan.dest_dir, true)?;
+        let (src_commit, src_target) = working_copy(&plan.src_dir, true)?;
+        let (dest_commit, dest_target) = working_copy(&plan.dest_dir, false)?;
+        debug!(
+            "Working copies: '{}' at {dest_commit}, '{}' at {src_commit}.",
+            plan.dest_dir.display(),
+            plan.src_dir.display()
+        );
+        move_to(&plan.dest_dir, &src_target)?;
+        if let Err(err) = move_to(&plan.src_dir, &dest_target) {
+            move_to(&plan.dest_dir, &dest_target)?;
+            return Err(format!(
+                "{err}\n'{}' was put back on its own working copy; `jj op log` shows what happened.",
+                plan.dest_dir.display()
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// Reads the working-copy commit of `dir` and how another workspace takes it over. With
+/// `snapshot`, jj records the files on disk first.
+fn working_copy(dir: &Path, snapshot: bool) -> Result<(String, Target), Box<dyn Error>> {
+    let mut args = vec!["log", "--no-graph", "-r", "@", "-T", WORKING_COPY_TEMPLATE];
+    if !snapshot {
+        args.push("--ignore-working-copy");
+    }
+    let output = run_jj(dir, &args)?;
+    parse_working_copy(&output).ok_or_else(|| {
+        format!(
+            "Unexpected output from jj for '{}': {}",
+            dir.display(),
+            output.trim()
+        )
+        .into()
+    })
+}
+
+fn move_to(dir: &Path, target: &Target) -> Result<(), Box<dyn Error>> {
+    let mut args = Vec::new();
+    match target {
+        Target::Edit(commit) => args.extend(["edit", commit.as_str()]),
+        Target::New(parents) => {
+            args.push("new");
+            args.extend(parents.iter().map(String::as_str));
+        }
+    }
+    run_jj(dir, &args)?;
+    Ok(())
+}
+
+fn parse_working_copy(output: &str) -> Option<(String, Target)> {
+    let mut fields = output.split_whitespace();
+    let commit = fields.next()?.to_string();
+    let target = match fields.next()? {
+        "edit" => Target::Edit(commit.clone()),
+        "new" => Target::New(fields.map(str::to_string).collect()),
+        _ => return None,
+    };
+    Some((commit, target))
+}
+
+/// Workspace names from `jj workspace list`, whose lines read `<name>: <change> <commit> ...`.
+fn workspace_names(listing: &str) -> Vec<&str> {
+    listing
+        .lines()
+        .filter_map(|line| line.split_once(": ").map(|(name, _)| name))
+        .collect()
+}
+
+/// `name` as a revset string literal.
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Runs jj (`$SWAP_WORKTREE_JJ`, or `jj` from PATH) in `dir`, failing with its stderr when it
+/// exits with an error.
+fn run_jj(dir: &Path, args: &[&str]) -> Result<String, Box<dyn Error>> {
+    let program = env::var_os("SWAP_WORKTREE_JJ")
+        .filter(|value| !value.is_empty())
+        .unwrap_or_else(|| "jj".into());
+    let command = args.join(" ");
+    let started = Instant::now();
+    let output = Command::new(&program)
+        .current_dir(dir)
+        .args(["--no-pager", "--color=never"])
+        .args(args)
+        .output()
+        .map_err(|err| format!("Failed to run '{}': {err}", program.to_string_lossy()))?;
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    trace!(
+        event = "jj",
+        worktree = dir.to_str(),
+        command,
+        status = output.status.code(),
+        stdout = stdout.trim_end(),
+        stderr = stderr.trim_end(),
+        "jj {command} in '{}': {} after {:?}",
+        dir.display(),
+        output.status,
+        started.elapsed()
+    );
+    if !output.status.success() {
+        return Err(format!(
+            "jj {command} failed in '{}':\n{}",
+            dir.display(),
+            stderr.trim()
+        )
+        .into());
+    }
+    Ok(stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_working_copy, quote, workspace_names, Target};
+
+    #[test]
+    fn reads_workspaces_and_working_copies() {
+        let listing = "default: qpvuntsm 230dd059 (empty) (no description set)\nreview: zsuskuln 8a2f1c3e fix: typo\n";
+        assert_eq!(workspace_names(listing), ["default", "review"]);
+        assert_eq!(quote(r#"my "ws""#), r#""my \"ws\"""#);
+        assert_eq!(
+            parse_working_copy("8a2f1c3e edit 230dd059\n"),
+            Some(("8a2f1c3e".to_string(), Target::Edit("8a2f1c3e".to_string())))
+        );
+        assert_eq!(
+            parse_working_copy("5e6f7a8b new 230dd059 41c2d3e4\n"),
+            Some((
+                "5e6f7a8b".to_string(),
+                Target::New(vec!["230dd059".to_string(), "41c2d3e4".to_string()])
+            ))
+        );
+        assert_eq!(parse_working_copy("5e6f7a8b squash\n"), None);
+    }
+}
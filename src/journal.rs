@@ -0,0 +1,380 @@
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write as _};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use tracing::{debug, warn};
+
+use crate::info_log;
+use crate::{
+    apply_and_drop_stash, detach_worktree, find_stash_reference, git_args, git_common_dir, lock,
+    run_git, status, switch_worktree, worktree_move, Logger, StashRecord, SwapOptions, SwapPlan,
+};
+
+/// The journal of the swap running in this process, if any. Steps are recorded from deep
+/// inside the swap, so it lives next to the other per-process settings instead of being
+/// threaded through every call.
+static ACTIVE: Mutex<Option<Active>> = Mutex::new(None);
+
+struct Active {
+    path: PathBuf,
+    touched: bool,
+}
+
+/// Guard for the journal of one swap. Dropping it before the swap touched anything removes
+/// the journal again; once a worktree was modified, the journal stays until the swap or
+/// its unwinding completes, so `recover` can pick up after a crash.
+pub(crate) struct Journal;
+
+/// What a journal says about an interrupted swap.
+struct Recorded {
+    plan: SwapPlan,
+    dest_stash: Option<String>,
+    src_stash: Option<String>,
+    applied: Vec<String>,
+    step: String,
+    /// The swap ran with `--detach-source`, so finishing leaves the source detached.
+    detach_source: bool,
+    /// Where `--mode move` held the destination directory while the source moved in.
+    parking: Option<PathBuf>,
+}
+
+impl Journal {
+    /// Starts `<git-common-dir>/swap-worktree/journal` for `plan`.
+    pub(crate) fn begin(common_dir: &Path, plan: &SwapPlan) -> Result<Self, Box<dyn Error>> {
+        let path = journal_path(common_dir);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let contents = format!(
+            "dest_dir={}\ndest_branch={}\nsrc_dir={}\nsrc_branch={}\nstep=started\n",
+            plan.dest_dir.display(),
+            plan.dest_branch,
+            plan.src_dir.display(),
+            plan.src_branch
+        );
+        write_synced(&path, &contents, false)?;
+        activate(path, false);
+        Ok(Self)
+    }
+}
+
+impl Drop for Journal {
+    fn drop(&mut self) {
+        let Ok(mut active) = ACTIVE.lock() else {
+            return;
+        };
+        if let Some(journal) = active.take() {
+            if !journal.touched {
+                let _ = fs::remove_file(&journal.path);
+            }
+        }
+    }
+}
+
+/// Fails while the journal of an earlier, interrupted swap is still there: its worktrees
+/// have to be sorted out before they can be swapped again.
+pub(crate) fn ensure_no_interrupted_swap(common_dir: &Path) -> Result<(), Box<dyn Error>> {
+    let path = journal_path(common_dir);
+    if path.exists() {
+        return Err(format!(
+            "An earlier swap was interrupted and left its journal at '{}'.\nRun `swap-worktree recover` to finish or reverse it first.",
+            path.display()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Where the journal of the repository with `common_dir` lives.
+pub(crate) fn journal_path(common_dir: &Path) -> PathBuf {
+    common_dir.join("swap-worktree").join("journal")
+}
+
+/// Appends `key=value` to the active journal and flushes it to disk. Does nothing when no
+/// journal is active.
+pub(crate) fn record(key: &str, value: &str) {
+    let Ok(mut active) = ACTIVE.lock() else {
+        return;
+    };
+    if let Some(journal) = active.as_mut() {
+        journal.touched = true;
+        if let Err(err) = write_synced(&journal.path, &format!("{key}={value}\n"), true) {
+            warn!("Failed to update the swap journal: {err}");
+        }
+    }
+}
+
+/// Removes the active journal once both worktrees are in a consistent state again.
+pub(crate) fn finish() {
+    let Ok(mut active) = ACTIVE.lock() else {
+        return;
+    };
+    if let Some(journal) = active.take() {
+        let _ = fs::remove_file(&journal.path);
+    }
+}
+
+/// Reads the journal of the repository containing `dir` and completes the interrupted swap:
+/// finished when the destination had already switched, reversed otherwise.
+pub(crate) fn recover(
+    dir: &Path,
+    options: &SwapOptions,
+    logger: &Logger,
+) -> Result<(), Box<dyn Error>> {
+    let common_dir = git_common_dir(dir)?;
+    let _repo_lock = lock::RepoLock::acquire(&common_dir)?;
+    let path = journal_path(&common_dir);
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            info_log!(logger, "No interrupted swap to recover.");
+            return Ok(());
+        }
+        Err(err) => return Err(format!("Failed to read '{}': {err}", path.display()).into()),
+    };
+    let recorded = parse(&contents).map_err(|err| {
+        format!(
+            "The journal '{}' is damaged ({err}).\nCheck both worktrees by hand, then delete it.",
+            path.display()
+        )
+    })?;
+    let plan = &recorded.plan;
+    if let Some(parking) = &recorded.parking {
+        activate(path, true);
+        let outcome = worktree_move::recover(&common_dir, plan, parking)?;
+        finish();
+        info_log!(
+            logger,
+            "Recovered the directory swap of '{}' and '{}': {outcome}.",
+            plan.dest_dir.display(),
+            plan.src_dir.display()
+        );
+        return Ok(());
+    }
+    let stash = |hash: &Option<String>, branch: &String, origin: &PathBuf| {
+        hash.as_ref().map(|hash| StashRecord {
+            hash: hash.clone(),
+            branch: branch.clone(),
+            origin: origin.clone(),
+        })
+    };
+    let dest_stash = stash(&recorded.dest_stash, &plan.dest_branch, &plan.dest_dir);
+    let src_stash = stash(&recorded.src_stash, &plan.src_branch, &plan.src_dir);
+
+    let finishing = matches!(recorded.step.as_str(), "switched-dest" | "switched");
+    // Each worktree's target branch, and the stash that belongs on top of it.
+    let (targets, incoming) = if finishing {
+        (
+            [
+                (&plan.dest_dir, &plan.src_branch),
+                (&plan.src_dir, &plan.dest_branch),
+            ],
+            [&src_stash, &dest_stash],
+        )
+    } else {
+        (
+            [
+                (&plan.dest_dir, &plan.dest_branch),
+                (&plan.src_dir, &plan.src_branch),
+            ],
+            [&dest_stash, &src_stash],
+        )
+    };
+    // A detached source keeps its commit, and the destination's stash stays in the list.
+    let sides = if finishing && recorded.detach_source {
+        1
+    } else {
+        2
+    };
+    let (targets, incoming) = (&targets[..sides], &incoming[..sides]);
+    info_log!(
+        logger,
+        "Found an interrupted swap of '{}' ({}) and '{}' ({}); {} it.",
+        plan.dest_dir.display(),
+        plan.dest_branch,
+        plan.src_dir.display(),
+        plan.src_branch,
+        if finishing { "finishing" } else { "reversing" }
+    );
+    activate(path, true);
+
+    // The destination takes the source branch before the source moves off it, so a
+    // source that is to stay detached may still hold it.
+    if sides == 1 && branch_of(&plan.src_dir)?.as_ref() == Some(&plan.src_branch) {
+        detach_worktree(&plan.src_dir, &plan.src_branch)?;
+    }
+    // Free every branch that sits in the wrong worktree before switching any of them.
+    let mut current = Vec::new();
+    for (dir, _) in targets {
+        current.push(branch_of(dir)?);
+    }
+    for ((dir, target), current) in targets.iter().zip(&current) {
+        if let Some(branch) = current.as_ref().filter(|branch| branch != target) {
+            detach_worktree(dir, branch)?;
+        }
+    }
+    for ((dir, target), current) in targets.iter().zip(&current) {
+        if current.as_ref() != Some(target) {
+            switch_worktree(dir, target, &[])?;
+        }
+    }
+    for ((dir, branch), stash) in targets.iter().zip(incoming) {
+        let Some(stash) = stash else {
+            continue;
+        };
+        if recorded.applied.contains(&stash.hash) {
+            debug!("Stash {} was already applied.", stash.hash);
+            continue;
+        }
+        if find_stash_reference(&stash.origin, &stash.hash)?.is_none() {
+            warn!(
+                "Stash {} from '{}' is no longer in the stash list; its changes cannot be restored.",
+                stash.hash, stash.branch
+            );
+            continue;
+        }
+        apply_and_drop_stash(dir, branch, Some(stash), options);
+    }
+    finish();
+
+    match targets {
+        [(dest_dir, dest_branch), (src_dir, src_branch)] => info_log!(
+            logger,
+            "Recovered: '{}' is on '{dest_branch}' and '{}' is on '{src_branch}'.",
+            dest_dir.display(),
+            src_dir.display()
+        ),
+        _ => info_log!(
+            logger,
+            "Recovered: '{}' is on '{}' and '{}' stays detached.",
+            plan.dest_dir.display(),
+            plan.src_branch,
+            plan.src_dir.display()
+        ),
+    }
+    let leftover = status::swap_stashes(dir)?.len();
+    if leftover > 0 {
+        info_log!(
+            logger,
+            "{leftover} swap stash(es) are still in the stash list; see `swap-worktree status`."
+        );
+    }
+    Ok(())
+}
+
+fn activate(path: PathBuf, touched: bool) {
+    if let Ok(mut active) = ACTIVE.lock() {
+        *active = Some(Active { path, touched });
+    }
+}
+
+fn write_synced(path: &Path, contents: &str, append: bool) -> Result<(), Box<dyn Error>> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)?;
+    file.write_all(contents.as_bytes())?;
+    // The journal is only useful if it survives a power loss.
+    file.sync_data()?;
+    Ok(())
+}
+
+/// The branch checked out in `dir`, or `None` when its HEAD is detached.
+fn branch_of(dir: &Path) -> Result<Option<String>, Box<dyn Error>> {
+    let output = run_git(
+        Some(dir),
+        git_args!["symbolic-ref", "--quiet", "--short", "HEAD"],
+    )?;
+    Ok(output
+        .status
+        .success()
+        .then(|| output.stdout.trim().to_string()))
+}
+
+fn parse(contents: &str) -> Result<Recorded, String> {
+    let mut fields = Vec::new();
+    let mut applied = Vec::new();
+    for line in contents.lines().filter(|line| !line.is_empty()) {
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("unexpected line '{line}'"))?;
+        match (key, value) {
+            // Rolling back discards whatever was applied on the swapped branches.
+            ("step", "restoring") => applied.clear(),
+            ("applied", hash) => applied.push(hash.to_string()),
+            _ => {}
+        }
+        fields.push((key, value));
+    }
+    let last = |name: &str| {
+        fields
+            .iter()
+            .rev()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.to_string())
+    };
+    let required = |name: &str| last(name).ok_or_else(|| format!("'{name}' is missing"));
+    Ok(Recorded {
+        plan: SwapPlan {
+            dest_dir: PathBuf::from(required("dest_dir")?),
+            dest_branch: required("dest_branch")?,
+            src_dir: PathBuf::from(required("src_dir")?),
+            src_branch: required("src_branch")?,
+        },
+        dest_stash: last("dest_stash"),
+        src_stash: last("src_stash"),
+        applied,
+        step: required("step")?,
+        detach_source: last("detach_source").is_some(),
+        parking: last("parking").map(PathBuf::from),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    #[test]
+    fn reads_the_latest_step_and_applied_stashes() {
+        let recorded = parse(
+            "dest_dir=/work/main\ndest_branch=main\nsrc_dir=/work/feature\nsrc_branch=feature/a\nstep=started\nstep=stashing\ndest_stash=1c1cdd9c68b3bd55a72efa87c67fd03c4b5aa20c\nstep=switched-dest\napplied=e1e1b70d2e8c133c96ab8050cc582f88aa83ef77\n",
+        )
+        .unwrap();
+        assert_eq!(recorded.plan.src_branch, "feature/a");
+        assert_eq!(recorded.step, "switched-dest");
+        assert_eq!(
+            recorded.dest_stash.as_deref(),
+            Some("1c1cdd9c68b3bd55a72efa87c67fd03c4b5aa20c")
+        );
+        assert_eq!(recorded.src_stash, None);
+        assert_eq!(
+            recorded.applied,
+            ["e1e1b70d2e8c133c96ab8050cc582f88aa83ef77"]
+        );
+        let rolled_back = parse(
+            "dest_dir=/a\ndest_branch=main\nsrc_dir=/b\nsrc_branch=dev\nstep=switched\napplied=e1e1b70\nstep=restoring\n",
+        )
+        .unwrap();
+        assert!(rolled_back.applied.is_empty());
+        assert!(!rolled_back.detach_source);
+        assert_eq!(rolled_back.parking, None);
+        assert!(
+            parse("dest_dir=/a\ndest_branch=main\nsrc_dir=/b\nsrc_branch=dev\nstep=stashing\ndetach_source=true\n")
+                .unwrap()
+                .detach_source
+        );
+        let moved = parse(
+            "dest_dir=/a\ndest_branch=main\nsrc_dir=/b\nsrc_branch=dev\nstep=started\nparking=/.a.swap-worktree-42\nstep=moved-dest\n",
+        )
+        .unwrap();
+        assert_eq!(
+            moved.parking.as_deref(),
+            Some(std::path::Path::new("/.a.swap-worktree-42"))
+        );
+        assert!(parse("dest_dir=/work/main\nstep=started\n").is_err());
+    }
+}
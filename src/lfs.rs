@@ -0,0 +1,136 @@
+use std::error::Error;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Instant;
+
+use clap::ValueEnum;
+
+use tracing::debug;
+
+use crate::{git_args, git_command_in, record_timing, run_git, Logger, Verbosity};
+
+/// What to do with Git LFS content when a branch is switched.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum LfsPolicy {
+    /// Let git run the LFS smudge filter during the switch (git's default)
+    #[default]
+    Auto,
+    /// Skip the smudge filter and leave pointer files in place
+    Skip,
+    /// Skip the smudge filter, then fill files from the local LFS cache with `git lfs checkout`
+    Checkout,
+    /// Skip the smudge filter, then download and fill files with `git lfs pull`
+    Pull,
+}
+
+impl LfsPolicy {
+    fn command(self) -> Option<&'static str> {
+        match self {
+            Self::Auto | Self::Skip => None,
+            Self::Checkout => Some("checkout"),
+            Self::Pull => Some("pull"),
+        }
+    }
+}
+
+/// Returns `true` when any of `revisions` declares `filter=lfs` in a `.gitattributes` file.
+pub(crate) fn uses_lfs(dir: &Path, revisions: &[&str]) -> Result<bool, Box<dyn Error>> {
+    for revision in revisions {
+        let output = run_git(
+            Some(dir),
+            git_args![
+                "grep",
+                "--quiet",
+                "--fixed-strings",
+                "filter=lfs",
+                revision,
+                "--",
+                ":(glob)**/.gitattributes"
+            ],
+        )?;
+        if output.status.success() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Checks that the policy can be carried out and returns whether LFS is active, i.e. whether
+/// [`finish`] has anything to do after the swap.
+pub(crate) fn prepare(
+    dir: &Path,
+    branches: &[&str],
+    policy: LfsPolicy,
+) -> Result<bool, Box<dyn Error>> {
+    if policy == LfsPolicy::Auto || !uses_lfs(dir, branches)? {
+        return Ok(false);
+    }
+    if policy.command().is_some()
+        && !run_git(Some(dir), git_args!["lfs", "version"])?
+            .status
+            .success()
+    {
+        return Err(format!(
+            "--lfs {} needs the git-lfs extension, which is not installed.",
+            policy_name(policy)
+        )
+        .into());
+    }
+    debug!("Git LFS detected; skipping the smudge filter while switching.");
+    Ok(true)
+}
+
+/// The variable that keeps the smudge filter from downloading LFS content, for the switches
+/// to the new branches when the policy asks for it. Restoring a worktree smudges as usual.
+pub(crate) fn switch_env(policy: LfsPolicy) -> Option<(&'static str, &'static str)> {
+    (policy != LfsPolicy::Auto).then_some(("GIT_LFS_SKIP_SMUDGE", "1"))
+}
+
+/// Runs the policy's follow-up command in each worktree and returns one line per worktree.
+pub(crate) fn finish(dirs: &[&Path], policy: LfsPolicy, logger: &Logger) -> Vec<String> {
+    let Some(subcommand) = policy.command() else {
+        return dirs
+            .iter()
+            .map(|dir| {
+                format!(
+                    "'{}': LFS files left as pointers (--lfs skip)",
+                    dir.display()
+                )
+            })
+            .collect();
+    };
+    dirs.iter()
+        .map(|dir| match run_lfs(dir, subcommand, logger) {
+            Ok(()) => format!("'{}': git lfs {subcommand} done", dir.display()),
+            Err(err) => format!(
+                "'{}': git lfs {subcommand} failed ({err}); run it manually to fill LFS files",
+                dir.display()
+            ),
+        })
+        .collect()
+}
+
+/// Runs `git lfs <subcommand>`, letting its progress output through in debug mode.
+fn run_lfs(dir: &Path, subcommand: &str, logger: &Logger) -> Result<(), Box<dyn Error>> {
+    debug!("Running git lfs {subcommand} in '{}'...", dir.display());
+    let args = git_args!["lfs", subcommand];
+    let mut cmd = git_command_in(dir);
+    cmd.args(&args).stdin(Stdio::null());
+    if !logger.shows(Verbosity::Debug) {
+        cmd.stdout(Stdio::null()).stderr(Stdio::null());
+    }
+    let started = Instant::now();
+    let status = cmd.status()?;
+    record_timing(Some(dir), &args, started.elapsed());
+    if !status.success() {
+        return Err(format!("exit status {status}").into());
+    }
+    Ok(())
+}
+
+fn policy_name(policy: LfsPolicy) -> String {
+    policy
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::status::short_hash;
+use crate::{display_relative, git_args, list_worktrees, run_git, run_git_success};
+
+/// One worktree as shown by `list`.
+#[derive(Serialize)]
+pub(crate) struct Row {
+    path: String,
+    branch: Option<String>,
+    head: Option<String>,
+    upstream: Option<String>,
+    ahead: Option<u32>,
+    behind: Option<u32>,
+    /// `None` when the worktree is missing or bare, so its status cannot be read.
+    dirty: Option<bool>,
+    locked: bool,
+    prunable: bool,
+}
+
+/// Upstream tracking of one local branch.
+#[derive(Debug, PartialEq)]
+struct Tracking {
+    upstream: String,
+    /// `None` when the upstream branch no longer exists.
+    counts: Option<(u32, u32)>,
+}
+
+/// Prints the worktrees of the repository containing `dir` as a table, or as a JSON array
+/// with `json`.
+pub(crate) fn show(dir: &Path, json: bool) -> Result<(), Box<dyn Error>> {
+    let rows = rows(dir, json)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&rows)?);
+        return Ok(());
+    }
+    let table: Vec<[String; 5]> = rows.iter().map(table_cells).collect();
+    print!(
+        "{}",
+        format_table(["PATH", "BRANCH", "HEAD", "UPSTREAM", "STATUS"], &table)
+    );
+    Ok(())
+}
+
+/// Reads every worktree of the repository containing `dir` with its tracking and dirty
+/// state. Paths are absolute with `absolute`, relative to the current directory otherwise.
+pub(crate) fn rows(dir: &Path, absolute: bool) -> Result<Vec<Row>, Box<dyn Error>> {
+    let tracking = branch_tracking(dir)?;
+    let mut rows = Vec::new();
+    for entry in list_worktrees(dir)? {
+        let dirty = if entry.is_stale() || entry.head.is_none() {
+            None
+        } else {
+            is_dirty(&entry.path)?
+        };
+        let branch_tracking = entry
+            .branch
+            .as_ref()
+            .and_then(|branch| tracking.get(branch));
+        rows.push(Row {
+            path: if absolute {
+                entry.path.display().to_string()
+            } else {
+                display_relative(&entry.path)
+            },
+            upstream: branch_tracking.map(|tracking| tracking.upstream.clone()),
+            ahead: branch_tracking.and_then(|tracking| Some(tracking.counts?.0)),
+            behind: branch_tracking.and_then(|tracking| Some(tracking.counts?.1)),
+            branch: entry.branch,
+            head: entry.head,
+            dirty,
+            locked: entry.locked.is_some(),
+            prunable: entry.prunable.is_some(),
+        });
+    }
+    Ok(rows)
+}
+
+fn table_cells(row: &Row) -> [String; 5] {
+    let upstream = match (&row.upstream, row.ahead.zip(row.behind)) {
+        (None, _) => "-".to_string(),
+        (Some(upstream), None) => format!("{upstream} (gone)"),
+        (Some(upstream), Some((0, 0))) => format!("{upstream} ="),
+        (Some(upstream), Some((ahead, behind))) => format!("{upstream} +{ahead} -{behind}"),
+    };
+    let mut status = match row.dirty {
+        Some(true) => "dirty".to_string(),
+        Some(false) => "clean".to_string(),
+        None if row.prunable => "prunable".to_string(),
+        None if row.head.is_none() => "bare".to_string(),
+        None => "missing".to_string(),
+    };
+    if row.locked {
+        status.push_str(", locked");
+    }
+    [
+        row.path.clone(),
+        row.branch
+            .clone()
+            .unwrap_or_else(|| "(detached)".to_string()),
+        row.head
+            .as_deref()
+            .map(short_hash)
+            .unwrap_or("-")
+            .to_string(),
+        upstream,
+        status,
+    ]
+}
+
+/// Lays out `rows` under `header` in left-aligned columns separated by two spaces.
+fn format_table<const N: usize>(header: [&str; N], rows: &[[String; N]]) -> String {
+    let mut table = vec![header.map(str::to_string)];
+    table.extend(rows.iter().cloned());
+    align_columns(&table)
+}
+
+/// Pads every cell but the last of each row to the widest cell of its column, separating
+/// columns by two spaces, one line per row.
+pub(crate) fn align_columns<const N: usize>(rows: &[[String; N]]) -> String {
+    let mut widths = [0; N];
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut table = String::new();
+    for cells in rows {
+        let line: Vec<String> = cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect();
+        table.push_str(line.join("  ").trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+/// Reads the upstream and ahead/behind counts of every local branch in one call.
+fn branch_tracking(dir: &Path) -> Result<HashMap<String, Tracking>, Box<dyn Error>> {
+    let output = run_git_success(
+        Some(dir),
+        git_args![
+            "for-each-ref",
+            "--format=%(refname:short)%00%(upstream:short)%00%(upstream:track,nobracket)",
+            "refs/heads/"
+        ],
+        "Failed to read upstream branches.",
+    )?;
+    Ok(output.stdout.lines().filter_map(parse_tracking).collect())
+}
+
+fn parse_tracking(line: &str) -> Option<(String, Tracking)> {
+    let mut fields = line.split('\0');
+    let (branch, upstream, track) = (fields.next()?, fields.next()?, fields.next()?);
+    if upstream.is_empty() {
+        return None;
+    }
+    let counts = if track == "gone" {
+        None
+    } else {
+        let count = |name: &str| {
+            track
+                .split(", ")
+                .find_map(|part| part.strip_prefix(name)?.trim().parse().ok())
+                .unwrap_or(0)
+        };
+        Some((count("ahead"), count("behind")))
+    };
+    Some((
+        branch.to_string(),
+        Tracking {
+            upstream: upstream.to_string(),
+            counts,
+        },
+    ))
+}
+
+fn is_dirty(dir: &Path) -> Result<Option<bool>, Box<dyn Error>> {
+    let output = run_git(Some(dir), git_args!["status", "--porcelain"])?;
+    Ok(output
+        .status
+        .success()
+        .then(|| !output.stdout.trim().is_empty()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{align_columns, format_table, parse_tracking, Tracking};
+
+    #[test]
+    fn parses_ahead_behind_and_gone_upstreams() {
+        assert_eq!(
+            parse_tracking("feature/a\x00origin/feature/a\x00ahead 2, behind 1"),
+            Some((
+                "feature/a".to_string(),
+                Tracking {
+                    upstream: "origin/feature/a".to_string(),
+                    counts: Some((2, 1)),
+                }
+            ))
+        );
+        assert_eq!(
+            parse_tracking("main\x00origin/main\x00").unwrap().1.counts,
+            Some((0, 0))
+        );
+        assert_eq!(
+            parse_tracking("old\x00origin/old\x00gone")
+                .unwrap()
+                .1
+                .counts,
+            None
+        );
+        assert_eq!(parse_tracking("local\x00\x00"), None);
+    }
+
+    #[test]
+    fn pads_columns_to_the_widest_cell() {
+        let rows = [["../feature".to_string(), "feature/a".to_string()]];
+        assert_eq!(
+            format_table(["PATH", "BRANCH"], &rows),
+            "PATH        BRANCH\n../feature  feature/a\n"
+        );
+        let rows = [
+            [".".to_string(), "main".to_string(), "clean".to_string()],
+            ["../w".to_string(), "b".to_string(), "dirty".to_string()],
+        ];
+        assert_eq!(
+            align_columns(&rows),
+            ".     main  clean\n../w  b     dirty\n"
+        );
+    }
+}
@@ -0,0 +1,226 @@
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Write as _};
+use std::path::{Path, PathBuf};
+use std::process;
+#[cfg(windows)]
+use std::process::Command;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tracing::warn;
+
+/// Lock files held by this process, so a second Ctrl-C can remove them before exiting
+/// without running any destructors.
+static HELD: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Exclusive lock on one repository, held for the whole swap so that two invocations cannot
+/// interleave their stashes and detaches. The lock file is removed when the guard is dropped,
+/// which covers early returns on errors as well.
+pub(crate) struct RepoLock {
+    path: PathBuf,
+}
+
+impl RepoLock {
+    /// Creates `<git-common-dir>/swap-worktree/swap.lock`, failing if another swap holds it.
+    pub(crate) fn acquire(common_dir: &Path) -> Result<Self, Box<dyn Error>> {
+        let path = lock_path(common_dir);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+        let contents = format!("pid={}\nstarted={started}\n", process::id());
+        match create(&path) {
+            Ok(mut file) => {
+                if let Err(err) = file.write_all(contents.as_bytes()) {
+                    let _ = fs::remove_file(&path);
+                    return Err(format!("Failed to write lock '{}': {err}", path.display()).into());
+                }
+            }
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => take_over(&path, &contents)?,
+            Err(err) => {
+                return Err(format!("Failed to create lock '{}': {err}", path.display()).into())
+            }
+        }
+        let lock = Self { path };
+        if let Ok(mut held) = HELD.lock() {
+            held.push(lock.path.clone());
+        }
+        Ok(lock)
+    }
+}
+
+impl Drop for RepoLock {
+    fn drop(&mut self) {
+        if let Ok(mut held) = HELD.lock() {
+            held.retain(|path| path != &self.path);
+        }
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Removes the lock files of every lock this process holds, right before it exits.
+pub(crate) fn release_all() {
+    if let Ok(held) = HELD.lock() {
+        for path in held.iter() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Replaces the lock at `path` with one holding `contents` when the process that holds it has
+/// exited. The new lock is written to a file of its own and renamed over the stale one only
+/// while the lock still names that process, so a swap that took it over in the meantime
+/// keeps it. Reading the lock back after the rename catches a swap that renamed its own
+/// over it at the same moment.
+fn take_over(path: &Path, contents: &str) -> Result<(), Box<dyn Error>> {
+    let stale = fs::read_to_string(path).unwrap_or_default();
+    if !holder_is_gone(&stale) {
+        return Err(held_error(path).into());
+    }
+    warn!(
+        "Taking over the stale lock '{}' ({}).",
+        path.display(),
+        describe_holder(path)
+    );
+    let mut replacement = path.as_os_str().to_os_string();
+    replacement.push(format!(".{}", process::id()));
+    let replacement = PathBuf::from(replacement);
+    fs::write(&replacement, contents)
+        .map_err(|err| format!("Failed to create lock '{}': {err}", replacement.display()))?;
+    let unchanged = fs::read_to_string(path).is_ok_and(|current| current == stale);
+    if !unchanged || fs::rename(&replacement, path).is_err() {
+        let _ = fs::remove_file(&replacement);
+        return Err(held_error(path).into());
+    }
+    if fs::read_to_string(path).ok().as_deref() != Some(contents) {
+        return Err(held_error(path).into());
+    }
+    Ok(())
+}
+
+fn create(path: &Path) -> io::Result<File> {
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+/// Where the lock of the repository with `common_dir` lives.
+pub(crate) fn lock_path(common_dir: &Path) -> PathBuf {
+    common_dir.join("swap-worktree").join("swap.lock")
+}
+
+/// Describes the process recorded in an existing lock file, e.g. "PID 42, started 5s ago".
+pub(crate) fn describe_holder(path: &Path) -> String {
+    let contents = fs::read_to_string(path).unwrap_or_default();
+    let field = |name: &str| {
+        contents
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix('='))
+            .unwrap_or("unknown")
+            .to_string()
+    };
+    let age = field("started")
+        .parse::<u64>()
+        .ok()
+        .and_then(|started| {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+            Some(format!("{}s ago", now.saturating_sub(started)))
+        })
+        .unwrap_or_else(|| "at an unknown time".to_string());
+    let gone = if holder_is_gone(&contents) {
+        ", no longer running"
+    } else {
+        ""
+    };
+    format!("PID {}, started {age}{gone}", field("pid"))
+}
+
+/// Whether the process recorded in the lock file `contents` has exited, which makes the lock
+/// stale. A lock without a readable PID is never considered stale.
+fn holder_is_gone(contents: &str) -> bool {
+    contents
+        .lines()
+        .find_map(|line| line.strip_prefix("pid="))
+        .and_then(|pid| pid.trim().parse::<u32>().ok())
+        .is_some_and(|pid| !process_alive(pid))
+}
+
+/// Whether the process `pid` is running. Assumes it is when that cannot be told.
+#[cfg(unix)]
+fn process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return true;
+    };
+    if pid <= 0 {
+        return true;
+    }
+    // Signal 0 only checks that the process exists. EPERM means it does, under another user.
+    // SAFETY: `kill` with signal 0 sends nothing and touches no memory.
+    let result = unsafe { libc::kill(pid, 0) };
+    result == 0 || io::Error::last_os_error().raw_os_error() != Some(libc::ESRCH)
+}
+
+/// Whether the process `pid` is running. Assumes it is when that cannot be told.
+#[cfg(windows)]
+fn process_alive(pid: u32) -> bool {
+    let filter = format!("PID eq {pid}");
+    match Command::new("tasklist")
+        .args(["/FI", &filter, "/NH", "/FO", "CSV"])
+        .output()
+    {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).contains(&format!("\"{pid}\"")),
+        Err(_) => true,
+    }
+}
+
+fn held_error(path: &Path) -> String {
+    format!(
+        "Another swap-worktree is already running in this repository ({}).\nIf no other swap is running, remove the stale lock file:\n  {}",
+        describe_holder(path),
+        path.display()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lock_path, RepoLock};
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    #[test]
+    fn second_acquire_fails_until_the_first_is_dropped() {
+        let common_dir = env::temp_dir().join(format!("swap-worktree-lock-{}", process::id()));
+        let first = RepoLock::acquire(&common_dir).unwrap();
+        let err = RepoLock::acquire(&common_dir).err().unwrap().to_string();
+        assert!(err.contains(&format!("PID {}", process::id())), "{err}");
+        drop(first);
+        drop(RepoLock::acquire(&common_dir).unwrap());
+        fs::remove_dir_all(&common_dir).unwrap();
+    }
+
+    #[test]
+    fn takes_over_the_lock_of_an_exited_process() {
+        let common_dir =
+            env::temp_dir().join(format!("swap-worktree-stale-lock-{}", process::id()));
+        let mut child = process::Command::new("git")
+            .arg("--version")
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+        child.wait().unwrap();
+        fs::create_dir_all(common_dir.join("swap-worktree")).unwrap();
+        fs::write(lock_path(&common_dir), format!("pid={pid}\nstarted=0\n")).unwrap();
+        let lock = RepoLock::acquire(&common_dir).unwrap();
+        let contents = fs::read_to_string(lock_path(&common_dir)).unwrap();
+        let files = fs::read_dir(common_dir.join("swap-worktree"))
+            .unwrap()
+            .count();
+        drop(lock);
+        fs::remove_dir_all(&common_dir).unwrap();
+        assert!(contents.starts_with(&format!("pid={}\n", process::id())));
+        assert_eq!(files, 1);
+    }
+}
@@ -0,0 +1,396 @@
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, Write as _};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use tracing::field::{Field, Visit};
+use tracing::span::EnteredSpan;
+use tracing::{info, info_span, Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, SubscriberExt as _};
+use tracing_subscriber::{fmt as fmt_layer, Layer, Registry};
+
+use crate::{style, Verbosity};
+
+/// How progress is reported on stdout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum ProgressFormat {
+    /// Human-readable output, as chosen by -v/-q
+    #[default]
+    Text,
+    /// One JSON object per line for each step, git command, stash, warning, and the result
+    Ndjson,
+    /// The NDJSON events as JSON-RPC `progress` notifications, for `serve`
+    #[value(skip)]
+    Rpc,
+    /// `key value` lines describing each completed swap, for `--porcelain`
+    #[value(skip)]
+    Porcelain,
+}
+
+/// Target of the events that announce a step of the swap.
+const STEP_TARGET: &str = "swap_worktree::step";
+
+/// Target of the lines printed by commands the swap runs for the user, such as `--exec`.
+pub(crate) const OUTPUT_TARGET: &str = "swap_worktree::output";
+
+/// Fields that only go to the log file; progress events stay one short line each.
+const LOG_ONLY_FIELDS: [&str; 2] = ["stdout", "stderr"];
+
+/// Version of the `--porcelain` format. Keys may be added within a version; renaming or
+/// removing one, or changing what it means, bumps it.
+const PORCELAIN_VERSION: u32 = 1;
+
+/// Set when stdout carries NDJSON, so nothing else may print there.
+static NDJSON: AtomicBool = AtomicBool::new(false);
+
+/// Set when stdout carries `--porcelain` lines, so nothing else may print there.
+static PORCELAIN: AtomicBool = AtomicBool::new(false);
+
+/// Installs the global subscriber: the console shows what `verbosity` asks for (or NDJSON
+/// progress events), and `log_file`, when given, receives every event (git commands and
+/// their output included) with a timestamp, whatever the console shows.
+pub(crate) fn init(
+    verbosity: Verbosity,
+    progress: ProgressFormat,
+    log_file: Option<&Path>,
+) -> Result<(), Box<dyn Error>> {
+    let file = match log_file {
+        Some(path) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|err| format!("Failed to open log file '{}': {err}", path.display()))?;
+            Some(
+                fmt_layer::layer()
+                    .with_writer(Mutex::new(file))
+                    .with_ansi(false)
+                    .with_target(false),
+            )
+        }
+        None => None,
+    };
+    let ndjson = matches!(progress, ProgressFormat::Ndjson | ProgressFormat::Rpc);
+    let porcelain = progress == ProgressFormat::Porcelain;
+    NDJSON.store(ndjson, Ordering::Relaxed);
+    PORCELAIN.store(porcelain, Ordering::Relaxed);
+    // Porcelain keeps warnings and errors on stderr, and nothing else.
+    let verbosity = if porcelain {
+        Verbosity::Quiet
+    } else {
+        verbosity
+    };
+    let subscriber = Registry::default()
+        .with((!ndjson).then_some(Console { verbosity }))
+        .with(ndjson.then_some(Ndjson {
+            rpc: progress == ProgressFormat::Rpc,
+        }))
+        .with(porcelain.then(Porcelain::default))
+        .with(file);
+    tracing::subscriber::set_global_default(subscriber)?;
+    let args: Vec<String> = env::args().collect();
+    info!(
+        "swap-worktree {} started: {}",
+        env!("CARGO_PKG_VERSION"),
+        args.join(" ")
+    );
+    Ok(())
+}
+
+/// Announces step `number` of the swap and returns the span that the step's events belong
+/// to. The span ends when the guard is dropped.
+pub(crate) fn step(number: u32, description: &str) -> EnteredSpan {
+    // Steps follow each other rather than nest, whatever span is current when they start.
+    let span = info_span!(parent: None, "step", number).entered();
+    info!(
+        target: STEP_TARGET,
+        event = "step",
+        number,
+        name = description,
+        "Step {number}: {description}"
+    );
+    span
+}
+
+/// Whether stdout carries NDJSON progress events (or the `serve` protocol) instead of text.
+pub(crate) fn ndjson() -> bool {
+    NDJSON.load(Ordering::Relaxed)
+}
+
+/// Whether stdout carries `--porcelain` lines instead of text.
+pub(crate) fn porcelain() -> bool {
+    PORCELAIN.load(Ordering::Relaxed)
+}
+
+/// Prints events to the terminal: warnings to stderr at every verbosity, step headers with
+/// `-v`, and details with `-vv`. Trace events (git commands) only go to the log file.
+struct Console {
+    verbosity: Verbosity,
+}
+
+impl<S: Subscriber> Layer<S> for Console {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let level = *metadata.level();
+        let required = if level <= Level::WARN {
+            Verbosity::Quiet
+        } else if metadata.target() == STEP_TARGET {
+            Verbosity::Verbose
+        } else if metadata.target() == OUTPUT_TARGET {
+            Verbosity::Normal
+        } else if level == Level::DEBUG {
+            Verbosity::Debug
+        } else {
+            return;
+        };
+        if self.verbosity < required {
+            return;
+        }
+        let mut message = Message(String::new());
+        event.record(&mut message);
+        let message = message.0;
+        match level {
+            Level::ERROR => eprintln!("{message}"),
+            Level::WARN => eprintln!("{} {message}", style::warning("Warning:")),
+            _ if metadata.target() == STEP_TARGET => println!("{}", style::header(&message)),
+            _ if metadata.target() == OUTPUT_TARGET => {
+                let mut fields = JsonFields(Map::new());
+                event.record(&mut fields);
+                if fields.0.get("stream").and_then(Value::as_str) == Some("stderr") {
+                    eprintln!("{message}");
+                } else {
+                    write_line(&message);
+                }
+            }
+            _ => println!("{message}"),
+        }
+    }
+}
+
+/// Prints every event that has an `event` field, and every warning and error, as one JSON
+/// object per line on stdout, wrapped in a JSON-RPC notification with `rpc`.
+struct Ndjson {
+    rpc: bool,
+}
+
+impl<S: Subscriber> Layer<S> for Ndjson {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut fields = JsonFields(Map::new());
+        event.record(&mut fields);
+        let mut fields = fields.0;
+        let kind = match (fields.remove("event"), *event.metadata().level()) {
+            (Some(kind), _) => kind,
+            (None, Level::ERROR) => "error".into(),
+            (None, Level::WARN) => "warning".into(),
+            (None, _) => return,
+        };
+        let line = Line {
+            event: kind,
+            fields,
+        };
+        let line = if self.rpc {
+            serde_json::to_string(&json!({
+                "jsonrpc": "2.0",
+                "method": "progress",
+                "params": line,
+            }))
+        } else {
+            serde_json::to_string(&line)
+        };
+        if let Ok(line) = line {
+            write_line(&line);
+        }
+    }
+}
+
+/// Prints one block of `key value` lines per completed swap, from the `done` event and the
+/// stash events before it.
+#[derive(Default)]
+struct Porcelain {
+    /// `(event, worktree, hash)` of the stash events since the last swap completed.
+    stashes: Mutex<Vec<(String, String, String)>>,
+}
+
+impl<S: Subscriber> Layer<S> for Porcelain {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut fields = JsonFields(Map::new());
+        event.record(&mut fields);
+        let fields = fields.0;
+        let Ok(mut stashes) = self.stashes.lock() else {
+            return;
+        };
+        let text = |name: &str| {
+            fields
+                .get(name)
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string()
+        };
+        match fields.get("event").and_then(Value::as_str) {
+            Some(kind @ ("stash" | "stash-applied" | "conflict")) => {
+                stashes.push((kind.to_string(), text("worktree"), text("hash")));
+            }
+            Some("done") => {
+                let block = porcelain_block(&fields, &stashes);
+                stashes.clear();
+                write_line(&block.join("\n"));
+            }
+            // A failed swap in a batch does not leak its stashes into the next block.
+            _ if *event.metadata().level() == Level::ERROR => stashes.clear(),
+            _ => {}
+        }
+    }
+}
+
+/// The `--porcelain` lines for the swap that `done` reports. A branch after the swap is left
+/// out when that worktree was left detached.
+fn porcelain_block(done: &Map<String, Value>, stashes: &[(String, String, String)]) -> Vec<String> {
+    let mut lines = vec![format!("version {PORCELAIN_VERSION}")];
+    let keys = [
+        ("dest-dir", "dest_dir"),
+        ("dest-branch-before", "dest_branch_before"),
+        ("dest-branch-after", "dest_branch"),
+        ("src-dir", "src_dir"),
+        ("src-branch-before", "src_branch_before"),
+        ("src-branch-after", "src_branch"),
+    ];
+    for (key, field) in keys {
+        if let Some(value) = done.get(field).and_then(Value::as_str) {
+            lines.push(format!("{key} {value}"));
+        }
+    }
+    let dest_dir = done.get("dest_dir").and_then(Value::as_str);
+    for (kind, worktree, hash) in stashes {
+        let key = match kind.as_str() {
+            "stash" if Some(worktree.as_str()) == dest_dir => "dest-stash",
+            "stash" => "src-stash",
+            "stash-applied" => "stash-applied",
+            _ => "stash-conflict",
+        };
+        lines.push(format!("{key} {hash}"));
+    }
+    lines
+}
+
+/// Writes one line to stdout at once, so that lines from different threads never mix.
+pub(crate) fn write_line(line: &str) {
+    let mut stdout = io::stdout().lock();
+    let _ = writeln!(stdout, "{line}");
+    let _ = stdout.flush();
+}
+
+/// One progress line; `event` comes first so that the kind of each line is visible at a
+/// glance.
+#[derive(Serialize)]
+struct Line {
+    event: Value,
+    #[serde(flatten)]
+    fields: Map<String, Value>,
+}
+
+/// Collects the fields of an event as JSON values.
+struct JsonFields(Map<String, Value>);
+
+impl JsonFields {
+    fn insert(&mut self, field: &Field, value: Value) {
+        if !LOG_ONLY_FIELDS.contains(&field.name()) {
+            self.0.insert(field.name().to_string(), value);
+        }
+    }
+}
+
+impl Visit for JsonFields {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, format!("{value:?}").into());
+    }
+}
+
+/// Collects the `message` field of an event.
+struct Message(String);
+
+impl Visit for Message {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.0 = format!("{value:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{porcelain_block, Line};
+    use serde_json::{json, Map};
+
+    #[test]
+    fn progress_lines_start_with_the_event() {
+        let mut fields = Map::new();
+        fields.insert("command".to_string(), json!("switch main"));
+        fields.insert("status".to_string(), json!(0));
+        let line = serde_json::to_string(&Line {
+            event: json!("git"),
+            fields,
+        })
+        .unwrap();
+        assert_eq!(
+            line,
+            r#"{"event":"git","command":"switch main","status":0}"#
+        );
+    }
+
+    #[test]
+    fn porcelain_blocks_list_branches_and_stashes() {
+        let done = json!({
+            "dest_dir": "/work/app",
+            "dest_branch_before": "main",
+            "dest_branch": "feature/b",
+            "src_dir": "/work/my review",
+            "src_branch_before": "feature/b",
+        });
+        let stashes = [
+            ("stash", "/work/app", "295085d"),
+            ("stash", "/work/my review", "8a2f1c3"),
+            ("stash-applied", "/work/app", "8a2f1c3"),
+            ("conflict", "/work/my review", "295085d"),
+        ]
+        .map(|(kind, worktree, hash)| (kind.to_string(), worktree.to_string(), hash.to_string()));
+        assert_eq!(
+            porcelain_block(done.as_object().unwrap(), &stashes),
+            [
+                "version 1",
+                "dest-dir /work/app",
+                "dest-branch-before main",
+                "dest-branch-after feature/b",
+                "src-dir /work/my review",
+                "src-branch-before feature/b",
+                "dest-stash 295085d",
+                "src-stash 8a2f1c3",
+                "stash-applied 8a2f1c3",
+                "stash-conflict 295085d",
+            ]
+        );
+    }
+}
@@ -3,42 +3,397 @@ use std::env;
 use std::error::Error;
 use std::ffi::{OsStr, OsString};
 use std::fmt::Write as _;
+use std::io::{self, IsTerminal, Write as _};
 use std::path::{Path, PathBuf};
-use std::process::{Command, ExitStatus};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use clap::{CommandFactory, Parser, ValueHint};
+use backend::Backend as _;
+use clap::{ArgAction, CommandFactory, Parser, ValueEnum, ValueHint};
 use clap_complete::engine::{ArgValueCompleter, CompletionCandidate};
 use clap_complete::CompleteEnv;
+use config::{Config, Profile};
+use embedded_repos::EmbeddedRepoPolicy;
+use hooks::{Hooks, Stage};
+use lfs::LfsPolicy;
+use logging::ProgressFormat;
+use paths::normalize_path;
+use preview::StashPreview;
+use snapshot::RepoSnapshot;
+use sparse::SparsePolicy;
+use style::ColorChoice;
+use tracing::{debug, error, info, trace, warn};
+use worktree_config::WorktreeConfigPolicy;
+use worktree_move::SwapMode;
 
-#[derive(Debug, Parser)]
+mod backend;
+mod batch;
+mod branch_match;
+mod carry_files;
+mod case_collisions;
+mod clean;
+mod clones;
+mod completion_cache;
+mod config;
+mod disk;
+mod doctor;
+mod drafts;
+mod embedded_repos;
+mod exec;
+mod history;
+mod holding;
+mod hooks;
+mod index_flags;
+mod interrupt;
+mod jj;
+mod journal;
+mod lfs;
+mod list;
+mod lock;
+mod logging;
+mod manpage;
+mod partial;
+mod paths;
+mod preview;
+mod repair;
+mod sequencer;
+mod serve;
+mod shell_init;
+mod snapshot;
+mod sparse;
+mod status;
+mod style;
+mod submodules;
+mod timings;
+mod worktree_config;
+mod worktree_move;
+
+#[derive(Debug, Clone, Parser)]
 #[command(
     name = "swap-worktree",
     version,
     about = "Swap branches (and state) between two Git worktrees.",
-    disable_help_subcommand = true
+    disable_help_subcommand = true,
+    subcommand_negates_reqs = true
 )]
 struct Cli {
-    /// Enable verbose logging
-    #[arg(short, long)]
+    #[command(subcommand)]
+    command: Option<Subcommand>,
+
+    /// Show each step (-v) or every detail (-vv)
+    #[arg(short, long, action = ArgAction::Count, global = true, conflicts_with = "quiet")]
+    verbose: u8,
+
+    /// Print nothing on success; warnings and errors still go to stderr
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Same as -vv (kept for existing scripts)
+    #[arg(short, long, global = true, hide = true, conflicts_with = "quiet")]
     debug: bool,
 
-    /// Destination worktree directory
-    #[arg(value_hint = ValueHint::DirPath, value_name = "DESTINATION_WORKTREE_DIR")]
-    destination_worktree_dir: String,
+    /// Append a timestamped log of every step and git command, with its output, to this file
+    #[arg(long, value_name = "PATH", global = true, value_hint = ValueHint::FilePath)]
+    log_file: Option<PathBuf>,
+
+    /// Report progress as text or as one JSON event per line, for editors and other frontends
+    #[arg(long, value_enum, default_value_t, global = true)]
+    progress_format: ProgressFormat,
+
+    /// Print `key value` lines about each completed swap instead of text, in a stable format for scripts
+    #[arg(long, global = true, conflicts_with = "progress_format")]
+    porcelain: bool,
+
+    /// When to color the output
+    #[arg(long, value_enum, default_value_t, global = true)]
+    color: ColorChoice,
+
+    /// Do not ask for confirmation before swapping or dropping stashes
+    #[arg(short, long, global = true)]
+    yes: bool,
+
+    /// Git executable to run [default: $SWAP_WORKTREE_GIT, or git from PATH]
+    #[arg(long, value_name = "PATH", global = true, value_hint = ValueHint::ExecutablePath)]
+    git_cmd: Option<PathBuf>,
+
+    /// Repository of a work tree without a `.git` of its own, e.g. a bare dotfiles repository (requires --work-tree)
+    #[arg(long, value_name = "PATH", global = true, requires = "work_tree", value_hint = ValueHint::DirPath)]
+    git_dir: Option<PathBuf>,
+
+    /// Work tree that git commands run in get `GIT_DIR=<--git-dir>` and `GIT_WORK_TREE` for (requires --git-dir)
+    #[arg(long, value_name = "PATH", global = true, requires = "git_dir", value_hint = ValueHint::DirPath)]
+    work_tree: Option<PathBuf>,
+
+    /// Destination worktree directory (taken from the profile with --profile)
+    #[arg(
+        required_unless_present_any = ["profile", "last"],
+        value_hint = ValueHint::DirPath,
+        value_name = "DESTINATION_WORKTREE_DIR",
+        add = ArgValueCompleter::new(destination_value_completer)
+    )]
+    destination_worktree_dir: Option<String>,
 
     /// Source branch to take over the destination worktree
     #[arg(
+        required_unless_present_any = ["profile", "last"],
         value_name = "SOURCE_BRANCH_NAME",
         add = ArgValueCompleter::new(branch_value_completer)
     )]
-    source_branch_name: String,
+    source_branch_name: Option<String>,
+
+    /// Use the destination and defaults of the `[profile.<NAME>]` config table
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Swap back the most recent swap of this repository: its destination takes its old branch again
+    #[arg(
+        long,
+        conflicts_with_all = ["destination_worktree_dir", "source_branch_name", "profile"]
+    )]
+    last: bool,
+
+    /// After a successful swap, write the destination directory to FILE (used by `init`)
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    cd_file: Option<PathBuf>,
+
+    /// Swap with a separate clone of the same repository that has the source branch checked out
+    #[arg(long, value_hint = ValueHint::DirPath, value_name = "SOURCE_CLONE_DIR")]
+    across_clones: Option<String>,
+
+    /// Also stash ignored files so they travel with their branch
+    #[arg(long)]
+    include_ignored: bool,
+
+    /// Stash only tracked changes; untracked files stay in their worktree
+    #[arg(long, conflicts_with_all = ["include_ignored", "no_stash"])]
+    ignore_untracked: bool,
+
+    /// Refuse to stash more untracked files than this per worktree, e.g. 200MB (--force overrides)
+    #[arg(long, value_name = "SIZE", value_parser = config::parse_size)]
+    max_stash_size: Option<u64>,
+
+    /// Create a worktree for the source branch when none holds it (or recreate a stale one)
+    #[arg(long)]
+    create: bool,
+
+    /// Prune stale worktree records before looking up the source branch
+    #[arg(long)]
+    prune_stale: bool,
+
+    /// Run `git worktree repair` without asking when the source worktree was moved by hand
+    #[arg(long)]
+    repair: bool,
+
+    /// Switch branches in place, or trade the worktree directories so each branch keeps its files
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = SwapMode::Switch,
+        conflicts_with = "across_clones"
+    )]
+    mode: SwapMode,
+
+    /// Never stash; abort unless both worktrees are clean
+    #[arg(long, conflicts_with = "include_ignored")]
+    no_stash: bool,
+
+    /// Stash message template; {branch}, {dir}, {timestamp}, and {pid} are filled in [default: swap-stash-{branch}]
+    #[arg(long, value_name = "TEMPLATE")]
+    stash_message: Option<String>,
+
+    /// Keep the stashes after applying them instead of dropping them
+    #[arg(long)]
+    keep_stash: bool,
+
+    /// Leave the source worktree detached at its commit instead of giving it the destination's branch
+    #[arg(long, conflicts_with = "across_clones")]
+    detach_source: bool,
+
+    /// Swap back and restore both worktrees if a stash fails to apply
+    #[arg(long, conflicts_with_all = ["across_clones", "no_stash"])]
+    rollback_on_conflict: bool,
+
+    /// Run `git mergetool` when a stash applies with conflicts
+    #[arg(long, conflicts_with = "rollback_on_conflict")]
+    mergetool: bool,
+
+    /// Swap even when either worktree is locked with `git worktree lock`
+    #[arg(long)]
+    ignore_locks: bool,
+
+    /// Swap even when safety checks (such as in-progress rebases, merges, or the stash size limit) fail
+    #[arg(long)]
+    force: bool,
+
+    /// Stash changes inside submodules and update submodules after switching
+    #[arg(long, conflicts_with = "across_clones")]
+    recurse_submodules: bool,
+
+    /// Which checkouts run git hooks such as post-checkout
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = GitHooks::All)]
+    git_hooks: GitHooks,
+
+    /// Run no git hooks during the swap (same as --git-hooks none)
+    #[arg(long, conflicts_with = "git_hooks")]
+    no_hooks: bool,
+
+    /// Abort before switching when a partial clone would download missing objects
+    #[arg(long, conflicts_with = "across_clones")]
+    no_fetch_on_demand: bool,
+
+    /// What to do with git repositories nested in untracked directories, which stashing skips
+    #[arg(
+        long,
+        value_enum,
+        value_name = "POLICY",
+        default_value_t = EmbeddedRepoPolicy::Abort,
+        conflicts_with = "across_clones"
+    )]
+    embedded_repos: EmbeddedRepoPolicy,
+
+    /// How to handle Git LFS files when switching branches
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = LfsPolicy::Auto)]
+    lfs: LfsPolicy,
+
+    /// Whether sparse-checkout patterns follow the branch or stay with the directory
+    #[arg(
+        long,
+        value_enum,
+        value_name = "POLICY",
+        default_value_t = SparsePolicy::KeepDir,
+        conflicts_with = "across_clones"
+    )]
+    sparse: SparsePolicy,
+
+    /// Whether per-worktree config (extensions.worktreeConfig) follows the branch or stays
+    #[arg(
+        long,
+        value_enum,
+        value_name = "POLICY",
+        default_value_t = WorktreeConfigPolicy::KeepDir,
+        conflicts_with = "across_clones"
+    )]
+    worktree_config: WorktreeConfigPolicy,
+
+    /// Per-worktree config key or section that follows the branch (repeatable)
+    #[arg(long, value_name = "KEY", conflicts_with = "across_clones")]
+    carry_config: Vec<String>,
+
+    /// Finish one worktree before touching the other (auto-enabled on shared rotational disks)
+    #[arg(long)]
+    serial: bool,
+
+    /// How many times to retry a git command that found `index.lock` held by another process
+    #[arg(long, value_name = "N")]
+    lock_retries: Option<u32>,
+
+    /// Initial delay in milliseconds before retrying; doubles after every attempt
+    #[arg(long, value_name = "MS")]
+    lock_retry_delay: Option<u64>,
+
+    /// Print each worktree's branch, upstream distance, uncommitted files and conflicts after the swap
+    #[arg(long)]
+    stat: bool,
+
+    /// Report how long each phase of the swap and each git command took
+    #[arg(long)]
+    timings: bool,
+
+    /// Command to run in both worktrees after the swap, e.g. "npm ci" (repeatable)
+    #[arg(long, value_name = "COMMAND")]
+    exec: Vec<String>,
+
+    /// Kill any single git command that runs longer than this many seconds
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    timeout: Option<u64>,
+}
+
+#[derive(Debug, Clone, clap::Subcommand)]
+enum Subcommand {
+    /// Print a table of worktrees with their branch, HEAD, upstream and dirty state
+    List {
+        /// Any directory inside the repository (defaults to the current directory)
+        #[arg(value_hint = ValueHint::DirPath, default_value = ".")]
+        dir: String,
+
+        /// Print a JSON array instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// List worktrees, swap stashes, and leftovers from interrupted swaps
+    Status {
+        /// Any directory inside the repository (defaults to the current directory)
+        #[arg(value_hint = ValueHint::DirPath, default_value = ".")]
+        dir: String,
+    },
+    /// Drop the swap stashes left in the stash list after confirmation
+    Clean {
+        /// Any directory inside the repository (defaults to the current directory)
+        #[arg(value_hint = ValueHint::DirPath, default_value = ".")]
+        dir: String,
+    },
+    /// Check git, worktrees and leftovers for problems that make swaps fail
+    Doctor {
+        /// Any directory inside the repository (defaults to the current directory)
+        #[arg(value_hint = ValueHint::DirPath, default_value = ".")]
+        dir: String,
+    },
+    /// Show the swaps recorded in this repository, oldest first
+    History {
+        /// Any directory inside the repository (defaults to the current directory)
+        #[arg(value_hint = ValueHint::DirPath, default_value = ".")]
+        dir: String,
+
+        /// Only show the most recent N swaps
+        #[arg(long, value_name = "N")]
+        last: Option<usize>,
+    },
+    /// Print a shell function that cds into the destination after a successful swap
+    Init {
+        /// Shell to print the function for
+        #[arg(value_enum)]
+        shell: shell_init::Shell,
+    },
+    /// Print a static completion script for installing into the shell's completion directory
+    Completions {
+        /// Shell to generate completions for
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+    /// Print the man page in roff format, e.g. `swap-worktree man > swap-worktree.1`
+    Man,
+    /// Finish or reverse a swap that was interrupted by a crash or a killed terminal
+    Recover {
+        /// Any directory inside the repository (defaults to the current directory)
+        #[arg(value_hint = ValueHint::DirPath, default_value = ".")]
+        dir: String,
+    },
+    /// Run the swaps listed in a file, one `<dir> <branch>` per line, one after another
+    Batch {
+        /// File with the swaps, or `-` to read them from stdin
+        #[arg(value_hint = ValueHint::FilePath)]
+        file: String,
+
+        /// Run the remaining swaps after one fails instead of stopping
+        #[arg(long)]
+        keep_going: bool,
+    },
+    /// Answer JSON-RPC requests from an editor plugin on stdin, one per line
+    Serve {
+        /// Any directory inside the repository (defaults to the current directory)
+        #[arg(value_hint = ValueHint::DirPath, default_value = ".")]
+        dir: String,
+    },
 }
 
 macro_rules! git_args {
     ($($arg:expr),* $(,)?) => {{
-        vec![$(OsString::from($arg)),*]
+        vec![$(::std::ffi::OsString::from($arg)),*]
     }};
 }
+pub(crate) use git_args;
 
 struct GitOutput {
     stdout: String,
@@ -47,141 +402,1251 @@ struct GitOutput {
     command: String,
 }
 
+struct CommandTiming {
+    label: String,
+    dir: Option<PathBuf>,
+    elapsed: Duration,
+}
+
+/// The slowest git invocation of the current swap, used to point out slow steps. Only the
+/// slowest one is kept because `serve` runs git for as long as the editor stays open.
+static SLOWEST_COMMAND: Mutex<Option<CommandTiming>> = Mutex::new(None);
+
+/// Steps faster than this are not worth mentioning in the summary.
+const SLOW_STEP_THRESHOLD: Duration = Duration::from_secs(10);
+
+/// Deadline for a single git invocation, set from `--timeout`. `None` waits forever.
+static GIT_TIMEOUT: Mutex<Option<Duration>> = Mutex::new(None);
+
+/// Which checkouts of a swap run git's hooks (`post-checkout` and the like). A swap switches
+/// each worktree once, but recovering and `--across-clones` detach first, so a slow hook can
+/// run twice per worktree.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum GitHooks {
+    /// Every checkout, detaches included, as with plain git
+    #[default]
+    All,
+    /// Only the switches that leave each worktree on its new branch, not the detaches before them
+    Final,
+    /// None; git runs with `core.hooksPath` pointing nowhere
+    None,
+}
+
+/// The hook policy of the current swap, set from `--git-hooks`.
+static GIT_HOOKS: Mutex<GitHooks> = Mutex::new(GitHooks::All);
+
+/// The git executable, set from `--git-cmd`. `None` falls back to `$SWAP_WORKTREE_GIT`, then
+/// to `git` from `PATH`.
+static GIT_PROGRAM: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// A work tree without a `.git` of its own and the repository it belongs to, set from
+/// `--work-tree` and `--git-dir`: the one case where a swap passes `GIT_DIR` on to git.
+struct RepositoryOverride {
+    git_dir: PathBuf,
+    work_tree: PathBuf,
+}
+
+static REPOSITORY_OVERRIDE: Mutex<Option<RepositoryOverride>> = Mutex::new(None);
+
+impl RepositoryOverride {
+    /// Whether git run in the absolute path `dir` works on the overridden work tree: `dir`
+    /// lies inside it, and not inside a worktree or repository nested in it, which has a
+    /// `.git` of its own.
+    fn covers(&self, dir: &Path) -> bool {
+        dir.starts_with(&self.work_tree)
+            && !dir
+                .ancestors()
+                .take_while(|ancestor| *ancestor != self.work_tree)
+                .any(|ancestor| ancestor.join(".git").exists())
+    }
+}
+
+/// Variables that tie git to one particular repository, index, or object store: those of
+/// `git rev-parse --local-env-vars` except the `-c` settings. A wrapper script that exported
+/// one of them would point every command at the wrong place, e.g. both worktrees at the
+/// same `GIT_INDEX_FILE`, so they are never passed on. Other `GIT_*` variables (authors,
+/// SSH, tracing, `-c` config) are inherited as usual.
+const REPOSITORY_ENV_VARS: [&str; 14] = [
+    "GIT_ALTERNATE_OBJECT_DIRECTORIES",
+    "GIT_COMMON_DIR",
+    "GIT_CONFIG",
+    "GIT_DIR",
+    "GIT_GRAFT_FILE",
+    "GIT_IMPLICIT_WORK_TREE",
+    "GIT_INDEX_FILE",
+    "GIT_INTERNAL_SUPER_PREFIX",
+    "GIT_NO_REPLACE_OBJECTS",
+    "GIT_OBJECT_DIRECTORY",
+    "GIT_PREFIX",
+    "GIT_REPLACE_REF_BASE",
+    "GIT_SHALLOW_FILE",
+    "GIT_WORK_TREE",
+];
+
+/// How git commands react to `index.lock` being held by an IDE or background fetch.
+#[derive(Clone, Copy)]
+struct LockRetry {
+    attempts: u32,
+    delay: Duration,
+}
+
+/// Retry settings for every git invocation, set once from the command line and config.
+static LOCK_RETRY: Mutex<LockRetry> = Mutex::new(LockRetry {
+    attempts: 5,
+    delay: Duration::from_millis(100),
+});
+
 struct StashRecord {
     hash: String,
-    reference: Option<String>,
     branch: String,
+    origin: PathBuf,
+}
+
+struct WorktreeEntry {
+    path: PathBuf,
+    head: Option<String>,
+    branch: Option<String>,
+    prunable: Option<String>,
+    locked: Option<String>,
+}
+
+impl WorktreeEntry {
+    fn is_stale(&self) -> bool {
+        self.prunable.is_some() || !self.path.exists()
+    }
+}
+
+struct SwapPlan {
+    dest_dir: PathBuf,
+    dest_branch: String,
+    src_dir: PathBuf,
+    src_branch: String,
+}
+
+struct SwapOptions {
+    mode: SwapMode,
+    create: bool,
+    prune_stale: bool,
+    repair: bool,
+    include_ignored: bool,
+    ignore_untracked: bool,
+    no_stash: bool,
+    max_stash_size: Option<u64>,
+    stash_message: String,
+    keep_stash: bool,
+    /// Leave the source detached; the destination's changes stay in the stash list.
+    detach_source: bool,
+    rollback_on_conflict: bool,
+    mergetool: bool,
+    force: bool,
+    ignore_locks: bool,
+    recurse_submodules: bool,
+    /// Let a partial clone download missing objects while switching; off with `--no-fetch-on-demand`.
+    fetch_on_demand: bool,
+    embedded_repos: EmbeddedRepoPolicy,
+    lfs: LfsPolicy,
+    sparse: SparsePolicy,
+    worktree_config: WorktreeConfigPolicy,
+    carry_config: Vec<String>,
+    carry_files: Vec<String>,
+    serial: bool,
+    exec: Vec<String>,
+    /// Describe both worktrees after the swap, from `--stat`.
+    stat: bool,
+    hooks: Hooks,
+    /// Swap without asking first, from `--yes` or `confirm = false`.
+    yes: bool,
+}
+
+impl SwapOptions {
+    fn new(cli: &Cli, config: &Config, profile: Option<&Profile>, common_dir: &Path) -> Self {
+        let from_profile =
+            |value: fn(&Profile) -> Option<bool>| profile.and_then(value).unwrap_or(false);
+        Self {
+            hooks: Hooks::new(config, profile, common_dir),
+            yes: cli.yes || config.confirm == Some(false),
+            mode: cli.mode,
+            create: cli.create,
+            prune_stale: cli.prune_stale,
+            repair: cli.repair,
+            // The two stash flags conflict, so a profile default yields to the opposite flag.
+            include_ignored: cli.include_ignored
+                || (!cli.no_stash && from_profile(|profile| profile.include_ignored)),
+            no_stash: cli.no_stash
+                || (!cli.include_ignored && from_profile(|profile| profile.no_stash)),
+            ignore_untracked: cli.ignore_untracked,
+            max_stash_size: cli.max_stash_size.or(config.max_stash_size),
+            stash_message: cli
+                .stash_message
+                .clone()
+                .or_else(|| config.stash_message.clone())
+                .unwrap_or_else(|| status::DEFAULT_STASH_MESSAGE.to_string()),
+            keep_stash: cli.keep_stash
+                || profile
+                    .and_then(|profile| profile.keep_stash)
+                    .or(config.keep_stash)
+                    .unwrap_or(false),
+            detach_source: cli.detach_source,
+            rollback_on_conflict: cli.rollback_on_conflict,
+            mergetool: cli.mergetool,
+            force: cli.force,
+            ignore_locks: cli.ignore_locks,
+            recurse_submodules: cli.recurse_submodules,
+            fetch_on_demand: !cli.no_fetch_on_demand,
+            embedded_repos: cli.embedded_repos,
+            lfs: cli.lfs,
+            sparse: cli.sparse,
+            worktree_config: cli.worktree_config,
+            carry_config: config
+                .carry_config
+                .iter()
+                .flatten()
+                .chain(&cli.carry_config)
+                .cloned()
+                .collect(),
+            carry_files: config.carry_files.clone().unwrap_or_default(),
+            serial: cli.serial,
+            stat: cli.stat,
+            exec: profile
+                .and_then(|profile| profile.exec.as_ref())
+                .into_iter()
+                .flatten()
+                .chain(&cli.exec)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Variables for the switches that move a worktree onto its new branch, from `--lfs` and
+    /// `--no-fetch-on-demand`. Each switch gets them on its own command line, so they never
+    /// reach the process environment or the switches that restore a worktree.
+    fn switch_env(&self) -> Vec<(&'static str, &'static str)> {
+        lfs::switch_env(self.lfs)
+            .into_iter()
+            .chain(partial::switch_env(self.fetch_on_demand))
+            .collect()
+    }
+
+    fn stash_scope(&self) -> &'static str {
+        if self.no_stash {
+            "skipped, --no-stash requires clean worktrees"
+        } else if self.include_ignored {
+            "including untracked and ignored files"
+        } else if self.ignore_untracked {
+            "tracked changes only, untracked files stay put"
+        } else {
+            "including untracked files"
+        }
+    }
+}
+
+/// How much the CLI prints to stdout. Warnings and errors go to stderr at every level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Verbosity {
+    /// `-q`: nothing on success
+    Quiet,
+    /// The summary line and per-feature reports
+    Normal,
+    /// `-v`: also each step of the swap
+    Verbose,
+    /// `-vv`: also every detail of every step
+    Debug,
+}
+
+impl Cli {
+    fn verbosity(&self) -> Verbosity {
+        if self.quiet {
+            return Verbosity::Quiet;
+        }
+        match self.verbose.saturating_add(if self.debug { 2 } else { 0 }) {
+            0 => Verbosity::Normal,
+            1 => Verbosity::Verbose,
+            _ => Verbosity::Debug,
+        }
+    }
 }
 
 struct Logger {
-    debug_enabled: bool,
+    verbosity: Verbosity,
 }
 
 impl Logger {
-    fn new(debug_enabled: bool) -> Self {
-        Self { debug_enabled }
+    fn new(verbosity: Verbosity) -> Self {
+        // NDJSON progress, porcelain lines and the server protocol own stdout; text output
+        // has to stay out.
+        if logging::ndjson() || logging::porcelain() {
+            return Self {
+                verbosity: Verbosity::Quiet,
+            };
+        }
+        Self { verbosity }
     }
 
-    fn is_enabled(&self) -> bool {
-        self.debug_enabled
+    fn shows(&self, level: Verbosity) -> bool {
+        self.verbosity >= level
+    }
+
+    /// Prints a titled list of per-worktree results, or nothing when there are none.
+    fn report(&self, title: &str, lines: &[String]) {
+        if lines.is_empty() || !self.shows(Verbosity::Normal) {
+            return;
+        }
+        println!("{}:", style::title(title));
+        for line in lines {
+            println!("  {line}");
+        }
+    }
+
+    /// Prints which branch each worktree of `plan` moved from and to.
+    /// Reports where each branch ended up; with `detached` the source has no branch.
+    fn summary(&self, plan: &SwapPlan, detached: bool) {
+        let src_branch = (!detached).then_some(plan.dest_branch.as_str());
+        info!(
+            event = "done",
+            dest_dir = plan.dest_dir.to_str(),
+            dest_branch = plan.src_branch,
+            dest_branch_before = plan.dest_branch,
+            src_dir = plan.src_dir.to_str(),
+            src_branch,
+            src_branch_before = plan.src_branch,
+            "Swap complete."
+        );
+        let rows = [
+            (
+                display_relative(&plan.dest_dir),
+                plan.dest_branch.as_str(),
+                plan.src_branch.as_str(),
+            ),
+            (
+                display_relative(&plan.src_dir),
+                plan.src_branch.as_str(),
+                src_branch.unwrap_or("(detached)"),
+            ),
+        ];
+        self.report("Swap complete", &style::swap_table(&rows));
     }
 }
 
-macro_rules! debug_log {
+/// Prints a result meant for people, suppressed by `--quiet`.
+macro_rules! info_log {
     ($logger:expr, $($arg:tt)*) => {
-        if $logger.is_enabled() {
+        if $logger.shows($crate::Verbosity::Normal) {
             println!($($arg)*);
         }
     };
 }
+pub(crate) use info_log;
 
 fn main() {
     CompleteEnv::with_factory(Cli::command).complete();
 
-    if let Err(err) = run(Cli::parse()) {
+    let cli = Cli::parse();
+    style::init(cli.color);
+    if let Ok(mut program) = GIT_PROGRAM.lock() {
+        program.clone_from(&cli.git_cmd);
+    }
+    if let (Some(git_dir), Some(work_tree), Ok(mut repository)) =
+        (&cli.git_dir, &cli.work_tree, REPOSITORY_OVERRIDE.lock())
+    {
+        let absolute = |path: &PathBuf| paths::canonicalize(path).unwrap_or_else(|_| path.clone());
+        *repository = Some(RepositoryOverride {
+            git_dir: absolute(git_dir),
+            work_tree: absolute(work_tree),
+        });
+    }
+    let progress = match cli.command {
+        Some(Subcommand::Serve { .. }) => ProgressFormat::Rpc,
+        _ if cli.porcelain => ProgressFormat::Porcelain,
+        _ => cli.progress_format,
+    };
+    if let Err(err) = logging::init(cli.verbosity(), progress, cli.log_file.as_deref()) {
         eprintln!("{err}");
         std::process::exit(1);
     }
+    if let Err(err) = run(cli) {
+        error!("{err}");
+        std::process::exit(1);
+    }
 }
 
 fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
-    let dest_arg = cli.destination_worktree_dir;
-    let src_branch = cli.source_branch_name;
-    let logger = Logger::new(cli.debug);
+    match &cli.command {
+        Some(Subcommand::List { dir, json }) => {
+            let dir = canonicalize_dir(dir)?;
+            ensure_git_worktree(&dir)?;
+            list::show(&dir, *json)
+        }
+        Some(Subcommand::Status { dir }) => {
+            let dir = canonicalize_dir(dir)?;
+            ensure_git_worktree(&dir)?;
+            status::show(&dir)
+        }
+        Some(Subcommand::Clean { dir }) => {
+            let dir = canonicalize_dir(dir)?;
+            ensure_git_worktree(&dir)?;
+            clean::clean(&dir, cli.yes)
+        }
+        Some(Subcommand::Doctor { dir }) => {
+            let dir = canonicalize_dir(dir)?;
+            ensure_git_worktree(&dir)?;
+            doctor::run(&dir)
+        }
+        Some(Subcommand::History { dir, last }) => {
+            let dir = canonicalize_dir(dir)?;
+            ensure_git_worktree(&dir)?;
+            history::show(&dir, *last)
+        }
+        Some(Subcommand::Init { shell }) => {
+            print!("{}", shell_init::function(*shell));
+            Ok(())
+        }
+        Some(Subcommand::Completions { shell }) => {
+            let mut command = Cli::command();
+            let name = command.get_name().to_string();
+            clap_complete::generate(*shell, &mut command, name, &mut io::stdout());
+            Ok(())
+        }
+        Some(Subcommand::Man) => {
+            manpage::render(Cli::command(), &mut io::stdout())?;
+            Ok(())
+        }
+        Some(Subcommand::Recover { dir }) => {
+            let dir = canonicalize_dir(dir)?;
+            ensure_git_worktree(&dir)?;
+            let common_dir = git_common_dir(&dir)?;
+            let config = Config::load(Some(&common_dir))?;
+            let options = SwapOptions::new(&cli, &config, None, &common_dir);
+            journal::recover(&dir, &options, &Logger::new(cli.verbosity()))
+        }
+        Some(Subcommand::Batch { file, keep_going }) => batch::run(&cli, file, *keep_going),
+        Some(Subcommand::Serve { dir }) => {
+            let dir = canonicalize_dir(dir)?;
+            ensure_git_worktree(&dir)?;
+            serve::run(&dir)
+        }
+        None => run_swap(cli),
+    }
+}
 
-    let dest_dir = canonicalize_dir(&dest_arg)?;
-    ensure_git_worktree(&dest_dir)?;
+/// The flags on the command line that only mean something for git worktrees, which a swap
+/// of jj workspaces refuses instead of ignoring.
+fn git_only_flags(cli: &Cli) -> Vec<&'static str> {
+    [
+        (cli.git_cmd.is_some(), "--git-cmd"),
+        (cli.git_dir.is_some(), "--git-dir"),
+        (cli.across_clones.is_some(), "--across-clones"),
+        (cli.include_ignored, "--include-ignored"),
+        (cli.ignore_untracked, "--ignore-untracked"),
+        (cli.max_stash_size.is_some(), "--max-stash-size"),
+        (cli.create, "--create"),
+        (cli.prune_stale, "--prune-stale"),
+        (cli.repair, "--repair"),
+        (cli.mode != SwapMode::default(), "--mode"),
+        (cli.no_stash, "--no-stash"),
+        (cli.stash_message.is_some(), "--stash-message"),
+        (cli.keep_stash, "--keep-stash"),
+        (cli.detach_source, "--detach-source"),
+        (cli.rollback_on_conflict, "--rollback-on-conflict"),
+        (cli.mergetool, "--mergetool"),
+        (cli.ignore_locks, "--ignore-locks"),
+        (cli.recurse_submodules, "--recurse-submodules"),
+        (cli.git_hooks != GitHooks::default(), "--git-hooks"),
+        (cli.no_hooks, "--no-hooks"),
+        (cli.no_fetch_on_demand, "--no-fetch-on-demand"),
+        (
+            cli.embedded_repos != EmbeddedRepoPolicy::default(),
+            "--embedded-repos",
+        ),
+        (cli.lfs != LfsPolicy::default(), "--lfs"),
+        (cli.sparse != SparsePolicy::default(), "--sparse"),
+        (
+            cli.worktree_config != WorktreeConfigPolicy::default(),
+            "--worktree-config",
+        ),
+        (!cli.carry_config.is_empty(), "--carry-config"),
+        (cli.lock_retries.is_some(), "--lock-retries"),
+        (cli.lock_retry_delay.is_some(), "--lock-retry-delay"),
+        (cli.stat, "--stat"),
+        (cli.timeout.is_some(), "--timeout"),
+    ]
+    .into_iter()
+    .filter_map(|(given, flag)| given.then_some(flag))
+    .collect()
+}
 
-    let repo_root = determine_repo_root(&dest_dir)?;
-    debug_log!(&logger, "Operating in repository: {}", repo_root.display());
-    debug_log!(&logger, "---");
+fn run_swap(cli: Cli) -> Result<(), Box<dyn Error>> {
+    let (dest_arg, src_branch) = match (
+        &cli.destination_worktree_dir,
+        &cli.source_branch_name,
+        &cli.profile,
+    ) {
+        _ if cli.last => {
+            let last = history::last_swap(&env::current_dir()?)?;
+            debug!(
+                "Swapping back the last swap: '{}' takes '{}' again.",
+                last.dest_dir.display(),
+                last.dest_branch
+            );
+            (last.dest_dir, last.dest_branch)
+        }
+        (Some(dest), Some(src), _) => (PathBuf::from(dest), src.clone()),
+        // With a profile, a single positional argument is the source branch.
+        (Some(src), None, Some(profile)) => (profile_destination(profile)?, src.clone()),
+        (None, None, Some(_)) => return Err("SOURCE_BRANCH_NAME is required.".into()),
+        _ => {
+            return Err("Both DESTINATION_WORKTREE_DIR and SOURCE_BRANCH_NAME are required.".into())
+        }
+    };
+    let logger = Logger::new(cli.verbosity());
+    if cli.timings {
+        timings::start();
+    }
+    let dest_dir = canonicalize_dir(dest_arg)?;
+    if let Some(workspace) = jj::Jj::detect(&dest_dir) {
+        let flags = git_only_flags(&cli);
+        if !flags.is_empty() {
+            return Err(format!(
+                "'{}' is a jj workspace; {} only apply to git worktrees. Nothing was changed.",
+                dest_dir.display(),
+                flags.join(", ")
+            )
+            .into());
+        }
+        let state_dir = workspace.state_dir()?;
+        let config = Config::load(Some(&state_dir))?;
+        let profile = cli
+            .profile
+            .as_deref()
+            .map(|name| config.profile(name))
+            .transpose()?;
+        let _repo_lock = lock::RepoLock::acquire(&state_dir)?;
+        let options = SwapOptions::new(&cli, &config, profile, &state_dir);
+        backend::swap(&workspace, &dest_dir, &src_branch, &options, &logger)?;
+        return write_cd_file(&cli, &dest_dir);
+    }
+    let mut snapshot = RepoSnapshot::read(&dest_dir)?;
+    let common_dir = snapshot.common_dir.clone();
+    let config = Config::load(Some(&common_dir))?;
+    let profile = match cli.profile.as_deref() {
+        Some(name) => Some(config.profile(name)?),
+        None => None,
+    };
+    let _repo_lock = lock::RepoLock::acquire(&common_dir)?;
+    journal::ensure_no_interrupted_swap(&common_dir)?;
+    let mut options = SwapOptions::new(&cli, &config, profile, &common_dir);
+    configure_lock_retry(&cli, &config);
+    if let Ok(mut timeout) = GIT_TIMEOUT.lock() {
+        *timeout = cli.timeout.map(Duration::from_secs);
+    }
+    if let Ok(mut hooks) = GIT_HOOKS.lock() {
+        *hooks = if cli.no_hooks {
+            GitHooks::None
+        } else {
+            cli.git_hooks
+        };
+    }
+    if let Ok(mut slowest) = SLOWEST_COMMAND.lock() {
+        *slowest = None;
+    }
 
-    debug_log!(
-        &logger,
-        "Step 1: Fetching branch for destination directory '{}'...",
-        dest_dir.display()
+    debug!(
+        "Operating in repository: {}",
+        snapshot.repo_root().display()
+    );
+
+    let _step = logging::step(
+        1,
+        &format!(
+            "Fetching branch for destination directory '{}'...",
+            dest_dir.display()
+        ),
     );
-    let dest_branch = current_branch(&dest_dir)?;
-    debug_log!(&logger, "Found destination branch: '{dest_branch}'");
-    debug_log!(&logger, "---");
+    sequencer::ensure_no_operations_in_progress(
+        &[(&dest_dir, &snapshot.operations)],
+        options.force,
+    )?;
+    let dest_branch = snapshot.current_branch()?;
+    debug!("Found destination branch: '{dest_branch}'");
 
-    debug_log!(
-        &logger,
-        "Step 2: Fetching directory for source branch '{src_branch}'..."
+    if let Some(clone_arg) = cli.across_clones.as_deref() {
+        clones::swap_across_clones(
+            &dest_dir,
+            &dest_branch,
+            &snapshot,
+            clone_arg,
+            &src_branch,
+            &options,
+            &logger,
+        )?;
+        return write_cd_file(&cli, &dest_dir);
+    }
+
+    let src_branch = branch_match::resolve(&snapshot, &dest_branch, &src_branch, &logger)?;
+    let _step = logging::step(
+        2,
+        &format!("Fetching directory for source branch '{src_branch}'..."),
     );
-    let src_dir = find_worktree_for_branch(&dest_dir, &src_branch)?;
-    debug_log!(&logger, "Found source directory: '{}'", src_dir.display());
-    debug_log!(&logger, "---");
+    let src_dir = resolve_source_worktree(&dest_dir, &mut snapshot, &src_branch, &options)?;
+    debug!("Found source directory: '{}'", src_dir.display());
 
-    let dest_dir_canon = dest_dir.canonicalize()?;
-    let src_dir_canon = src_dir.canonicalize()?;
-    if dest_dir_canon == src_dir_canon {
+    if paths::same_path(&dest_dir, &src_dir) {
         return Err("Source and destination directories are the same. Nothing to swap.".into());
     }
+    sequencer::ensure_no_operations_in_progress(
+        &[(&src_dir, &sequencer::operations_in_progress(&src_dir)?)],
+        options.force,
+    )?;
+    ensure_unlocked(
+        &snapshot.worktrees,
+        &[&dest_dir, &src_dir],
+        options.ignore_locks,
+    )?;
+    if options.no_stash {
+        ensure_clean_worktrees(&[(&dest_dir, &dest_branch), (&src_dir, &src_branch)])?;
+    }
+    if !options.serial && disk::shares_rotational_disk(&dest_dir, &src_dir) {
+        debug!("Both worktrees share a rotational disk; enabling serial mode.");
+        options.serial = true;
+    }
 
-    debug_log!(
-        &logger,
-        "Step 3: Stashing changes in both worktrees (including untracked files)..."
+    let plan = SwapPlan {
+        dest_dir,
+        dest_branch,
+        src_dir,
+        src_branch,
+    };
+    if options.mode == SwapMode::Move {
+        worktree_move::run(&plan, &snapshot, &options, &logger)?;
+        return write_cd_file(&cli, &plan.dest_dir);
+    }
+    let clone_shape = partial::CloneShape::read(&plan.dest_dir)?;
+    let fetches = clone_shape.check(&plan, options.detach_source, options.fetch_on_demand)?;
+    let (mut dest_embedded, mut src_embedded) =
+        embedded_repos::find_both(&plan, &snapshot.toplevel, &options)?;
+    confirm_swap(&plan, &options, &fetches)?;
+    // Until here Ctrl-C simply exits: nothing has been changed yet.
+    interrupt::install()?;
+    let _journal = journal::Journal::begin(&common_dir, &plan)?;
+    options.hooks.run(Stage::PreSwap, &plan)?;
+    let dest_sparse = sparse::read_profile(&plan.dest_dir)?;
+    let src_sparse = sparse::read_profile(&plan.src_dir)?;
+    let lfs_active = lfs::prepare(
+        &plan.dest_dir,
+        &[&plan.dest_branch, &plan.src_branch],
+        options.lfs,
+    )?;
+    let worktree_config = worktree_config::read(&plan.dest_dir, &plan.src_dir)?;
+    let dest_drafts = drafts::take(&plan.dest_dir)?;
+    let src_drafts = drafts::take(&plan.src_dir)?;
+    // From here on, a failure puts back what was already taken out of the worktrees.
+    let (dest_submodules, src_submodules) = if options.recurse_submodules {
+        debug!("Stashing changes inside submodules...");
+        submodules::stash_both(&plan, &options)?
+    } else {
+        (Vec::new(), Vec::new())
+    };
+    let put_back_submodules = |err: Box<dyn Error>| {
+        submodules::restore_original(&dest_submodules, &options);
+        submodules::restore_original(&src_submodules, &options);
+        err
+    };
+    let (dest_carried, src_carried) = carry_files::take_both(
+        &snapshot.toplevel,
+        &plan.src_dir,
+        &options.carry_files,
+        &common_dir.join("swap-worktree").join("carry"),
+    )
+    .map_err(put_back_submodules)?;
+    if let Err(err) = embedded_repos::take_both(
+        &mut dest_embedded,
+        &mut src_embedded,
+        options.embedded_repos,
+        &common_dir.join("swap-worktree").join("embedded"),
+    ) {
+        let _ = dest_carried.put_back();
+        let _ = src_carried.put_back();
+        return Err(put_back_submodules(err));
+    }
+    // Cleared last: the bits are only set again once the swap has either finished or failed.
+    let (dest_flags, src_flags) = match index_flags::take_both(&plan.dest_dir, &plan.src_dir) {
+        Ok(flags) => flags,
+        Err(err) => {
+            let _ = dest_embedded.put_back();
+            let _ = src_embedded.put_back();
+            let _ = dest_carried.put_back();
+            let _ = src_carried.put_back();
+            return Err(put_back_submodules(err));
+        }
+    };
+    let swapped = if options.serial {
+        swap_serially(&plan, &options)
+    } else {
+        swap_in_phases(&plan, &options)
+    };
+    let stashes = match swapped {
+        Ok(stashes) => {
+            journal::finish();
+            stashes
+        }
+        Err(err) => {
+            // Best effort: put the bits back where they were before the swap was attempted.
+            let _ = index_flags::restore(&plan.dest_dir, &dest_flags);
+            let _ = index_flags::restore(&plan.src_dir, &src_flags);
+            let _ = dest_carried.put_back();
+            let _ = src_carried.put_back();
+            let _ = dest_embedded.put_back();
+            let _ = src_embedded.put_back();
+            let err = put_back_submodules(err);
+            let first_line = err
+                .to_string()
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .to_string();
+            history::append(
+                &common_dir,
+                &history::HistoryEntry::new(&plan, &[], format!("failed: {first_line}")),
+            );
+            return Err(err);
+        }
+    };
+    history::append(
+        &common_dir,
+        &history::HistoryEntry::new(&plan, &stashes, "swapped".to_string()),
     );
-    let dest_stash = stash_worktree(&dest_dir, &dest_branch, &logger)?;
-    let src_stash = stash_worktree(&src_dir, &src_branch, &logger)?;
-    debug_log!(&logger, "---");
-
-    debug_log!(&logger, "Step 4: Swapping branches between worktrees...");
-    detach_worktree(&dest_dir, &dest_branch, &logger)?;
-    if let Err(err) = detach_worktree(&src_dir, &src_branch, &logger) {
-        eprintln!("Error: {err}");
-        eprintln!(
-            "Attempting to restore '{}' to '{}'...",
-            dest_dir.display(),
-            dest_branch
-        );
-        let _ = run_git(Some(&dest_dir), git_args!["switch", &dest_branch]);
-        return Err("Failed to detach source worktree. Aborting.".into());
+    let carry_report = carry_files::exchange(&dest_carried, &src_carried);
+    let embedded_report = embedded_repos::exchange(&dest_embedded, &src_embedded);
+    let config_report = match worktree_config {
+        Some(snapshot) => worktree_config::swap(
+            &plan.dest_dir,
+            &plan.src_dir,
+            snapshot,
+            options.worktree_config,
+            &options.carry_config,
+        ),
+        None => Vec::new(),
+    };
+    let flags_report = index_flags::carry(&plan.dest_dir, &dest_flags, &plan.src_dir, &src_flags);
+    let drafts_report = drafts::exchange(&plan.dest_dir, &dest_drafts, &plan.src_dir, &src_drafts);
+    let sparse_report = sparse::restore(
+        &plan.dest_dir,
+        dest_sparse,
+        &plan.src_dir,
+        src_sparse,
+        options.sparse,
+    );
+    let lfs_report = if lfs_active {
+        lfs::finish(&[&plan.dest_dir, &plan.src_dir], options.lfs, &logger)
+    } else {
+        Vec::new()
+    };
+    let submodule_report = if options.recurse_submodules {
+        debug!("Updating submodules and restoring their changes...");
+        submodules::restore_submodules(&plan, dest_submodules, src_submodules, &options)
+    } else {
+        Vec::new()
+    };
+    let exec_report = exec::run_in_worktrees(
+        &options.exec,
+        &[&plan.dest_dir, &plan.src_dir],
+        options.serial,
+    );
+    let stat_report = if options.stat {
+        status::after_swap(&[&plan.dest_dir, &plan.src_dir])
+    } else {
+        Vec::new()
+    };
+    debug!("Worktree swap complete.");
+    logger.summary(&plan, options.detach_source);
+    logger.report("Carried files", &carry_report);
+    logger.report("Embedded repositories", &embedded_report);
+    logger.report("Commit message drafts", &drafts_report);
+    logger.report("Per-worktree config", &config_report);
+    logger.report("Index flags", &flags_report);
+    logger.report("Sparse checkout", &sparse_report);
+    logger.report("Submodules", &submodule_report);
+    logger.report("Git LFS", &lfs_report);
+    logger.report("Exec", &exec_report);
+    logger.report("Worktree state", &stat_report);
+    if options.keep_stash {
+        report_kept_stashes(&stashes, &logger);
     }
-    debug_log!(&logger, "Both worktrees detached. Proceeding with swap.");
+    if options.detach_source {
+        report_parked_stash(&plan, &stashes, &logger);
+    }
+    report_slowest_step(&options, &logger);
+    timings::report(&logger);
+    options.hooks.run(Stage::PostSwap, &plan)?;
 
-    switch_worktree(&dest_dir, &src_branch, &logger)?;
-    if let Err(err) = switch_worktree(&src_dir, &dest_branch, &logger) {
-        return Err(format!(
-            "Error: {err}\nCRITICAL STATE: '{}' is on '{src_branch}', but '{}' is still detached.\nPlease manually run:\n  git -C '{}' switch '{src_branch}'\n  git -C '{}' switch '{dest_branch}'",
-            dest_dir.display(),
-            src_dir.display(),
-            dest_dir.display(),
-            src_dir.display(),
-        ).into());
+    write_cd_file(&cli, &plan.dest_dir)
+}
+
+/// Tells the `init` shell function where to go: the destination now holds the branch the
+/// user asked for.
+fn write_cd_file(cli: &Cli, dest_dir: &Path) -> Result<(), Box<dyn Error>> {
+    let Some(path) = &cli.cd_file else {
+        return Ok(());
+    };
+    std::fs::write(path, dest_dir.as_os_str().as_encoded_bytes())
+        .map_err(|err| format!("Failed to write '{}': {err}", path.display()).into())
+}
+
+fn swap_in_phases(
+    plan: &SwapPlan,
+    options: &SwapOptions,
+) -> Result<Vec<StashRecord>, Box<dyn Error>> {
+    let SwapPlan {
+        dest_dir,
+        dest_branch,
+        src_dir,
+        src_branch,
+    } = plan;
+    let _step = logging::step(
+        3,
+        &format!(
+            "Stashing changes in both worktrees ({})...",
+            options.stash_scope()
+        ),
+    );
+    check_interrupt(plan, None, None, options)?;
+    ensure_no_leftover_stashes(plan, options)?;
+    journal::record("step", "stashing");
+    if options.detach_source {
+        journal::record("detach_source", "true");
+    }
+    let [dest_result, src_result] =
+        stash_in_parallel([(dest_dir, dest_branch), (src_dir, src_branch)], options);
+    if let Ok(stash) = &dest_result {
+        record_stash("dest_stash", stash.as_ref());
+    }
+    if let Ok(stash) = &src_result {
+        record_stash("src_stash", stash.as_ref());
     }
+    let (dest_stash, src_stash) = match (dest_result, src_result) {
+        (Ok(dest_stash), Ok(src_stash)) => (dest_stash, src_stash),
+        (Err(err), src_result) => {
+            let src_stash = src_result.ok().flatten();
+            return Err(unwind_failed_step(
+                err,
+                plan,
+                [None, src_stash.as_ref()],
+                options,
+                "Failed to stash destination worktree.",
+            ));
+        }
+        (Ok(dest_stash), Err(err)) => {
+            return Err(unwind_failed_step(
+                err,
+                plan,
+                [dest_stash.as_ref(), None],
+                options,
+                "Failed to stash source worktree.",
+            ))
+        }
+    };
+    let interrupted = || check_interrupt(plan, dest_stash.as_ref(), src_stash.as_ref(), options);
+    interrupted()?;
+
+    let _step = logging::step(4, "Swapping branches between worktrees...");
+    // Neither worktree is detached first: the destination takes the source branch while the
+    // source still has it, and the source moves off it right after, so there are two
+    // checkouts instead of four and no moment with both worktrees detached.
+    if let Err(err) = switch_fast(dest_dir, src_branch, &options.switch_env(), take_branch) {
+        return Err(unwind_failed_step(
+            err,
+            plan,
+            [dest_stash.as_ref(), src_stash.as_ref()],
+            options,
+            "Failed to switch destination worktree.",
+        ));
+    }
+    journal::record("step", "switched-dest");
+    interrupted()?;
+    if let Err(err) = leave_source_branch(plan, options) {
+        return Err(unwind_failed_source_switch(
+            err,
+            plan,
+            [dest_stash.as_ref(), src_stash.as_ref()],
+            &[],
+            options,
+        ));
+    }
+    journal::record("step", "switched");
+    interrupted()?;
 
-    debug_log!(&logger, "Branch swap successful.");
-    debug_log!(
-        &logger,
+    debug!("Branch swap successful.");
+    debug!(
         "  '{}' is now on branch '{src_branch}'.",
         dest_dir.display()
     );
-    debug_log!(
-        &logger,
-        "  '{}' is now on branch '{dest_branch}'.",
-        src_dir.display()
-    );
-    debug_log!(&logger, "---");
-
-    debug_log!(
-        &logger,
-        "Step 5: Applying stashes to their new locations..."
-    );
-    apply_and_drop_stash(&dest_dir, &src_branch, src_stash.as_ref(), &logger);
-    apply_and_drop_stash(&src_dir, &dest_branch, dest_stash.as_ref(), &logger);
-    debug_log!(&logger, "---");
-    debug_log!(&logger, "Worktree swap complete.");
-    if !logger.is_enabled() {
-        println!(
-            "Swap complete: '{}' -> '{src_branch}', '{}' -> '{dest_branch}'.",
-            dest_dir.display(),
+    if !options.detach_source {
+        debug!(
+            "  '{}' is now on branch '{dest_branch}'.",
             src_dir.display()
         );
     }
 
-    Ok(())
+    let _step = logging::step(5, "Applying stashes to their new locations...");
+    if options.rollback_on_conflict {
+        apply_or_roll_back(plan, dest_stash.as_ref(), src_stash.as_ref(), options)?;
+    } else {
+        apply_and_drop_stash(dest_dir, src_branch, src_stash.as_ref(), options);
+        if !options.detach_source {
+            apply_and_drop_stash(src_dir, dest_branch, dest_stash.as_ref(), options);
+        }
+    }
+    Ok(dest_stash.into_iter().chain(src_stash).collect())
+}
+
+/// Finishes one worktree before touching the other so that spinning disks are not asked
+/// to seek back and forth between two checkouts.
+fn swap_serially(
+    plan: &SwapPlan,
+    options: &SwapOptions,
+) -> Result<Vec<StashRecord>, Box<dyn Error>> {
+    let SwapPlan {
+        dest_dir,
+        dest_branch,
+        src_dir,
+        src_branch,
+    } = plan;
+    let _step = logging::step(
+        3,
+        &format!(
+            "Stashing one worktree at a time ({})...",
+            options.stash_scope()
+        ),
+    );
+    check_interrupt(plan, None, None, options)?;
+    ensure_no_leftover_stashes(plan, options)?;
+    journal::record("step", "stashing");
+    if options.detach_source {
+        journal::record("detach_source", "true");
+    }
+    let dest_stash = stash_worktree(dest_dir, dest_branch, options)?;
+    record_stash("dest_stash", dest_stash.as_ref());
+    check_interrupt(plan, dest_stash.as_ref(), None, options)?;
+    let src_stash = match stash_worktree(src_dir, src_branch, options) {
+        Ok(stash) => stash,
+        Err(err) => {
+            return Err(unwind_failed_step(
+                err,
+                plan,
+                [dest_stash.as_ref(), None],
+                options,
+                "Failed to stash source worktree.",
+            ))
+        }
+    };
+    record_stash("src_stash", src_stash.as_ref());
+    // Once the first stash is applied the swap is finished rather than unwound.
+    check_interrupt(plan, dest_stash.as_ref(), src_stash.as_ref(), options)?;
+
+    let _step = logging::step(4, "Switching and restoring one worktree at a time...");
+    if let Err(err) = switch_fast(dest_dir, src_branch, &options.switch_env(), take_branch) {
+        return Err(unwind_failed_step(
+            err,
+            plan,
+            [dest_stash.as_ref(), src_stash.as_ref()],
+            options,
+            "Failed to switch destination worktree.",
+        ));
+    }
+    journal::record("step", "switched-dest");
+    // The destination's stash is only dropped once the source has moved off its branch, so
+    // that a failed switch can still put everything back.
+    let (dest_applied, created) = if options.rollback_on_conflict {
+        (false, Vec::new())
+    } else {
+        let created = match src_stash.as_ref() {
+            Some(stash) => created_paths(dest_dir, stash)?,
+            None => Vec::new(),
+        };
+        (
+            apply_incoming_stash(dest_dir, src_branch, src_stash.as_ref(), options),
+            created,
+        )
+    };
+    if let Err(err) = leave_source_branch(plan, options) {
+        return Err(unwind_failed_source_switch(
+            err,
+            plan,
+            [dest_stash.as_ref(), src_stash.as_ref()],
+            &created,
+            options,
+        ));
+    }
+    journal::record("step", "switched");
+    if let Some(stash) = src_stash.as_ref().filter(|_| dest_applied) {
+        finish_stash(stash, options);
+    }
+    if options.rollback_on_conflict {
+        apply_or_roll_back(plan, dest_stash.as_ref(), src_stash.as_ref(), options)?;
+    } else if !options.detach_source {
+        apply_and_drop_stash(src_dir, dest_branch, dest_stash.as_ref(), options);
+    }
+    Ok(dest_stash.into_iter().chain(src_stash).collect())
+}
+
+/// Handles a step that failed before the swap was complete: both worktrees go back to their
+/// original branches and get their own stashes back.
+fn unwind_failed_step(
+    err: Box<dyn Error>,
+    plan: &SwapPlan,
+    stashes: [Option<&StashRecord>; 2],
+    options: &SwapOptions,
+    reason: &str,
+) -> Box<dyn Error> {
+    eprintln!("Error: {err}");
+    eprintln!("Attempting to restore both worktrees...");
+    let [dest_stash, src_stash] = stashes;
+    if let Err(restore_err) = restore_original_branches(plan, dest_stash, src_stash, options) {
+        warn!("Restoring failed: {restore_err}");
+        for stash in stashes.into_iter().flatten() {
+            eprintln!(
+                "  Stash {} from '{}' was kept; re-apply it with `git stash apply {}`.",
+                stash.hash,
+                stash.origin.display(),
+                stash.hash
+            );
+        }
+        eprintln!("  Run `swap-worktree recover` to retry restoring both worktrees.");
+    }
+    format!("{reason} Aborting.").into()
+}
+
+/// Handles a source worktree that could not move off its branch after the destination took
+/// it: whatever was applied to the destination is discarded, and the swap is unwound.
+fn unwind_failed_source_switch(
+    err: Box<dyn Error>,
+    plan: &SwapPlan,
+    stashes: [Option<&StashRecord>; 2],
+    created: &[PathBuf],
+    options: &SwapOptions,
+) -> Box<dyn Error> {
+    if let Err(discard_err) = discard_partial_changes(plan, created) {
+        warn!("Discarding the changes applied so far failed: {discard_err}");
+        return critical_switch_error(err, plan);
+    }
+    unwind_failed_step(
+        err,
+        plan,
+        stashes,
+        options,
+        "Failed to switch source worktree.",
+    )
+}
+
+fn critical_switch_error(err: Box<dyn Error>, plan: &SwapPlan) -> Box<dyn Error> {
+    let SwapPlan {
+        dest_dir,
+        dest_branch,
+        src_dir,
+        src_branch,
+    } = plan;
+    format!(
+        "Error: {err}\nCRITICAL STATE: '{}' is on '{src_branch}', but '{}' did not move off it to '{dest_branch}'.\nRun `swap-worktree recover` to finish the swap, or manually run:\n  git -C '{}' switch --ignore-other-worktrees '{dest_branch}'",
+        dest_dir.display(),
+        src_dir.display(),
+        src_dir.display(),
+    )
+    .into()
+}
+
+/// Unwinds the swap when Ctrl-C was pressed, leaving both worktrees on their original
+/// branches with their original changes, and reports the interruption as an error.
+fn check_interrupt(
+    plan: &SwapPlan,
+    dest_stash: Option<&StashRecord>,
+    src_stash: Option<&StashRecord>,
+    options: &SwapOptions,
+) -> Result<(), Box<dyn Error>> {
+    if !interrupt::requested() {
+        return Ok(());
+    }
+    eprintln!("Restoring both worktrees after the interrupt...");
+    restore_original_branches(plan, dest_stash, src_stash, options).map_err(|err| {
+        format!(
+            "Interrupted, and restoring the worktrees failed: {err}
+Check both worktrees and `git stash list` before retrying."
+        )
+    })?;
+    Err(format!(
+        "Interrupted. Nothing was swapped: '{}' is on '{}' and '{}' is on '{}'.",
+        plan.dest_dir.display(),
+        plan.dest_branch,
+        plan.src_dir.display(),
+        plan.src_branch
+    )
+    .into())
+}
+
+/// Applies both stashes and drops them only when both applied cleanly. On the first
+/// failure the swap is reversed so both worktrees end up exactly as they started.
+fn apply_or_roll_back(
+    plan: &SwapPlan,
+    dest_stash: Option<&StashRecord>,
+    src_stash: Option<&StashRecord>,
+    options: &SwapOptions,
+) -> Result<(), Box<dyn Error>> {
+    let mut created = Vec::new();
+    // A detached source takes nothing; the destination's stash stays in the list.
+    let incoming = if options.detach_source {
+        None
+    } else {
+        dest_stash
+    };
+    for (dir, branch, stash) in [
+        (&plan.dest_dir, &plan.src_branch, src_stash),
+        (&plan.src_dir, &plan.dest_branch, incoming),
+    ] {
+        if let Some(stash) = stash {
+            created.extend(created_paths(dir, stash)?);
+        }
+        // A case collision fails the apply before git touches anything, like a conflict.
+        let checked = match stash {
+            Some(stash) => case_collisions::check(dir, stash, options.force),
+            None => Ok(()),
+        };
+        if let Err(detail) = checked.and_then(|()| apply_stash(dir, branch, stash)) {
+            warn!(
+                "Failed to apply stash to '{}'.\nOutput: {detail}",
+                dir.display()
+            );
+            roll_back_swap(plan, dest_stash, src_stash, &created, options)?;
+            return Err(format!(
+                "Stash could not be applied in '{}'. The swap was rolled back: '{}' is on '{}' and '{}' is on '{}' again, with their original changes restored.",
+                dir.display(),
+                plan.dest_dir.display(),
+                plan.dest_branch,
+                plan.src_dir.display(),
+                plan.src_branch
+            )
+            .into());
+        }
+    }
+    for stash in [src_stash, incoming].into_iter().flatten() {
+        finish_stash(stash, options);
+    }
+    Ok(())
+}
+
+/// Puts both worktrees back on their original branches with their original changes.
+/// Anything a (partially) applied stash left behind is discarded first: tracked changes via
+/// `reset --hard` and the untracked files listed in `created`, which did not exist before.
+fn roll_back_swap(
+    plan: &SwapPlan,
+    dest_stash: Option<&StashRecord>,
+    src_stash: Option<&StashRecord>,
+    created: &[PathBuf],
+    options: &SwapOptions,
+) -> Result<(), Box<dyn Error>> {
+    eprintln!("Rolling back the swap...");
+    journal::record("step", "restoring");
+    discard_partial_changes(plan, created)?;
+    restore_original_branches(plan, dest_stash, src_stash, options)
+}
+
+/// Resets both worktrees and removes the untracked files listed in `created`, undoing
+/// whatever a (partially) applied stash left behind.
+fn discard_partial_changes(plan: &SwapPlan, created: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+    for dir in [&plan.dest_dir, &plan.src_dir] {
+        debug!("Discarding partial changes in '{}'...", dir.display());
+        run_git_success(
+            Some(dir),
+            git_args!["reset", "--hard", "--quiet"],
+            "Failed to reset worktree during rollback.",
+        )?;
+    }
+    for path in created {
+        if path.is_file() || path.is_symlink() {
+            std::fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// The untracked files of `stash` that do not exist in `dir` yet, i.e. the ones applying it
+/// would create there.
+fn created_paths(dir: &Path, stash: &StashRecord) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    Ok(stash_untracked_paths(dir, stash)?
+        .into_iter()
+        .map(|path| dir.join(path))
+        .filter(|path| !path.exists() && !path.is_symlink())
+        .collect())
+}
+
+/// Switches each worktree back to its original branch, without detaching either first, and
+/// re-applies its own stash. Uncommitted changes that were not stashed yet stay in place.
+fn restore_original_branches(
+    plan: &SwapPlan,
+    dest_stash: Option<&StashRecord>,
+    src_stash: Option<&StashRecord>,
+    options: &SwapOptions,
+) -> Result<(), Box<dyn Error>> {
+    journal::record("step", "restoring");
+    take_branch(&plan.dest_dir, &plan.dest_branch, &[])?;
+    take_branch(&plan.src_dir, &plan.src_branch, &[])?;
+    for (dir, branch, stash) in [
+        (&plan.dest_dir, &plan.dest_branch, dest_stash),
+        (&plan.src_dir, &plan.src_branch, src_stash),
+    ] {
+        apply_stash(dir, branch, stash).map_err(|detail| {
+            format!(
+                "Rollback could not restore the original changes in '{}': {detail}\nThe stash has been kept.",
+                dir.display()
+            )
+        })?;
+    }
+    for stash in [dest_stash, src_stash].into_iter().flatten() {
+        finish_stash(stash, options);
+    }
+    journal::finish();
+    Ok(())
+}
+
+/// Remembers a stash in the journal so `recover` can restore it after a crash.
+fn record_stash(key: &str, stash: Option<&StashRecord>) {
+    if let Some(stash) = stash {
+        journal::record(key, &stash.hash);
+    }
+}
+
+/// Lists the untracked files recorded in `stash` (its third parent), if it has any.
+fn stash_untracked_paths(dir: &Path, stash: &StashRecord) -> Result<Vec<String>, Box<dyn Error>> {
+    let untracked_tree = format!("{}^3", stash.hash);
+    let has_untracked = run_git(
+        Some(dir),
+        git_args!["rev-parse", "--verify", "--quiet", &untracked_tree],
+    )?;
+    if !has_untracked.status.success() {
+        return Ok(Vec::new());
+    }
+    let listing = run_git_success(
+        Some(dir),
+        git_args!["ls-tree", "-r", "-z", "--name-only", &untracked_tree],
+        "Failed to list untracked files of stash.",
+    )?;
+    Ok(listing
+        .stdout
+        .split('\0')
+        .filter(|path| !path.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Finds the destination of profile `name`. Profiles come from the user config and from the
+/// repository config of the current directory, since the destination repository is not
+/// known yet.
+fn profile_destination(name: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let common_dir = env::current_dir()
+        .ok()
+        .and_then(|dir| git_common_dir(&dir).ok());
+    let config = Config::load(common_dir.as_deref())?;
+    config.profile(name)?.destination().ok_or_else(|| {
+        format!("Profile '{name}' has no destination; pass DESTINATION_WORKTREE_DIR as well.")
+            .into()
+    })
 }
 
 fn canonicalize_dir(path: impl AsRef<Path>) -> Result<PathBuf, Box<dyn Error>> {
@@ -192,7 +1657,7 @@ fn canonicalize_dir(path: impl AsRef<Path>) -> Result<PathBuf, Box<dyn Error>> {
     if !dir.is_dir() {
         return Err(format!("'{}' is not a directory.", dir.display()).into());
     }
-    Ok(dir.canonicalize()?)
+    Ok(paths::canonicalize(dir)?)
 }
 
 fn ensure_git_worktree(dir: &Path) -> Result<(), Box<dyn Error>> {
@@ -207,23 +1672,13 @@ fn ensure_git_worktree(dir: &Path) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-fn determine_repo_root(dir: &Path) -> Result<PathBuf, Box<dyn Error>> {
+fn git_common_dir(dir: &Path) -> Result<PathBuf, Box<dyn Error>> {
     let output = run_git_success(
         Some(dir),
         git_args!["rev-parse", "--git-common-dir"],
         "Failed to determine repository root.",
     )?;
-    let git_dir = PathBuf::from(output.stdout.trim());
-    let git_dir = if git_dir.is_absolute() {
-        git_dir
-    } else {
-        dir.join(git_dir)
-    };
-    let repo_root = git_dir
-        .parent()
-        .map(Path::to_path_buf)
-        .unwrap_or_else(|| dir.to_path_buf());
-    Ok(repo_root)
+    Ok(normalize_path(dir, output.stdout.trim()))
 }
 
 fn current_branch(dir: &Path) -> Result<String, Box<dyn Error>> {
@@ -239,70 +1694,220 @@ fn current_branch(dir: &Path) -> Result<String, Box<dyn Error>> {
     Ok(branch.to_string())
 }
 
-fn find_worktree_for_branch(dir: &Path, branch: &str) -> Result<PathBuf, Box<dyn Error>> {
+/// Locates the worktree hosting `branch`, honouring `--repair`, `--prune-stale` and
+/// `--create` when the branch has no usable worktree. Repairing and pruning update
+/// `snapshot` as well.
+fn resolve_source_worktree(
+    dest_dir: &Path,
+    snapshot: &mut RepoSnapshot,
+    branch: &str,
+    options: &SwapOptions,
+) -> Result<PathBuf, Box<dyn Error>> {
+    // Before pruning, which would drop the record of a worktree that was only moved.
+    repair::offer(dest_dir, snapshot, branch, options)?;
+    if options.prune_stale {
+        prune_stale_worktrees(dest_dir, &snapshot.worktrees)?;
+        snapshot.worktrees.retain(|entry| entry.prunable.is_none());
+    }
+    match snapshot.worktree_entry(branch) {
+        Some(entry) if options.create && entry.is_stale() => {
+            create_worktree(dest_dir, &entry.path, branch, true)
+        }
+        Some(entry) => checked_worktree_path(entry, branch),
+        None if options.create => {
+            let path = new_worktree_path(dest_dir, branch)?;
+            create_worktree(dest_dir, &path, branch, false)
+        }
+        None if !snapshot.local_branches.iter().any(|name| name == branch) => Err(format!(
+            "There is no branch named '{branch}'. Pass --create to check out a remote branch of that name in a new worktree."
+        )
+        .into()),
+        None => Err(format!(
+            "Could not find worktree for branch '{branch}'. Pass --create to check it out in a new worktree next to the destination."
+        )
+        .into()),
+    }
+}
+
+fn checked_worktree_path(entry: &WorktreeEntry, branch: &str) -> Result<PathBuf, Box<dyn Error>> {
+    if let Some(reason) = &entry.prunable {
+        let reason = if reason.is_empty() {
+            "no reason given"
+        } else {
+            reason.as_str()
+        };
+        return Err(format!(
+            "Source worktree '{}' (for branch '{branch}') is marked prunable by git: {reason}.\nNext steps:\n  --create       recreate the worktree at '{}' and swap with it\n  --prune-stale  drop stale worktree records before looking up the branch",
+            entry.path.display(),
+            entry.path.display()
+        )
+        .into());
+    }
+    if !entry.path.exists() {
+        return Err(format!(
+            "Source directory '{}' (for branch '{branch}') does not exist.",
+            entry.path.display()
+        )
+        .into());
+    }
+    Ok(entry.path.clone())
+}
+
+/// Refuses to touch the locked entries of `worktrees` among `dirs`, naming each lock and its
+/// reason. With
+/// `ignore_locks` the locks are only reported as warnings.
+fn ensure_unlocked(
+    worktrees: &[WorktreeEntry],
+    dirs: &[&Path],
+    ignore_locks: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut report = String::new();
+    for entry in worktrees {
+        let Some(reason) = &entry.locked else {
+            continue;
+        };
+        if !dirs.iter().any(|dir| paths::same_path(&entry.path, dir)) {
+            continue;
+        }
+        let reason = if reason.is_empty() {
+            "no reason given"
+        } else {
+            reason.as_str()
+        };
+        writeln!(&mut report, "  '{}': {reason}", entry.path.display()).ok();
+    }
+    if report.is_empty() {
+        return Ok(());
+    }
+    if ignore_locks {
+        warn!(
+            "Swapping locked worktrees because of --ignore-locks:\n{}",
+            report.trim_end()
+        );
+        return Ok(());
+    }
+    Err(format!(
+        "Refusing to swap locked worktrees:\n{}\nRun `git worktree unlock <path>` or pass --ignore-locks to swap anyway.",
+        report.trim_end()
+    )
+    .into())
+}
+
+fn prune_stale_worktrees(dir: &Path, worktrees: &[WorktreeEntry]) -> Result<(), Box<dyn Error>> {
+    for entry in worktrees {
+        if let Some(reason) = &entry.prunable {
+            warn!(
+                "Pruning stale worktree record '{}' ({reason}).",
+                entry.path.display()
+            );
+        }
+    }
+    run_git_success(
+        Some(dir),
+        git_args!["worktree", "prune"],
+        "Failed to prune stale worktrees.",
+    )?;
+    debug!("Pruned stale worktree records.");
+    Ok(())
+}
+
+fn new_worktree_path(dest_dir: &Path, branch: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let parent = dest_dir.parent().unwrap_or(dest_dir);
+    let path = parent.join(branch.replace('/', "-"));
+    if path.exists() {
+        return Err(format!(
+            "Cannot create a worktree for '{branch}': '{}' already exists.",
+            path.display()
+        )
+        .into());
+    }
+    Ok(path)
+}
+
+fn create_worktree(
+    dir: &Path,
+    path: &Path,
+    branch: &str,
+    force: bool,
+) -> Result<PathBuf, Box<dyn Error>> {
+    debug!(
+        "Creating worktree '{}' for branch '{branch}'...",
+        path.display()
+    );
+    let mut args = git_args!["worktree", "add"];
+    if force {
+        args.push(OsString::from("--force"));
+    }
+    args.push(path.as_os_str().to_os_string());
+    args.push(OsString::from(branch));
+    run_git_success(
+        Some(dir),
+        args,
+        "Failed to create worktree for source branch.",
+    )?;
+    Ok(paths::canonicalize(path)?)
+}
+
+fn list_worktrees(dir: &Path) -> Result<Vec<WorktreeEntry>, Box<dyn Error>> {
     let output = run_git_success(
         Some(dir),
         git_args!["worktree", "list", "--porcelain"],
         "Failed to list worktrees.",
     )?;
-    let mut worktree_path: Option<String> = None;
-    let mut branch_name: Option<String> = None;
-    for line in output.stdout.lines() {
-        if line.trim().is_empty() {
-            if branch_name
-                .as_deref()
-                .map(|name| name == branch)
-                .unwrap_or(false)
-            {
-                if let Some(path) = worktree_path {
-                    let path_buf = normalize_path(dir, &path);
-                    if !path_buf.exists() {
-                        return Err(format!(
-                            "Source directory '{}' (for branch '{branch}') does not exist.",
-                            path_buf.display()
-                        )
-                        .into());
-                    }
-                    return Ok(path_buf);
-                }
-            }
-            worktree_path = None;
-            branch_name = None;
-            continue;
-        }
+    Ok(parse_worktrees(dir, &output.stdout))
+}
 
+fn parse_worktrees(base: &Path, porcelain: &str) -> Vec<WorktreeEntry> {
+    let mut entries = Vec::new();
+    let mut current: Option<WorktreeEntry> = None;
+    for line in porcelain.lines() {
         if let Some(rest) = line.strip_prefix("worktree ") {
-            worktree_path = Some(rest.trim().to_string());
+            entries.extend(current.take());
+            current = Some(WorktreeEntry {
+                path: normalize_path(base, rest.trim()),
+                head: None,
+                branch: None,
+                prunable: None,
+                locked: None,
+            });
+            continue;
+        }
+        // The bare repository of a bare-plus-linked-worktrees layout is listed first, but
+        // has no working directory to swap.
+        if line == "bare" {
+            current = None;
+        }
+        let Some(entry) = current.as_mut() else {
+            continue;
+        };
+        if let Some(rest) = line.strip_prefix("HEAD ") {
+            entry.head = Some(rest.trim().to_string());
         } else if let Some(rest) = line.strip_prefix("branch ") {
             let trimmed = rest.trim();
-            branch_name = Some(
+            entry.branch = Some(
                 trimmed
                     .strip_prefix("refs/heads/")
                     .unwrap_or(trimmed)
                     .to_string(),
             );
+        } else if line == "prunable" {
+            entry.prunable = Some(String::new());
+        } else if let Some(rest) = line.strip_prefix("prunable ") {
+            entry.prunable = Some(rest.trim().to_string());
+        } else if line == "locked" {
+            entry.locked = Some(String::new());
+        } else if let Some(rest) = line.strip_prefix("locked ") {
+            entry.locked = Some(rest.trim().to_string());
         }
     }
+    entries.extend(current);
+    entries
+}
 
-    if branch_name
-        .as_deref()
-        .map(|name| name == branch)
-        .unwrap_or(false)
-    {
-        if let Some(path) = worktree_path {
-            let path_buf = normalize_path(dir, &path);
-            if !path_buf.exists() {
-                return Err(format!(
-                    "Source directory '{}' (for branch '{branch}') does not exist.",
-                    path_buf.display()
-                )
-                .into());
-            }
-            return Ok(path_buf);
-        }
-    }
-
-    Err(format!("Could not find worktree for branch '{branch}'.").into())
+/// Whether the porcelain lists a bare repository, whose linked worktrees are the only
+/// checkouts.
+fn lists_bare_repository(porcelain: &str) -> bool {
+    porcelain.lines().any(|line| line == "bare")
 }
 
 fn list_worktree_branches(dir: &Path) -> Result<Vec<String>, Box<dyn Error>> {
@@ -330,26 +1935,33 @@ fn parse_worktree_branches(porcelain: &str) -> Vec<String> {
     branches.into_iter().collect()
 }
 
-fn normalize_path(base: &Path, path: &str) -> PathBuf {
-    let candidate = PathBuf::from(path);
-    if candidate.is_absolute() {
-        candidate
-    } else {
-        base.join(candidate)
-    }
-}
-
 fn stash_worktree(
     dir: &Path,
     branch: &str,
-    logger: &Logger,
+    options: &SwapOptions,
 ) -> Result<Option<StashRecord>, Box<dyn Error>> {
-    debug_log!(logger, "Stashing '{}' (Branch: {branch})...", dir.display());
-    let message = format!("swap-stash-{branch}");
-    let output = run_git(Some(dir), git_args!["stash", "push", "-u", "-m", &message])?;
+    if options.no_stash {
+        return Ok(None);
+    }
+    debug!("Stashing '{}' (Branch: {branch})...", dir.display());
+    let message = status::stash_message(&options.stash_message, branch, dir);
+    let mut args = git_args!["stash", "push", "-m", &message];
+    if options.include_ignored {
+        warn_ignored_payload(dir)?;
+        args.push(OsString::from("-a"));
+    } else if !options.ignore_untracked {
+        args.push(OsString::from("-u"));
+    }
+    if let Some(git_dir) = nested_git_dir(dir) {
+        // Stashing untracked files would take the repository itself along.
+        let mut exclude = OsString::from(":(exclude,top)");
+        exclude.push(git_dir);
+        args.extend(git_args!["--", ":/", exclude]);
+    }
+    let output = run_git_retrying(Some(dir), args, &[])?;
     let combined = combined_output(&output);
     if combined.trim() == "No local changes to save" {
-        debug_log!(logger, "No changes to stash in '{}'.", dir.display());
+        debug!("No changes to stash in '{}'.", dir.display());
         return Ok(None);
     }
     if !output.status.success() {
@@ -361,126 +1973,626 @@ fn stash_worktree(
         .into());
     }
 
-    let rev = run_git_success(
+    let hash = find_pushed_stash(dir, &message)?;
+    debug!(
+        event = "stash",
+        worktree = dir.to_str(),
+        branch,
+        hash,
+        "Stashed changes from '{}' as {hash}.",
+        dir.display()
+    );
+    Ok(Some(StashRecord {
+        hash,
+        branch: branch.to_string(),
+        origin: dir.to_path_buf(),
+    }))
+}
+
+/// Stashes two worktrees at the same time. Each has its own index, and git serializes the
+/// updates of the stash ref they share, so only the slow part, writing the stash, overlaps.
+fn stash_in_parallel(
+    worktrees: [(&Path, &str); 2],
+    options: &SwapOptions,
+) -> [Result<Option<StashRecord>, Box<dyn Error>>; 2] {
+    let span = tracing::Span::current();
+    thread::scope(|scope| {
+        let handles = worktrees.map(|(dir, branch)| {
+            let span = span.clone();
+            scope.spawn(move || {
+                let _span = span.enter();
+                stash_worktree(dir, branch, options).map_err(|err| err.to_string())
+            })
+        });
+        handles.map(|handle| match handle.join() {
+            Ok(result) => result.map_err(Into::into),
+            Err(_) => Err("Stashing crashed.".into()),
+        })
+    })
+}
+
+/// Finds the stash that `stash push -m <message>` just created in `dir`. Another worktree
+/// may push its own stash at the same time, so `stash@{0}` is not necessarily ours.
+fn find_pushed_stash(dir: &Path, message: &str) -> Result<String, Box<dyn Error>> {
+    let output = run_git_success(
         Some(dir),
-        git_args!["rev-parse", "stash@{0}"],
+        git_args!["stash", "list", "--format=%H%x00%gs"],
         "Failed to determine stash SHA.",
     )?;
-    let hash = rev.stdout.trim().to_string();
-    debug_log!(
-        logger,
-        "Stashed changes from '{}' as {hash}.",
+    newest_stash_with_message(&output.stdout, message)
+        .map(str::to_string)
+        .ok_or_else(|| {
+            format!(
+                "Failed to find the stash just created in '{}'.",
+                dir.display()
+            )
+            .into()
+        })
+}
+
+/// Picks the newest entry of `stash list --format=%H%x00%gs` whose message is `message`.
+fn newest_stash_with_message<'a>(list: &'a str, message: &str) -> Option<&'a str> {
+    list.lines().find_map(|line| {
+        let (hash, subject) = line.split_once('\0')?;
+        // Subjects read "On <branch>: <message>"; branch names cannot contain ':'.
+        (subject.split_once(": ")?.1 == message).then_some(hash)
+    })
+}
+
+/// Checks what the stashes would carry against `max_stash_size`, then shows which branches
+/// are about to trade places, what gets stashed on each side and the `notes` on the plan,
+/// and asks before anything changes. The question is skipped with `--yes` or `confirm = false`, and whenever nobody
+/// could answer: without a terminal on stdin, or with NDJSON output for a frontend.
+fn confirm_swap(
+    plan: &SwapPlan,
+    options: &SwapOptions,
+    notes: &[String],
+) -> Result<(), Box<dyn Error>> {
+    let asking = !options.yes && !logging::ndjson() && io::stdin().is_terminal();
+    if !(asking || options.max_stash_size.is_some()) {
+        return Ok(());
+    }
+    let dest = display_relative(&plan.dest_dir);
+    let src = display_relative(&plan.src_dir);
+    // With --no-stash both worktrees were already checked to be clean, so there is nothing
+    // to preview or measure; the swap is still confirmed.
+    let (dest_preview, src_preview) = if options.no_stash {
+        (StashPreview::default(), StashPreview::default())
+    } else {
+        let preview = |dir: &Path| {
+            StashPreview::read(dir, !options.ignore_untracked, options.include_ignored)
+        };
+        let dest_preview = preview(&plan.dest_dir)?;
+        let src_preview = preview(&plan.src_dir)?;
+        preview::ensure_within_limit(
+            &[(&dest, &dest_preview), (&src, &src_preview)],
+            options.max_stash_size,
+            options.force,
+        )?;
+        (dest_preview, src_preview)
+    };
+    if !asking {
+        return Ok(());
+    }
+    eprintln!(
+        "{}",
+        swap_summary(
+            (&dest, &plan.dest_branch, dest_preview.files),
+            (&src, &plan.src_branch, src_preview.files),
+        )
+    );
+    eprintln!("  stash: {}", options.stash_scope());
+    for (dir, preview) in [(&dest, &dest_preview), (&src, &src_preview)] {
+        if preview.files > 0 {
+            eprintln!("  {dir}: {}", preview.describe());
+        }
+    }
+    if options.ignore_untracked && !options.no_stash {
+        for (label, dir) in [(&dest, &plan.dest_dir), (&src, &plan.src_dir)] {
+            let staying = StashPreview::read(dir, true, false)?.untracked;
+            if staying > 0 {
+                eprintln!("  {label}: keeps {}", untracked_files(staying));
+            }
+        }
+    }
+    if options.detach_source {
+        eprintln!(
+            "  {src} will be left detached; changes from {} stay in the stash list.",
+            plan.dest_branch
+        );
+    }
+    for note in notes {
+        eprintln!("  {note}");
+    }
+    if confirm("Swap?") {
+        Ok(())
+    } else {
+        Err("Swap cancelled; nothing was changed. Pass --yes to swap without asking.".into())
+    }
+}
+
+/// "1 untracked file" or "12 untracked files".
+fn untracked_files(count: usize) -> String {
+    match count {
+        1 => "1 untracked file".to_string(),
+        count => format!("{count} untracked files"),
+    }
+}
+
+/// "<dest> main ⇄ feature/x <src>", followed by how many dirty files each side stashes.
+fn swap_summary(dest: (&str, &str, usize), src: (&str, &str, usize)) -> String {
+    let files = |count: usize| match count {
+        1 => "1 dirty file".to_string(),
+        count => format!("{count} dirty files"),
+    };
+    let stashed = match (dest.2, src.2) {
+        (0, 0) => "both worktrees are clean".to_string(),
+        (count, 0) => format!("{} will be stashed in {}", files(count), dest.0),
+        (0, count) => format!("{} will be stashed in {}", files(count), src.0),
+        (dest_count, src_count) if dest_count == src_count => {
+            format!("{} will be stashed on each side", files(dest_count))
+        }
+        (dest_count, src_count) => format!(
+            "{} will be stashed in {} and {} in {}",
+            files(dest_count),
+            dest.0,
+            files(src_count),
+            src.0
+        ),
+    };
+    format!("{} {} ⇄ {} {}; {stashed}", dest.0, dest.1, src.1, src.0)
+}
+
+/// Fails with a per-worktree report of uncommitted changes when any of `worktrees` is dirty.
+fn ensure_clean_worktrees(worktrees: &[(&Path, &str)]) -> Result<(), Box<dyn Error>> {
+    let mut report = String::new();
+    for (dir, branch) in worktrees {
+        let output = run_git_success(
+            Some(dir),
+            git_args!["status", "--porcelain"],
+            "Failed to read worktree status.",
+        )?;
+        if output.stdout.trim().is_empty() {
+            continue;
+        }
+        writeln!(&mut report, "'{}' ({branch}):", dir.display()).ok();
+        for line in output.stdout.lines() {
+            writeln!(&mut report, "  {line}").ok();
+        }
+    }
+    if report.is_empty() {
+        return Ok(());
+    }
+    Err(format!(
+        "Refusing to swap with --no-stash because of uncommitted changes:\n{}",
+        report.trim_end()
+    )
+    .into())
+}
+
+/// Stops before stashing while swap stashes of either branch of `plan` are still in the
+/// stash list, as a failed or interrupted swap leaves them: stashing on top of them makes it
+/// hard to tell which changes belong where. Only warns with `--force`, and with
+/// `--keep-stash`, which leaves such stashes behind on purpose.
+fn ensure_no_leftover_stashes(
+    plan: &SwapPlan,
+    options: &SwapOptions,
+) -> Result<(), Box<dyn Error>> {
+    if options.no_stash {
+        return Ok(());
+    }
+    let leftovers: Vec<String> = status::swap_stashes(&plan.dest_dir)?
+        .iter()
+        .filter(|stash| [&plan.dest_branch, &plan.src_branch].contains(&&stash.branch))
+        .map(|stash| format!("  {}", stash.describe()))
+        .collect();
+    if leftovers.is_empty() {
+        return Ok(());
+    }
+    let listing = leftovers.join("\n");
+    if options.force || options.keep_stash {
+        warn!("Swap stashes from an earlier run are still in the stash list:\n{listing}");
+        return Ok(());
+    }
+    Err(format!(
+        "Swap stashes from an earlier run are still in the stash list; nothing was changed:\n{listing}\nRun `swap-worktree recover` if a swap was interrupted, or apply them and run `swap-worktree clean`. Pass --force to swap anyway."
+    )
+    .into())
+}
+
+fn warn_ignored_payload(dir: &Path) -> Result<(), Box<dyn Error>> {
+    let output = run_git_success(
+        Some(dir),
+        git_args![
+            "ls-files",
+            "--others",
+            "--ignored",
+            "--exclude-standard",
+            "--directory"
+        ],
+        "Failed to list ignored files.",
+    )?;
+    let count = output.stdout.lines().count();
+    if count > 0 {
+        warn!(
+            "--include-ignored will stash {count} ignored path(s) from '{}'. Large build outputs or dependency folders can make the swap slow and bloat the object store.",
+            dir.display()
+        );
+    }
+    Ok(())
+}
+
+fn find_stash_reference(dir: &Path, hash: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let output = run_git_success(
+        Some(dir),
+        git_args!["stash", "list", "--format=%H:%gd"],
+        "Failed to list stashes.",
+    )?;
+    for line in output.stdout.lines() {
+        if let Some((commit, reference)) = line.split_once(':') {
+            if commit == hash {
+                return Ok(Some(reference.trim().to_string()));
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn detach_worktree(dir: &Path, branch: &str) -> Result<(), Box<dyn Error>> {
+    debug!(
+        "Detaching HEAD in '{}' (freeing {branch})...",
+        dir.display()
+    );
+    let mut args = hook_override(false);
+    args.extend(git_args!["switch", "--detach"]);
+    run_git_success(Some(dir), args, "Failed to detach worktree.")?;
+    Ok(())
+}
+
+/// Switches `dir` to `branch` even while another worktree still has it checked out. That
+/// worktree has to move off it next; until then both are on the same branch.
+fn take_branch(dir: &Path, branch: &str, env: &[(&str, &str)]) -> Result<(), Box<dyn Error>> {
+    debug!(
+        "Switching '{}' -> to '{branch}' (ignoring other worktrees)...",
+        dir.display()
+    );
+    let mut args = hook_override(true);
+    args.extend(git_args!["switch", "--ignore-other-worktrees", branch]);
+    run_git_success_with_env(Some(dir), args, env, "Failed to switch worktree branch.")
+        .map_err(partial::explain)?;
+    Ok(())
+}
+
+/// Checks out a branch with `git switch`, such as [`take_branch`] and [`switch_worktree`].
+type SwitchBranch = fn(&Path, &str, &[(&str, &str)]) -> Result<(), Box<dyn Error>>;
+
+/// Checks out `branch` in `dir` for a forward step of the swap: with plumbing when it can,
+/// see [`checkout_with_plumbing`], and with `switch` otherwise.
+fn switch_fast(
+    dir: &Path,
+    branch: &str,
+    env: &[(&str, &str)],
+    switch: SwitchBranch,
+) -> Result<(), Box<dyn Error>> {
+    if checkout_with_plumbing(dir, branch, env)? {
+        return Ok(());
+    }
+    switch(dir, branch, env)
+}
+
+/// Moves `dir` onto `branch` without the checkout machinery of `git switch`: a two-way
+/// `read-tree -m -u` from HEAD updates the index and the files, then `symbolic-ref` points
+/// this worktree's HEAD at the branch, and `post-checkout` runs as it would after a switch.
+/// read-tree refuses to overwrite local changes and untracked files just like a switch;
+/// when it refuses, nothing has changed and `false` tells the caller to fall back to
+/// `git switch`, which explains why.
+fn checkout_with_plumbing(
+    dir: &Path,
+    branch: &str,
+    env: &[(&str, &str)],
+) -> Result<bool, Box<dyn Error>> {
+    let target = format!("refs/heads/{branch}");
+    let heads = run_git(
+        Some(dir),
+        git_args![
+            "rev-parse",
+            "HEAD",
+            format!("{target}^{{commit}}"),
+            "--abbrev-ref",
+            "HEAD"
+        ],
+    )?;
+    let [old, new, old_name] = heads.stdout.lines().collect::<Vec<_>>()[..] else {
+        return Ok(false);
+    };
+    if !heads.status.success() {
+        return Ok(false);
+    }
+    debug!(
+        "Checking out '{branch}' in '{}' with read-tree...",
+        dir.display()
+    );
+    let read = run_git_with_env(Some(dir), git_args!["read-tree", "-m", "-u", old, new], env)?;
+    if !read.status.success() {
+        debug!(
+            "read-tree refused ({}); falling back to git switch.",
+            read.stderr.trim()
+        );
+        return Ok(false);
+    }
+    let from = if old_name == "HEAD" { old } else { old_name };
+    let pointed = run_git_success(
+        Some(dir),
+        git_args![
+            "symbolic-ref",
+            "-m",
+            format!("checkout: moving from {from} to {branch}"),
+            "HEAD",
+            &target
+        ],
+        "Failed to point HEAD at the new branch.",
+    );
+    if let Err(err) = pointed {
+        // Put the files back so that HEAD and the working tree agree again.
+        let _ = run_git(Some(dir), git_args!["read-tree", "-m", "-u", new, old]);
+        return Err(err);
+    }
+    let mut args = hook_override(true);
+    args.extend(git_args![
+        "hook",
+        "run",
+        "--ignore-missing",
+        "post-checkout",
+        "--",
+        old,
+        new,
+        "1"
+    ]);
+    run_git_success_with_env(Some(dir), args, env, "The post-checkout hook failed.")?;
+    Ok(true)
+}
+
+/// Moves the source worktree off the branch the destination has just taken: onto the
+/// destination's old branch, or to a detached HEAD with `--detach-source`.
+fn leave_source_branch(plan: &SwapPlan, options: &SwapOptions) -> Result<(), Box<dyn Error>> {
+    if options.detach_source {
+        debug!(
+            "Leaving '{}' detached (--detach-source).",
+            plan.src_dir.display()
+        );
+        detach_worktree(&plan.src_dir, &plan.src_branch)
+    } else {
+        switch_fast(
+            &plan.src_dir,
+            &plan.dest_branch,
+            &options.switch_env(),
+            switch_worktree,
+        )
+    }
+}
+
+/// Switches `dir` to `branch` with the extra variables in `env`, see
+/// [`SwapOptions::switch_env`].
+fn switch_worktree(dir: &Path, branch: &str, env: &[(&str, &str)]) -> Result<(), Box<dyn Error>> {
+    debug!("Switching '{}' -> to '{branch}'...", dir.display());
+    let mut args = hook_override(true);
+    args.extend(git_args!["switch", branch]);
+    run_git_success_with_env(Some(dir), args, env, "Failed to switch worktree branch.")
+        .map_err(partial::explain)?;
+    Ok(())
+}
+
+/// `-c core.hooksPath=<nowhere>` when the `--git-hooks` policy skips the hooks of this
+/// checkout; `final_switch` tells the switches to the new branches from the detaches.
+fn hook_override(final_switch: bool) -> Vec<OsString> {
+    let policy = GIT_HOOKS.lock().map(|hooks| *hooks).unwrap_or_default();
+    let skip = match policy {
+        GitHooks::All => false,
+        GitHooks::Final => !final_switch,
+        GitHooks::None => true,
+    };
+    if !skip {
+        return Vec::new();
+    }
+    let nowhere = if cfg!(windows) { "NUL" } else { "/dev/null" };
+    git_args!["-c", format!("core.hooksPath={nowhere}")]
+}
+
+fn apply_and_drop_stash(
+    dir: &Path,
+    branch: &str,
+    stash: Option<&StashRecord>,
+    options: &SwapOptions,
+) {
+    let applied = apply_incoming_stash(dir, branch, stash, options);
+    if let Some(stash) = stash.filter(|_| applied) {
+        finish_stash(stash, options);
+    }
+}
+
+/// Applies `stash` to `dir`, offering the mergetool on conflicts, and returns whether it is
+/// fully applied and can be dropped. A stash that is not is kept and reported.
+fn apply_incoming_stash(
+    dir: &Path,
+    branch: &str,
+    stash: Option<&StashRecord>,
+    options: &SwapOptions,
+) -> bool {
+    let checked = match stash {
+        Some(stash) => case_collisions::check(dir, stash, options.force),
+        None => Ok(()),
+    };
+    let Err(detail) = checked.and_then(|()| apply_stash(dir, branch, stash)) else {
+        return true;
+    };
+    let hash = stash.map(|stash| stash.hash.as_str()).unwrap_or_default();
+    warn!(
+        event = "conflict",
+        worktree = dir.to_str(),
+        hash,
+        "Failed to apply stash {hash} to '{}'.\nOutput: {detail}",
+        dir.display()
+    );
+    if stash.is_some() && resolve_with_mergetool(dir, options) {
+        eprintln!("All conflicts in '{}' are resolved.", dir.display());
+        return true;
+    }
+    eprintln!(
+        "The stash has been kept. Please resolve manually in '{}'.",
+        dir.display()
+    );
+    false
+}
+
+/// Offers `git mergetool` for the conflicts a failed apply left in `dir`, either because
+/// `--mergetool` was given or after asking on an interactive terminal. Returns `true` once
+/// no unmerged paths remain.
+fn resolve_with_mergetool(dir: &Path, options: &SwapOptions) -> bool {
+    let conflicted = match unmerged_paths(dir) {
+        Ok(paths) if !paths.is_empty() => paths,
+        _ => return false,
+    };
+    eprintln!(
+        "Conflicted paths in '{}':\n  {}",
+        dir.display(),
+        conflicted.join("\n  ")
+    );
+    if !options.mergetool && !confirm(&format!("Run git mergetool in '{}' now?", dir.display())) {
+        return false;
+    }
+    let status = git_command_in(dir).arg("mergetool").status();
+    if let Err(err) = status {
+        warn!("Failed to run git mergetool: {err}");
+        return false;
+    }
+    matches!(unmerged_paths(dir), Ok(paths) if paths.is_empty())
+}
+
+fn unmerged_paths(dir: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let output = run_git_success(
+        Some(dir),
+        git_args!["diff", "--name-only", "--diff-filter=U"],
+        "Failed to list conflicted paths.",
+    )?;
+    Ok(output.stdout.lines().map(str::to_string).collect())
+}
+
+/// Asks a yes/no question on stderr. Without an interactive terminal the answer is "no".
+fn confirm(question: &str) -> bool {
+    if !io::stdin().is_terminal() {
+        return false;
+    }
+    eprint!("{question} [y/N] ");
+    let _ = io::stderr().flush();
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim(), "y" | "Y" | "yes" | "Yes")
+}
+
+/// Applies `stash` (if any) to `dir`, returning git's output when the apply fails.
+fn apply_stash(dir: &Path, branch: &str, stash: Option<&StashRecord>) -> Result<(), String> {
+    let Some(stash) = stash else {
+        debug!("No stash from '{branch}' to apply to '{}'.", dir.display());
+        return Ok(());
+    };
+    debug!(
+        "Applying stash {} (from {}) to '{}'...",
+        stash.hash,
+        stash.branch,
         dir.display()
     );
-    let reference = find_stash_reference(dir, &hash)?;
-    Ok(Some(StashRecord {
-        hash,
-        reference,
-        branch: branch.to_string(),
-    }))
+    match run_git(Some(dir), git_args!["stash", "apply", &stash.hash]) {
+        Ok(output) if output.status.success() => {
+            debug!(
+                event = "stash-applied",
+                worktree = dir.to_str(),
+                hash = stash.hash,
+                "Successfully applied stash."
+            );
+            journal::record("applied", &stash.hash);
+            Ok(())
+        }
+        Ok(output) => Err(combined_output(&output)),
+        Err(err) => Err(err.to_string()),
+    }
 }
 
-fn find_stash_reference(dir: &Path, hash: &str) -> Result<Option<String>, Box<dyn Error>> {
-    let output = run_git_success(
-        Some(dir),
-        git_args!["stash", "list", "--format=%H:%gd"],
-        "Failed to list stashes.",
-    )?;
-    for line in output.stdout.lines() {
-        if let Some((commit, reference)) = line.split_once(':') {
-            if commit == hash {
-                return Ok(Some(reference.trim().to_string()));
-            }
+/// Drops an applied stash from the stash list it was created in, unless `--keep-stash`.
+fn finish_stash(stash: &StashRecord, options: &SwapOptions) {
+    if options.keep_stash {
+        debug!("Keeping stash {} (--keep-stash).", stash.hash);
+    } else if let Ok(Some(reference)) = find_stash_reference(&stash.origin, &stash.hash) {
+        if let Err(err) = drop_stash(&stash.origin, &reference) {
+            warn!("Failed to drop applied stash {reference}: {err}");
         }
+    } else {
+        warn!(
+            "Could not determine stash reference for {}. The stash remains in the list.",
+            stash.hash
+        );
     }
-    Ok(None)
 }
 
-fn detach_worktree(dir: &Path, branch: &str, logger: &Logger) -> Result<(), Box<dyn Error>> {
-    debug_log!(
+/// Tells where the destination's changes went with `--detach-source`: its branch is no
+/// longer checked out anywhere, so its stash stays in the list until someone pops it.
+fn report_parked_stash(plan: &SwapPlan, stashes: &[StashRecord], logger: &Logger) {
+    let Some(stash) = stashes
+        .iter()
+        .find(|stash| stash.branch == plan.dest_branch)
+    else {
+        return;
+    };
+    let reference = find_stash_reference(&stash.origin, &stash.hash)
+        .ok()
+        .flatten()
+        .unwrap_or_else(|| stash.hash.clone());
+    info!(
+        event = "stash-kept",
+        branch = plan.dest_branch,
+        hash = stash.hash,
+        "Changes from '{}' were kept in {reference}.",
+        plan.dest_branch
+    );
+    info_log!(
         logger,
-        "Detaching HEAD in '{}' (freeing {branch})...",
-        dir.display()
+        "Changes from '{}' were kept in {reference}; restore them with `git switch {} && git stash pop {reference}`.",
+        plan.dest_branch,
+        plan.dest_branch
     );
-    run_git_success(
-        Some(dir),
-        git_args!["switch", "--detach"],
-        "Failed to detach worktree.",
-    )?;
-    Ok(())
-}
-
-fn switch_worktree(dir: &Path, branch: &str, logger: &Logger) -> Result<(), Box<dyn Error>> {
-    debug_log!(logger, "Switching '{}' -> to '{branch}'...", dir.display());
-    run_git_success(
-        Some(dir),
-        git_args!["switch", branch],
-        "Failed to switch worktree branch.",
-    )?;
-    Ok(())
 }
 
-fn apply_and_drop_stash(dir: &Path, branch: &str, stash: Option<&StashRecord>, logger: &Logger) {
-    if let Some(stash) = stash {
-        debug_log!(
-            logger,
-            "Applying stash {} (from {}) to '{}'...",
-            stash.hash,
-            stash.branch,
-            dir.display()
-        );
-        let result = run_git(Some(dir), git_args!["stash", "apply", &stash.hash]);
-        match result {
-            Ok(output) if output.status.success() => {
-                debug_log!(logger, "Successfully applied stash.");
-                if let Some(reference) = &stash.reference {
-                    if let Err(err) = drop_stash(dir, reference, logger) {
-                        eprintln!("Warning: Failed to drop applied stash {reference}: {err}");
-                    }
-                } else {
-                    eprintln!(
-                        "Warning: Could not determine stash reference for {}. The stash remains in the list.",
-                        stash.hash
-                    );
-                }
-            }
-            Ok(output) => {
-                eprintln!(
-                    "Warning: Failed to apply stash {} to '{}'.\nOutput: {}",
-                    stash.hash,
-                    dir.display(),
-                    combined_output(&output)
-                );
-                eprintln!(
-                    "The stash has been kept. Please resolve manually in '{}'.",
-                    dir.display()
-                );
-            }
-            Err(err) => {
-                eprintln!(
-                    "Warning: Failed to apply stash {} to '{}': {err}",
-                    stash.hash,
-                    dir.display()
-                );
-                eprintln!(
-                    "The stash has been kept. Please resolve manually in '{}'.",
-                    dir.display()
-                );
-            }
+fn report_kept_stashes(stashes: &[StashRecord], logger: &Logger) {
+    if stashes.is_empty() || !logger.shows(Verbosity::Normal) {
+        return;
+    }
+    println!(
+        "{}",
+        style::title("Kept stashes (drop them once you have verified the result):")
+    );
+    for stash in stashes {
+        match find_stash_reference(&stash.origin, &stash.hash) {
+            Ok(Some(reference)) => println!(
+                "  {reference} from '{}' ({}): git -C '{}' stash drop {reference}",
+                stash.branch,
+                stash.hash,
+                stash.origin.display()
+            ),
+            _ => println!(
+                "  {} from '{}' (no longer in the stash list of '{}')",
+                stash.hash,
+                stash.branch,
+                stash.origin.display()
+            ),
         }
-    } else {
-        debug_log!(
-            logger,
-            "No stash from '{branch}' to apply to '{}'.",
-            dir.display()
-        );
     }
 }
 
-fn drop_stash(dir: &Path, reference: &str, logger: &Logger) -> Result<(), Box<dyn Error>> {
+fn drop_stash(dir: &Path, reference: &str) -> Result<(), Box<dyn Error>> {
     let output = run_git(Some(dir), git_args!["stash", "drop", reference])?;
     if output.status.success() {
-        debug_log!(logger, "Dropped stash {reference}.");
+        debug!("Dropped stash {reference}.");
         Ok(())
     } else {
         Err(format!(
@@ -506,18 +2618,227 @@ fn combined_output(output: &GitOutput) -> String {
 }
 
 fn run_git(dir: Option<&Path>, args: Vec<OsString>) -> Result<GitOutput, Box<dyn Error>> {
+    run_git_with_env(dir, args, &[])
+}
+
+/// Runs git like [`run_git`], with the variables in `env` added to this command only.
+fn run_git_with_env(
+    dir: Option<&Path>,
+    args: Vec<OsString>,
+    env: &[(&str, &str)],
+) -> Result<GitOutput, Box<dyn Error>> {
     let command = describe_args(&args);
-    let mut cmd = Command::new("git");
-    if let Some(dir) = dir {
-        cmd.arg("-C").arg(dir);
+    let mut cmd = match dir {
+        Some(dir) => git_command_in(dir),
+        None => git_command(),
+    };
+    // Keep git out of the terminal's process group so Ctrl-C reaches only this process and
+    // the running command can finish before the swap unwinds. Commands that may ask on the
+    // terminal stay in its group: reading from it in the background would stop them.
+    #[cfg(unix)]
+    if !may_prompt(&args) {
+        std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+    }
+    cmd.args(&args).envs(env.iter().copied());
+    let timeout = GIT_TIMEOUT.lock().ok().and_then(|timeout| *timeout);
+    let started = Instant::now();
+    let program = cmd.get_program().to_os_string();
+    let output = match timeout {
+        Some(timeout) => output_with_timeout(cmd, timeout),
+        None => cmd.output().map(Some),
     }
-    let output = cmd.args(&args).output()?;
-    Ok(GitOutput {
+    .map_err(|err| format!("Failed to run '{}': {err}", program.to_string_lossy()))?;
+    let elapsed = started.elapsed();
+    record_timing(dir, &args, elapsed);
+    let location = dir
+        .map(|dir| format!(" in '{}'", dir.display()))
+        .unwrap_or_default();
+    let Some(output) = output else {
+        trace!(
+            "git {command}{location}: killed after {}",
+            format_duration(elapsed)
+        );
+        return Err(format!(
+            "Step '{}' timed out after {}{location}; the git process was killed.\nCommand: git {command}",
+            step_label(&args),
+            format_duration(timeout.unwrap_or_default())
+        )
+        .into());
+    };
+    let output = GitOutput {
         stdout: String::from_utf8_lossy(&output.stdout).to_string(),
         stderr: String::from_utf8_lossy(&output.stderr).to_string(),
         status: output.status,
         command,
-    })
+    };
+    trace!(
+        event = "git",
+        worktree = dir.and_then(Path::to_str),
+        command = output.command,
+        status = output.status.code(),
+        stdout = output.stdout.trim_end(),
+        stderr = output.stderr.trim_end(),
+        "git {}{location}: {} after {elapsed:?}",
+        output.command,
+        output.status
+    );
+    Ok(output)
+}
+
+/// A `git` command using the configured executable, with the repository-selecting
+/// variables of [`REPOSITORY_ENV_VARS`] removed from its environment.
+fn git_command() -> Command {
+    let program = GIT_PROGRAM
+        .lock()
+        .ok()
+        .and_then(|program| program.clone())
+        .or_else(|| {
+            env::var_os("SWAP_WORKTREE_GIT")
+                .filter(|value| !value.is_empty())
+                .map(PathBuf::from)
+        })
+        .unwrap_or_else(|| PathBuf::from("git"));
+    let mut cmd = Command::new(program);
+    for name in REPOSITORY_ENV_VARS {
+        cmd.env_remove(name);
+    }
+    cmd
+}
+
+/// The `--git-dir` repository relative to its `--work-tree`, when git run in `dir` works on
+/// that work tree and the repository sits inside it, as bare dotfiles repositories do.
+fn nested_git_dir(dir: &Path) -> Option<PathBuf> {
+    let repository = REPOSITORY_OVERRIDE.lock().ok()?;
+    let repository = repository
+        .as_ref()
+        .filter(|repository| repository.covers(dir))?;
+    let relative = repository
+        .git_dir
+        .strip_prefix(&repository.work_tree)
+        .ok()?;
+    Some(relative.to_path_buf())
+}
+
+/// A `git -C <dir>` command. Inside the work tree of `--work-tree`, it gets `GIT_DIR` and
+/// `GIT_WORK_TREE` from the flags.
+fn git_command_in(dir: &Path) -> Command {
+    let mut cmd = git_command();
+    cmd.arg("-C").arg(dir);
+    if let Ok(repository) = REPOSITORY_OVERRIDE.lock() {
+        if let Some(repository) = repository
+            .as_ref()
+            .filter(|repository| repository.covers(dir))
+        {
+            cmd.env("GIT_DIR", &repository.git_dir)
+                .env("GIT_WORK_TREE", &repository.work_tree);
+        }
+    }
+    cmd
+}
+
+/// Runs `cmd` to completion like [`Command::output`], or kills it and returns `None` once
+/// `timeout` has passed. The pipes are drained on separate threads so a chatty command
+/// cannot block on a full pipe while we wait.
+fn output_with_timeout(
+    mut cmd: Command,
+    timeout: Duration,
+) -> io::Result<Option<std::process::Output>> {
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let drain = |pipe: Option<Box<dyn io::Read + Send>>| {
+        std::thread::spawn(move || {
+            let mut buffer = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut buffer);
+            }
+            buffer
+        })
+    };
+    let stdout = drain(child.stdout.take().map(|pipe| Box::new(pipe) as _));
+    let stderr = drain(child.stderr.take().map(|pipe| Box::new(pipe) as _));
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            // Helpers spawned by git may still hold the pipes open, so the reader threads
+            // are left to finish on their own.
+            return Ok(None);
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    };
+    Ok(Some(std::process::Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    }))
+}
+
+fn configure_lock_retry(cli: &Cli, config: &Config) {
+    if let Ok(mut retry) = LOCK_RETRY.lock() {
+        if let Some(attempts) = cli.lock_retries.or(config.lock_retries) {
+            retry.attempts = attempts;
+        }
+        if let Some(delay) = cli.lock_retry_delay.or(config.lock_retry_delay_ms) {
+            retry.delay = Duration::from_millis(delay);
+        }
+    }
+}
+
+/// Runs git like [`run_git`], but retries with exponential backoff while the command fails
+/// because another process holds `index.lock`.
+fn run_git_retrying(
+    dir: Option<&Path>,
+    args: Vec<OsString>,
+    env: &[(&str, &str)],
+) -> Result<GitOutput, Box<dyn Error>> {
+    let retry = LOCK_RETRY.lock().map(|retry| *retry).unwrap_or(LockRetry {
+        attempts: 0,
+        delay: Duration::ZERO,
+    });
+    let mut delay = retry.delay;
+    for _ in 0..retry.attempts {
+        let output = run_git_with_env(dir, args.clone(), env)?;
+        if output.status.success()
+            || !(is_index_lock_contention(&output.stderr)
+                || (output.stderr.trim().is_empty() && index_lock_held(dir)))
+        {
+            return Ok(output);
+        }
+        warn!(
+            "index.lock is held by another process (git {}); retrying in {}ms...",
+            output.command,
+            delay.as_millis()
+        );
+        std::thread::sleep(delay);
+        delay *= 2;
+    }
+    run_git_with_env(dir, args, env)
+}
+
+/// Checks for the lock file directly, for commands that fail without saying why. `stash push`
+/// does so when it cannot take the lock.
+fn index_lock_held(dir: Option<&Path>) -> bool {
+    let Ok(output) = run_git(dir, git_args!["rev-parse", "--git-path", "index.lock"]) else {
+        return false;
+    };
+    let path = output.stdout.trim();
+    output.status.success()
+        && dir
+            .map_or_else(|| PathBuf::from(path), |dir| dir.join(path))
+            .exists()
+}
+
+/// Recognizes git's "Unable to create '.../index.lock': File exists." failure. Other
+/// failures to create the lock, such as a read-only repository, are not worth retrying.
+fn is_index_lock_contention(stderr: &str) -> bool {
+    stderr.contains("index.lock': File exists")
 }
 
 fn run_git_success(
@@ -525,7 +2846,17 @@ fn run_git_success(
     args: Vec<OsString>,
     context: &str,
 ) -> Result<GitOutput, Box<dyn Error>> {
-    let output = run_git(dir, args)?;
+    run_git_success_with_env(dir, args, &[], context)
+}
+
+/// Runs git like [`run_git_success`], with the variables in `env` added to this command only.
+fn run_git_success_with_env(
+    dir: Option<&Path>,
+    args: Vec<OsString>,
+    env: &[(&str, &str)],
+    context: &str,
+) -> Result<GitOutput, Box<dyn Error>> {
+    let output = run_git_retrying(dir, args, env)?;
     if output.status.success() {
         Ok(output)
     } else {
@@ -539,6 +2870,143 @@ fn run_git_success(
     }
 }
 
+fn record_timing(dir: Option<&Path>, args: &[OsString], elapsed: Duration) {
+    timings::record(dir, args, elapsed);
+    let Ok(mut slowest) = SLOWEST_COMMAND.lock() else {
+        return;
+    };
+    if slowest
+        .as_ref()
+        .is_some_and(|timing| timing.elapsed >= elapsed)
+    {
+        return;
+    }
+    *slowest = Some(CommandTiming {
+        label: step_label(args),
+        dir: dir.map(Path::to_path_buf),
+        elapsed,
+    });
+}
+
+/// Names a git invocation by its command, keeping the subcommand for multi-level commands
+/// such as `stash push` or `worktree add`.
+fn step_label(args: &[OsString]) -> String {
+    let mut words = args.iter().map(|arg| arg.to_string_lossy());
+    let mut command = words.next();
+    // Skip `-c <name>=<value>` settings in front of the subcommand.
+    while command.as_deref() == Some("-c") {
+        words.next();
+        command = words.next();
+    }
+    let Some(command) = command else {
+        return String::new();
+    };
+    match (command.as_ref(), words.next()) {
+        ("stash" | "worktree", Some(sub)) if !sub.starts_with('-') => format!("{command} {sub}"),
+        _ => command.into_owned(),
+    }
+}
+
+/// Whether git may ask something on the terminal while running `args`: checkouts run hooks
+/// that may prompt, and fetches and LFS downloads may ask for credentials.
+fn may_prompt(args: &[OsString]) -> bool {
+    let label = step_label(args);
+    let command = label.split(' ').next().unwrap_or_default();
+    matches!(
+        command,
+        "switch" | "checkout" | "fetch" | "lfs" | "submodule"
+    )
+}
+
+/// Prints a one-line pointer at the slowest git command when it crossed
+/// [`SLOW_STEP_THRESHOLD`], with a hint about flags that could make it faster.
+fn report_slowest_step(options: &SwapOptions, logger: &Logger) {
+    if !logger.shows(Verbosity::Normal) {
+        return;
+    }
+    let Ok(slowest) = SLOWEST_COMMAND.lock() else {
+        return;
+    };
+    let Some(slowest) = slowest.as_ref() else {
+        return;
+    };
+    if slowest.elapsed < SLOW_STEP_THRESHOLD {
+        return;
+    }
+    let location = slowest
+        .dir
+        .as_deref()
+        .map(|dir| format!(" in {}", display_relative(dir)))
+        .unwrap_or_default();
+    let hint = match slowest.label.as_str() {
+        "stash push" | "stash apply" if options.include_ignored => {
+            "; ignored files make stashes large, consider dropping --include-ignored"
+        }
+        "stash push" | "stash apply" => {
+            "; large untracked files slow stashing, clean them up, pass --ignore-untracked, or set max_stash_size"
+        }
+        "switch" if !options.serial => {
+            "; checkouts dominate the swap, --serial can help on spinning disks"
+        }
+        _ => "",
+    };
+    println!(
+        "slowest step: {}{location} ({}){hint}",
+        slowest.label,
+        format_duration(slowest.elapsed)
+    );
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{secs}s")
+    }
+}
+
+/// Renders `path` relative to the current directory when that is shorter.
+fn display_relative(path: &Path) -> String {
+    let relative = env::current_dir()
+        .ok()
+        .and_then(|cwd| paths::canonicalize(&cwd).ok())
+        .and_then(|cwd| relative_path(path, &cwd));
+    match relative {
+        Some(relative) if relative.as_os_str().len() < path.as_os_str().len() => {
+            relative.display().to_string()
+        }
+        _ => path.display().to_string(),
+    }
+}
+
+fn relative_path(path: &Path, base: &Path) -> Option<PathBuf> {
+    if !path.is_absolute() || !base.is_absolute() {
+        return None;
+    }
+    let path_parts: Vec<_> = path.components().collect();
+    let base_parts: Vec<_> = base.components().collect();
+    let common = path_parts
+        .iter()
+        .zip(&base_parts)
+        .take_while(|(a, b)| paths::same_component(a, b))
+        .count();
+    if common == 0 {
+        return None;
+    }
+    let mut relative = PathBuf::new();
+    for _ in common..base_parts.len() {
+        relative.push("..");
+    }
+    for part in &path_parts[common..] {
+        relative.push(part);
+    }
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    Some(relative)
+}
+
 fn describe_args(args: &[OsString]) -> String {
     let mut rendered = String::new();
     for (index, arg) in args.iter().enumerate() {
@@ -550,22 +3018,118 @@ fn describe_args(args: &[OsString]) -> String {
     rendered
 }
 
+/// Offers the worktrees of the repository around the current directory, so completion never
+/// walks into a directory that is not a worktree.
+fn destination_value_completer(current: &OsStr) -> Vec<CompletionCandidate> {
+    let Some(cwd) = env::current_dir()
+        .ok()
+        .and_then(|dir| paths::canonicalize(&dir).ok())
+    else {
+        return Vec::new();
+    };
+    let Ok(snapshot) = RepoSnapshot::for_completion(&cwd, false) else {
+        return Vec::new();
+    };
+    let entries: Vec<WorktreeEntry> = snapshot
+        .worktrees
+        .into_iter()
+        .filter(|entry| !entry.is_stale())
+        .collect();
+    destination_candidates(&entries, &cwd, &current.to_string_lossy())
+}
+
+/// Worktree paths matching `prefix`, relative to `cwd` unless the prefix is absolute, each
+/// described by its branch. Bare entries are skipped.
+fn destination_candidates(
+    entries: &[WorktreeEntry],
+    cwd: &Path,
+    prefix: &str,
+) -> Vec<CompletionCandidate> {
+    entries
+        .iter()
+        .filter(|entry| entry.head.is_some())
+        .filter_map(|entry| {
+            let path = if Path::new(prefix).is_absolute() {
+                entry.path.clone()
+            } else {
+                relative_path(&entry.path, cwd).unwrap_or_else(|| entry.path.clone())
+            };
+            let path = path.display().to_string();
+            if !path.starts_with(prefix) {
+                return None;
+            }
+            let help = entry.branch.as_deref().unwrap_or("(detached)").to_string();
+            Some(CompletionCandidate::new(path).help(Some(help.into())))
+        })
+        .collect()
+}
+
 fn branch_value_completer(current: &OsStr) -> Vec<CompletionCandidate> {
-    let mut results = Vec::new();
     let dest_dir = match completion_destination_dir() {
         Some(dir) => dir,
-        None => return results,
+        None => return Vec::new(),
     };
     let prefix = current.to_string_lossy();
-    if let Ok(branches) = list_worktree_branches(&dest_dir) {
-        results.extend(
-            branches
-                .into_iter()
-                .filter(|name| name.starts_with(prefix.as_ref()))
-                .map(CompletionCandidate::new),
-        );
+    // Only --create can take a branch that no worktree holds yet.
+    let creating =
+        completion_words().is_some_and(|words| words.iter().any(|word| word == "--create"));
+    let Ok(snapshot) = RepoSnapshot::for_completion(&dest_dir, creating) else {
+        return Vec::new();
+    };
+    let worktree_branches: Vec<String> = snapshot
+        .worktrees
+        .iter()
+        .filter_map(|entry| entry.branch.clone())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    branch_candidates(
+        &worktree_branches,
+        &snapshot.local_branches,
+        &snapshot.remote_branches,
+        &prefix,
+    )
+}
+
+/// Branches matching `prefix`: those held by a worktree first, then other local branches,
+/// then remote branches under the local name `git worktree add` creates for them. Remote
+/// names that exist in several remotes are left out, since git cannot pick one.
+fn branch_candidates(
+    worktree_branches: &[String],
+    local: &[String],
+    remote: &[String],
+    prefix: &str,
+) -> Vec<CompletionCandidate> {
+    let mut seen: BTreeSet<&str> = worktree_branches.iter().map(String::as_str).collect();
+    let mut candidates: Vec<CompletionCandidate> = worktree_branches
+        .iter()
+        .filter(|name| name.starts_with(prefix))
+        .map(CompletionCandidate::new)
+        .collect();
+    for name in local {
+        if seen.insert(name) && name.starts_with(prefix) {
+            candidates.push(
+                CompletionCandidate::new(name).help(Some("local branch, no worktree".into())),
+            );
+        }
     }
-    results
+    let mut remote_names: Vec<(&str, &str)> = remote
+        .iter()
+        .filter_map(|full| Some((full.split_once('/')?.1, full.as_str())))
+        .filter(|(name, _)| *name != "HEAD" && !seen.contains(name))
+        .collect();
+    remote_names.sort();
+    for (index, (name, full)) in remote_names.iter().enumerate() {
+        let ambiguous = remote_names
+            .iter()
+            .enumerate()
+            .any(|(other, (other_name, _))| other != index && other_name == name);
+        if !ambiguous && name.starts_with(prefix) {
+            candidates
+                .push(CompletionCandidate::new(*name).help(Some(format!("remote {full}").into())));
+        }
+    }
+    candidates
 }
 
 fn completion_destination_dir() -> Option<PathBuf> {
@@ -585,13 +3149,27 @@ fn completion_words() -> Option<Vec<OsString>> {
 }
 
 fn completion_destination(words: &[OsString]) -> Option<OsString> {
+    let command = Cli::command();
+    let takes_value = |flag: &str| {
+        command.get_arguments().any(|arg| {
+            let matches = match flag.strip_prefix("--") {
+                Some(long) => arg.get_long() == Some(long),
+                None => flag.len() == 2 && arg.get_short() == flag.chars().nth(1),
+            };
+            matches && arg.get_action().takes_values()
+        })
+    };
     let mut iter = words.iter().skip(1);
     while let Some(arg) = iter.next() {
         if arg == "--" {
             return iter.next().cloned();
         }
         match arg.to_str() {
-            Some("-d") | Some("--debug") => continue,
+            Some(flag) if flag.starts_with('-') => {
+                if !flag.contains('=') && takes_value(flag) {
+                    iter.next();
+                }
+            }
             _ => return Some(arg.clone()),
         }
     }
@@ -600,7 +3178,13 @@ fn completion_destination(words: &[OsString]) -> Option<OsString> {
 
 #[cfg(test)]
 mod tests {
-    use super::parse_worktree_branches;
+    use super::{
+        branch_candidates, destination_candidates, git_command, is_index_lock_contention,
+        lists_bare_repository, may_prompt, newest_stash_with_message, parse_worktree_branches,
+        parse_worktrees, relative_path, step_label, swap_summary, RepositoryOverride,
+    };
+    use std::ffi::OsString;
+    use std::path::{Path, PathBuf};
 
     #[test]
     fn parses_branches_from_porcelain() {
@@ -630,4 +3214,250 @@ branch
         let branches = parse_worktree_branches(fixture);
         assert_eq!(branches, vec!["feature/b".to_string(), "main".to_string()]);
     }
+
+    #[test]
+    fn parses_prunable_worktrees() {
+        let fixture = r#"worktree /repos/main
+HEAD e1e1b70d2e8c133c96ab8050cc582f88aa83ef77
+branch refs/heads/main
+
+worktree /mnt/usb/feature-a
+HEAD 1c1cdd9c68b3bd55a72efa87c67fd03c4b5aa20c
+branch refs/heads/feature/a
+prunable gitdir file points to non-existent location
+
+"#;
+        let entries = parse_worktrees(Path::new("/repos/main"), fixture);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].prunable, None);
+        assert_eq!(entries[1].path, PathBuf::from("/mnt/usb/feature-a"));
+        assert_eq!(entries[1].branch.as_deref(), Some("feature/a"));
+        assert_eq!(
+            entries[1].prunable.as_deref(),
+            Some("gitdir file points to non-existent location")
+        );
+    }
+
+    #[test]
+    fn parses_locked_worktrees() {
+        let fixture = r#"worktree /repos/main
+HEAD e1e1b70d2e8c133c96ab8050cc582f88aa83ef77
+branch refs/heads/main
+
+worktree /repos/feature-a
+HEAD 1c1cdd9c68b3bd55a72efa87c67fd03c4b5aa20c
+branch refs/heads/feature/a
+locked on a portable drive
+
+worktree /repos/feature-b
+HEAD 9a9a71114237d6a1f2ba4d0332eec2a3edf1b738
+branch refs/heads/feature/b
+locked
+
+"#;
+        let entries = parse_worktrees(Path::new("/repos/main"), fixture);
+        assert_eq!(entries[0].locked, None);
+        assert_eq!(entries[1].locked.as_deref(), Some("on a portable drive"));
+        assert_eq!(entries[2].locked.as_deref(), Some(""));
+    }
+
+    #[test]
+    fn skips_the_bare_repository() {
+        let fixture = r#"worktree /repos/project.git
+bare
+
+worktree /repos/main
+HEAD e1e1b70d2e8c133c96ab8050cc582f88aa83ef77
+branch refs/heads/main
+
+worktree /repos/feature-a
+HEAD 1c1cdd9c68b3bd55a72efa87c67fd03c4b5aa20c
+branch refs/heads/feature/a
+
+"#;
+        let entries = parse_worktrees(Path::new("/repos/main"), fixture);
+        let paths: Vec<&Path> = entries.iter().map(|entry| entry.path.as_path()).collect();
+        assert_eq!(
+            paths,
+            [Path::new("/repos/main"), Path::new("/repos/feature-a")]
+        );
+        assert!(lists_bare_repository(fixture));
+        assert!(!lists_bare_repository(
+            "worktree /repos/main\nHEAD e1e1b70\nbranch refs/heads/main\n"
+        ));
+    }
+
+    #[test]
+    fn completes_destinations_relative_to_the_current_directory() {
+        let fixture = r#"worktree /repos/main
+bare
+
+worktree /repos/main-wt
+HEAD e1e1b70d2e8c133c96ab8050cc582f88aa83ef77
+branch refs/heads/main
+
+worktree /repos/feature-a
+HEAD 1c1cdd9c68b3bd55a72efa87c67fd03c4b5aa20c
+branch refs/heads/feature/a
+
+"#;
+        let entries = parse_worktrees(Path::new("/repos/main"), fixture);
+        let values = |prefix: &str| {
+            destination_candidates(&entries, Path::new("/repos/main-wt"), prefix)
+                .iter()
+                .map(|candidate| candidate.get_value().to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(values(""), [".", "../feature-a"]);
+        assert_eq!(values("../f"), ["../feature-a"]);
+        assert_eq!(values("/repos/m"), ["/repos/main-wt"]);
+    }
+
+    #[test]
+    fn offers_local_and_unambiguous_remote_branches_after_worktree_branches() {
+        let owned = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>()
+        };
+        let candidates = branch_candidates(
+            &owned(&["main"]),
+            &owned(&["fix/typo", "main"]),
+            &owned(&[
+                "origin/HEAD",
+                "origin/main",
+                "origin/feature/x",
+                "origin/shared",
+                "upstream/shared",
+            ]),
+            "",
+        );
+        let values: Vec<String> = candidates
+            .iter()
+            .map(|candidate| candidate.get_value().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(values, ["main", "fix/typo", "feature/x"]);
+        assert_eq!(
+            candidates[2].get_help().map(ToString::to_string).as_deref(),
+            Some("remote origin/feature/x")
+        );
+        assert_eq!(
+            branch_candidates(&owned(&["main"]), &[], &owned(&["origin/feature/x"]), "f").len(),
+            1
+        );
+    }
+
+    #[test]
+    fn detects_index_lock_contention() {
+        assert!(is_index_lock_contention(
+            "fatal: Unable to create '/repo/.git/worktrees/wt/index.lock': File exists.\n\nAnother git process seems to be running in this repository"
+        ));
+        assert!(!is_index_lock_contention(
+            "error: Your local changes to the following files would be overwritten by checkout"
+        ));
+        assert!(!is_index_lock_contention(
+            "fatal: Unable to create '/repo/.git/index.lock': Permission denied"
+        ));
+    }
+
+    #[test]
+    fn labels_steps_by_git_command() {
+        let args = |words: &[&str]| words.iter().map(OsString::from).collect::<Vec<_>>();
+        assert_eq!(step_label(&args(&["stash", "push", "-u"])), "stash push");
+        assert_eq!(step_label(&args(&["stash", "-u"])), "stash");
+        assert_eq!(step_label(&args(&["switch", "main"])), "switch");
+        assert_eq!(
+            step_label(&args(&[
+                "-c",
+                "core.hooksPath=/dev/null",
+                "switch",
+                "--detach"
+            ])),
+            "switch"
+        );
+        assert_eq!(step_label(&args(&[])), "");
+    }
+
+    #[test]
+    fn keeps_commands_that_may_prompt_on_the_terminal() {
+        let args = |words: &[&str]| words.iter().map(OsString::from).collect::<Vec<_>>();
+        assert!(may_prompt(&args(&["switch", "main"])));
+        assert!(may_prompt(&args(&[
+            "-c",
+            "core.hooksPath=/dev/null",
+            "fetch"
+        ])));
+        assert!(may_prompt(&args(&["lfs", "pull"])));
+        assert!(!may_prompt(&args(&["stash", "push", "-u"])));
+        assert!(!may_prompt(&args(&["rev-parse", "--git-dir"])));
+    }
+
+    #[test]
+    fn renders_relative_paths() {
+        let cwd = Path::new("/work/repo");
+        assert_eq!(
+            relative_path(Path::new("/work/big-wt"), cwd),
+            Some(PathBuf::from("../big-wt"))
+        );
+        assert_eq!(
+            relative_path(Path::new("/work/repo/sub"), cwd),
+            Some(PathBuf::from("sub"))
+        );
+        assert_eq!(relative_path(cwd, cwd), Some(PathBuf::from(".")));
+    }
+
+    #[test]
+    fn finds_the_newest_stash_with_a_message() {
+        let list = "e1e1b70\x00On feature/a: swap-stash-feature/a\n1c1cdd9\x00On main: swap-stash-main\n9a0b1c2\x00On main: swap-stash-main\n";
+        assert_eq!(
+            newest_stash_with_message(list, "swap-stash-main"),
+            Some("1c1cdd9")
+        );
+        assert_eq!(newest_stash_with_message(list, "swap-stash-dev"), None);
+    }
+
+    #[test]
+    fn keeps_repository_variables_away_from_git() {
+        let cmd = git_command();
+        let removed: Vec<_> = cmd
+            .get_envs()
+            .filter(|(_, value)| value.is_none())
+            .map(|(name, _)| name.to_string_lossy().into_owned())
+            .collect();
+        assert!(removed.iter().any(|name| name == "GIT_INDEX_FILE"));
+        assert!(removed.iter().any(|name| name == "GIT_DIR"));
+        assert!(!removed.iter().any(|name| name == "GIT_AUTHOR_NAME"));
+
+        let home = std::env::temp_dir().join(format!("swap-worktree-home-{}", std::process::id()));
+        std::fs::create_dir_all(home.join("wt").join(".git")).unwrap();
+        let dotfiles = RepositoryOverride {
+            git_dir: home.join(".dotfiles"),
+            work_tree: home.clone(),
+        };
+        let covered = [
+            dotfiles.covers(&home),
+            dotfiles.covers(&home.join(".config")),
+            dotfiles.covers(&home.join("wt").join("src")),
+            dotfiles.covers(&std::env::temp_dir()),
+        ];
+        std::fs::remove_dir_all(&home).unwrap();
+        assert_eq!(covered, [true, true, false, false]);
+    }
+
+    #[test]
+    fn summarizes_the_swap_before_asking() {
+        assert_eq!(
+            swap_summary((".", "main", 12), ("../wt", "feature/x", 12)),
+            ". main ⇄ feature/x ../wt; 12 dirty files will be stashed on each side"
+        );
+        assert_eq!(
+            swap_summary((".", "main", 1), ("../wt", "feature/x", 0)),
+            ". main ⇄ feature/x ../wt; 1 dirty file will be stashed in ."
+        );
+        assert_eq!(
+            swap_summary((".", "main", 0), ("../wt", "feature/x", 0)),
+            ". main ⇄ feature/x ../wt; both worktrees are clean"
+        );
+    }
 }
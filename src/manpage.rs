@@ -0,0 +1,95 @@
+use std::io::{self, Write};
+
+use clap::Command;
+use clap_mangen::roff::{bold, roman, Roff};
+use clap_mangen::Man;
+
+/// Environment variables the CLI reads, with what they change.
+const ENVIRONMENT: [(&str, &str); 6] = [
+    (
+        "SWAP_WORKTREE_GIT",
+        "The git executable to run instead of the first git on PATH. --git-cmd wins over it.",
+    ),
+    (
+        "SWAP_WORKTREE_JJ",
+        "The jj executable to run for Jujutsu workspaces instead of the first jj on PATH.",
+    ),
+    (
+        "XDG_CONFIG_HOME",
+        "Where the user configuration swap-worktree/config.toml is looked up; ~/.config when unset (%APPDATA% on Windows).",
+    ),
+    (
+        "NO_COLOR",
+        "When set and not empty, turns off colored output unless --color always is passed.",
+    ),
+    (
+        "GIT_DIR, GIT_WORK_TREE, GIT_INDEX_FILE",
+        "Not passed on to git, along with the other variables of git rev-parse --local-env-vars; use --git-dir and --work-tree instead.",
+    ),
+    (
+        "GIT_*",
+        "Other git variables, such as author identities, GIT_SSH_COMMAND and GIT_TRACE, are inherited by every git command.",
+    ),
+];
+
+/// Exit statuses and when the CLI returns them.
+const EXIT_STATUS: [(&str, &str); 3] = [
+    ("0", "The swap, or the subcommand, succeeded."),
+    (
+        "1",
+        "Something failed or was refused: the error on stderr says what, and whether anything was changed. Also returned by doctor when it finds a problem, and by batch unless every swap succeeded.",
+    ),
+    (
+        "130",
+        "A second Ctrl-C exited without restoring the worktrees; run swap-worktree recover.",
+    ),
+];
+
+/// Writes the man page of `command` in roff: the sections clap_mangen derives from the
+/// clap definitions, followed by ENVIRONMENT and EXIT STATUS.
+pub(crate) fn render(command: Command, out: &mut dyn Write) -> io::Result<()> {
+    let man = Man::new(command);
+    man.render_title(out)?;
+    man.render_name_section(out)?;
+    man.render_synopsis_section(out)?;
+    man.render_description_section(out)?;
+    man.render_options_section(out)?;
+    man.render_subcommands_section(out)?;
+    definitions("ENVIRONMENT", &ENVIRONMENT).to_writer(out)?;
+    definitions("EXIT STATUS", &EXIT_STATUS).to_writer(out)?;
+    man.render_version_section(out)
+}
+
+/// A section of tagged paragraphs, one per term.
+fn definitions(title: &str, entries: &[(&str, &str)]) -> Roff {
+    let mut roff = Roff::new();
+    roff.control("SH", [title]);
+    for (term, description) in entries {
+        roff.control("TP", []);
+        roff.text([bold(*term)]);
+        roff.text([roman(*description)]);
+    }
+    roff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render;
+    use clap::{Arg, Command};
+
+    #[test]
+    fn adds_environment_and_exit_status_sections() {
+        let command = Command::new("swap-worktree")
+            .version("0.1.0")
+            .about("Swap branches")
+            .arg(Arg::new("yes").long("yes").help("Do not ask"));
+        let mut page = Vec::new();
+        render(command, &mut page).unwrap();
+        let page = String::from_utf8(page).unwrap();
+        let position = |text: &str| page.find(text).unwrap();
+        assert!(position(".TH swap-worktree") < position(".SH OPTIONS"));
+        assert!(position(".SH OPTIONS") < position(".SH ENVIRONMENT"));
+        assert!(position(".SH ENVIRONMENT") < position(".SH \"EXIT STATUS\""));
+        assert!(page.contains("SWAP_WORKTREE_GIT"));
+    }
+}
@@ -0,0 +1,169 @@
+use std::error::Error;
+use std::path::Path;
+
+use tracing::{debug, info};
+
+use crate::{git_args, run_git, run_git_success, SwapPlan};
+
+/// What is missing from a clone that git cannot switch branches without: partial clones
+/// download missing objects from their promisor remotes on demand, shallow clones have no
+/// way to get them at all.
+pub(crate) struct CloneShape {
+    /// Remotes with `remote.<name>.promisor` set, the ones a partial clone fetches from.
+    promisors: Vec<String>,
+    shallow: bool,
+}
+
+impl CloneShape {
+    /// Reads the partial and shallow configuration of the repository containing `dir`.
+    pub(crate) fn read(dir: &Path) -> Result<Self, Box<dyn Error>> {
+        let shallow = run_git_success(
+            Some(dir),
+            git_args!["rev-parse", "--is-shallow-repository"],
+            "Failed to check for a shallow clone.",
+        )?
+        .stdout
+        .trim()
+            == "true";
+        let promisors = run_git(
+            Some(dir),
+            git_args!["config", "--get-regexp", r"^remote\..*\.promisor$"],
+        )?;
+        let promisors = promisor_remotes(&promisors.stdout);
+        debug!("Promisor remotes: {promisors:?}; shallow: {shallow}.");
+        Ok(Self { promisors, shallow })
+    }
+
+    /// Counts the objects each branch of `plan` still needs for its new worktree and
+    /// returns one line per branch for the plan. Fails when a shallow clone lacks them, or
+    /// when downloading them is not allowed.
+    pub(crate) fn check(
+        &self,
+        plan: &SwapPlan,
+        detach_source: bool,
+        fetch_on_demand: bool,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        if self.promisors.is_empty() && !self.shallow {
+            return Ok(Vec::new());
+        }
+        let mut branches = vec![plan.src_branch.as_str()];
+        if !detach_source {
+            branches.push(&plan.dest_branch);
+        }
+        let mut notes = Vec::new();
+        let mut refused = Vec::new();
+        for branch in branches {
+            let output = run_git_success(
+                Some(&plan.dest_dir),
+                git_args![
+                    "rev-list",
+                    "--objects",
+                    "--missing=print",
+                    "--no-walk",
+                    branch
+                ],
+                "Failed to look for missing objects.",
+            )?;
+            let missing = count_missing(&output.stdout);
+            if missing == 0 {
+                continue;
+            }
+            if self.promisors.is_empty() {
+                return Err(format!(
+                    "'{branch}' needs {} this shallow clone does not have; nothing was changed.\nFetch them with `git fetch --deepen=<depth>` or `git fetch --unshallow` first.",
+                    objects(missing)
+                )
+                .into());
+            }
+            let remotes = self.promisors.join("', '");
+            info!(
+                event = "fetch-on-demand",
+                branch,
+                objects = missing,
+                remote = remotes.as_str(),
+                "Switching to '{branch}' downloads up to {} from '{remotes}'.",
+                objects(missing)
+            );
+            notes.push(format!(
+                "switching to {branch} downloads up to {} from '{remotes}'",
+                objects(missing)
+            ));
+            refused.push(format!("  {branch}: {}", objects(missing)));
+        }
+        if !fetch_on_demand && !refused.is_empty() {
+            return Err(format!(
+                "The swap would download missing objects from the promisor remote; nothing was changed:\n{}\nRun it without --no-fetch-on-demand once the remote is reachable.",
+                refused.join("\n")
+            )
+            .into());
+        }
+        Ok(notes)
+    }
+}
+
+/// Adds what to do to a git error about an object a partial clone could not download.
+pub(crate) fn explain(err: Box<dyn Error>) -> Box<dyn Error> {
+    let message = err.to_string();
+    if message.contains("lazy fetching disabled") {
+        format!("{message}\nThe object is missing from this partial clone, and --no-fetch-on-demand keeps git from downloading it.").into()
+    } else if message.contains("from promisor remote") {
+        format!("{message}\nThis partial clone downloads missing objects while switching, and that failed. Check that the promisor remote is reachable and retry the swap.").into()
+    } else {
+        err
+    }
+}
+
+/// The variable that keeps the switches to the new branches from downloading objects on
+/// demand, so that a missing object fails the switch instead of reaching the network (git
+/// 2.44 and later).
+pub(crate) fn switch_env(fetch_on_demand: bool) -> Option<(&'static str, &'static str)> {
+    (!fetch_on_demand).then_some(("GIT_NO_LAZY_FETCH", "1"))
+}
+
+/// Remote names from `git config --get-regexp '^remote\..*\.promisor$'`, which prints
+/// `remote.<name>.promisor <value>` lines.
+fn promisor_remotes(config: &str) -> Vec<String> {
+    config
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once(' ')?;
+            let name = key.strip_prefix("remote.")?.strip_suffix(".promisor")?;
+            ["true", "yes", "on", "1"]
+                .contains(&value.trim().to_lowercase().as_str())
+                .then(|| name.to_string())
+        })
+        .collect()
+}
+
+/// "1 object" or "42 objects".
+fn objects(count: usize) -> String {
+    match count {
+        1 => "1 object".to_string(),
+        count => format!("{count} objects"),
+    }
+}
+
+/// Objects `rev-list --missing=print` reports as missing: the lines starting with `?`.
+fn count_missing(rev_list: &str) -> usize {
+    rev_list
+        .lines()
+        .filter(|line| line.starts_with('?'))
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{count_missing, promisor_remotes};
+
+    #[test]
+    fn reads_promisor_remotes_and_missing_objects() {
+        assert_eq!(
+            promisor_remotes(
+                "remote.origin.promisor true\nremote.fork.promisor false\nremote.my.mirror.promisor 1\n"
+            ),
+            ["origin", "my.mirror"]
+        );
+        let rev_list = "cb41451b60986d04346d4168652777bc202e7bf5\nd00491fd7e5bb6fa28c517a0bb32b8b506539d4d f1\n?587be6b4c3f93f93c489c0111bba5596147a26cb\n";
+        assert_eq!(count_missing(rev_list), 1);
+    }
+}
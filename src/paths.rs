@@ -0,0 +1,120 @@
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Canonicalizes `path` without the `\\?\` prefix Windows adds, which `git -C` and most
+/// other programs do not understand. The prefix is kept only where the path cannot be
+/// written without it, e.g. when it is longer than `MAX_PATH`.
+pub(crate) fn canonicalize(path: &Path) -> io::Result<PathBuf> {
+    dunce::canonicalize(path)
+}
+
+/// The real path of `path`, with symlinks resolved, or `path` itself when it cannot be
+/// resolved (e.g. a stale worktree whose directory is gone).
+pub(crate) fn resolve(path: &Path) -> PathBuf {
+    canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Whether `a` and `b` name the same file or directory. When both exist they are compared
+/// by identity (device and inode, or volume and file index on Windows), which also sees
+/// through symlinks, bind mounts, and short names. Otherwise their resolved paths are
+/// compared; on Windows that comparison ignores case, separator style, and `\\?\`.
+pub(crate) fn same_path(a: &Path, b: &Path) -> bool {
+    if let Ok(same) = same_file::is_same_file(a, b) {
+        return same;
+    }
+    comparison_key(&resolve(a).to_string_lossy(), cfg!(windows))
+        == comparison_key(&resolve(b).to_string_lossy(), cfg!(windows))
+}
+
+/// Whether two path components are equal, ignoring case on Windows.
+pub(crate) fn same_component(a: &Component, b: &Component) -> bool {
+    if cfg!(windows) {
+        comparison_key(&a.as_os_str().to_string_lossy(), true)
+            == comparison_key(&b.as_os_str().to_string_lossy(), true)
+    } else {
+        a == b
+    }
+}
+
+/// Turns a path printed by git into a path, relative paths being relative to `base`. Git
+/// for Windows prints `C:/work/repo`; the slashes become backslashes so the path displays
+/// and compares like the ones Windows hands out.
+pub(crate) fn normalize_path(base: &Path, path: &str) -> PathBuf {
+    let candidate = if cfg!(windows) {
+        PathBuf::from(path.replace('/', "\\"))
+    } else {
+        PathBuf::from(path)
+    };
+    if candidate.is_absolute() {
+        candidate
+    } else {
+        base.join(candidate)
+    }
+}
+
+/// The form of `path` that equal paths share. Without `windows` that is the path itself;
+/// with it, separators are unified, the `\\?\` prefix and trailing separators are dropped,
+/// and the path is lowercased, since NTFS and ReFS ignore case by default.
+fn comparison_key(path: &str, windows: bool) -> String {
+    if !windows {
+        return path.to_string();
+    }
+    let path = path.replace('/', "\\");
+    let path = if let Some(share) = path.strip_prefix(r"\\?\UNC\") {
+        format!(r"\\{share}")
+    } else if let Some(local) = path.strip_prefix(r"\\?\") {
+        local.to_string()
+    } else {
+        path
+    };
+    path.trim_end_matches('\\').to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::comparison_key;
+
+    #[test]
+    fn equates_windows_spellings_of_a_path() {
+        let key = |path| comparison_key(path, true);
+        assert_eq!(key(r"\\?\C:\Work\Repo"), key("c:/work/repo/"));
+        assert_eq!(key(r"C:\work\repo"), key("C:/work\\repo"));
+        assert_eq!(
+            key(r"\\?\UNC\server\share\repo"),
+            key(r"\\server\share\repo")
+        );
+        assert_ne!(key(r"C:\work\repo"), key(r"D:\work\repo"));
+        assert_ne!(
+            comparison_key("/work/Repo", false),
+            comparison_key("/work/repo", false)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sees_through_symlinked_worktree_paths() {
+        let base = std::env::temp_dir().join(format!("swap-worktree-paths-{}", std::process::id()));
+        let real = base.join("ssd").join("foo");
+        let link = base.join("wt-foo");
+        std::fs::create_dir_all(&real).unwrap();
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+        let same = super::same_path(&link, &real);
+        let resolved = (super::resolve(&link), super::resolve(&real));
+        let other = super::same_path(&link, &base);
+        std::fs::remove_dir_all(&base).unwrap();
+        assert!(same);
+        assert!(!other);
+        assert_eq!(resolved.0, resolved.1);
+        assert!(!super::same_path(&link, &real.join("gone")));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn canonicalizes_without_the_verbatim_prefix() {
+        let dir = std::env::temp_dir();
+        let canonical = super::canonicalize(&dir).unwrap();
+        assert!(!canonical.to_string_lossy().starts_with(r"\\?\"));
+        let upper = std::path::PathBuf::from(canonical.to_string_lossy().to_uppercase());
+        assert!(super::same_path(&canonical, &upper));
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use serde::Serialize;
+use tracing::warn;
+
+use crate::{git_args, run_git, run_git_success};
+
+/// How many of the largest untracked paths the size guard names.
+const LARGEST_SHOWN: usize = 5;
+
+/// What stashing one worktree would take along, counted from `git status` before anything
+/// is stashed.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub(crate) struct StashPreview {
+    /// Paths with any change, each counted once.
+    pub(crate) files: usize,
+    pub(crate) staged: usize,
+    pub(crate) modified: usize,
+    pub(crate) untracked: usize,
+    /// Only counted with `--include-ignored`, since they stay behind otherwise.
+    pub(crate) ignored: usize,
+    /// Size on disk of the untracked and ignored files, which the stash stores in full.
+    pub(crate) new_bytes: u64,
+    /// The top-level files and directories holding most of `new_bytes`, largest first.
+    pub(crate) largest: Vec<LargeEntry>,
+    /// `git diff HEAD --shortstat` of the tracked changes, e.g. "2 files changed, 9
+    /// insertions(+)".
+    pub(crate) diffstat: String,
+}
+
+/// A top-level file or directory of untracked files, e.g. `node_modules/`.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub(crate) struct LargeEntry {
+    pub(crate) path: String,
+    pub(crate) bytes: u64,
+}
+
+impl StashPreview {
+    /// Reads what a stash of `dir` would hold: tracked changes, plus untracked files unless
+    /// `untracked` is off, plus ignored files with `ignored`.
+    pub(crate) fn read(dir: &Path, untracked: bool, ignored: bool) -> Result<Self, Box<dyn Error>> {
+        let mut args = git_args!["status", "--porcelain", "-z"];
+        args.push(OsString::from(if untracked {
+            "--untracked-files=all"
+        } else {
+            "--untracked-files=no"
+        }));
+        if ignored {
+            args.push(OsString::from("--ignored"));
+        }
+        let status = run_git_success(Some(dir), args, "Failed to read worktree status.")?;
+        let mut preview = Self::default();
+        let mut sizes: BTreeMap<String, u64> = BTreeMap::new();
+        for (code, path) in parse_status(&status.stdout) {
+            preview.files += 1;
+            match code {
+                "??" => preview.untracked += 1,
+                "!!" => preview.ignored += 1,
+                _ => {
+                    preview.staged += usize::from(!code.starts_with(' '));
+                    preview.modified += usize::from(!code.ends_with(' '));
+                    continue;
+                }
+            }
+            let bytes = fs::symlink_metadata(dir.join(path)).map_or(0, |meta| meta.len());
+            preview.new_bytes += bytes;
+            *sizes.entry(top_level(path).to_string()).or_default() += bytes;
+        }
+        preview.largest = largest(sizes);
+        if preview.staged + preview.modified > 0 {
+            // Fails on an unborn branch, where there is no HEAD to compare with.
+            let diff = run_git(Some(dir), git_args!["diff", "HEAD", "--shortstat"])?;
+            if diff.status.success() {
+                preview.diffstat = diff.stdout.trim().to_string();
+            }
+        }
+        Ok(preview)
+    }
+
+    /// "3 staged, 1 modified, 120 untracked (2.1 GB); 3 files changed, 40 insertions(+)",
+    /// or "clean".
+    pub(crate) fn describe(&self) -> String {
+        let counts = [
+            (self.staged, "staged"),
+            (self.modified, "modified"),
+            (self.untracked, "untracked"),
+            (self.ignored, "ignored"),
+        ];
+        let parts: Vec<String> = counts
+            .iter()
+            .filter(|(count, _)| *count > 0)
+            .map(|(count, label)| format!("{count} {label}"))
+            .collect();
+        if parts.is_empty() {
+            return "clean".to_string();
+        }
+        let mut description = parts.join(", ");
+        if self.new_bytes > 0 {
+            description.push_str(&format!(" ({})", format_size(self.new_bytes)));
+        }
+        if !self.diffstat.is_empty() {
+            description.push_str("; ");
+            description.push_str(&self.diffstat);
+        }
+        description
+    }
+}
+
+/// Refuses to stash more than `limit` bytes of untracked (and ignored) files in any of the
+/// labelled worktrees, naming the largest paths. With `force` the report is a warning.
+pub(crate) fn ensure_within_limit(
+    sides: &[(&str, &StashPreview)],
+    limit: Option<u64>,
+    force: bool,
+) -> Result<(), Box<dyn Error>> {
+    let Some(limit) = limit else {
+        return Ok(());
+    };
+    let mut report = String::new();
+    for (label, preview) in sides
+        .iter()
+        .filter(|(_, preview)| preview.new_bytes > limit)
+    {
+        writeln!(&mut report, "  {label}: {}", format_size(preview.new_bytes)).ok();
+        let width = preview
+            .largest
+            .iter()
+            .map(|entry| entry.path.chars().count())
+            .max()
+            .unwrap_or(0);
+        for entry in &preview.largest {
+            writeln!(
+                &mut report,
+                "    {:width$}  {}",
+                entry.path,
+                format_size(entry.bytes)
+            )
+            .ok();
+        }
+    }
+    if report.is_empty() {
+        return Ok(());
+    }
+    let limit = format_size(limit);
+    if force {
+        warn!(
+            "Stashing untracked files above max_stash_size ({limit}) because of --force:\n{}",
+            report.trim_end()
+        );
+        return Ok(());
+    }
+    Err(format!(
+        "Refusing to stash more than {limit} of untracked files (max_stash_size):\n{}\nAdd them to .gitignore, pass --ignore-untracked to leave untracked files where they are, or pass --force to stash them anyway.",
+        report.trim_end()
+    )
+    .into())
+}
+
+/// The first component of `path`, with a trailing `/` for directories.
+fn top_level(path: &str) -> &str {
+    match path.find('/') {
+        Some(end) => &path[..=end],
+        None => path,
+    }
+}
+
+/// The `LARGEST_SHOWN` largest entries of `sizes`.
+fn largest(sizes: BTreeMap<String, u64>) -> Vec<LargeEntry> {
+    let mut entries: Vec<LargeEntry> = sizes
+        .into_iter()
+        .filter(|(_, bytes)| *bytes > 0)
+        .map(|(path, bytes)| LargeEntry { path, bytes })
+        .collect();
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.bytes));
+    entries.truncate(LARGEST_SHOWN);
+    entries
+}
+
+/// The `XY` code and path of every entry of `git status --porcelain -z`. Renames and
+/// copies carry their old path in an extra field, which is skipped.
+fn parse_status(output: &str) -> Vec<(&str, &str)> {
+    let mut entries = Vec::new();
+    let mut fields = output.split('\0').filter(|field| !field.is_empty());
+    while let Some(field) = fields.next() {
+        let (Some(code), Some(path)) = (field.get(..2), field.get(3..)) else {
+            continue;
+        };
+        if code.starts_with(['R', 'C']) {
+            fields.next();
+        }
+        entries.push((code, path));
+    }
+    entries
+}
+
+/// `bytes` in decimal units, e.g. "2.1 GB".
+pub(crate) fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1000 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1000.0;
+    let mut unit = UNITS[0];
+    for next in &UNITS[1..] {
+        if size < 1000.0 {
+            break;
+        }
+        size /= 1000.0;
+        unit = next;
+    }
+    format!("{size:.1} {unit}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ensure_within_limit, format_size, parse_status, LargeEntry, StashPreview};
+
+    #[test]
+    fn counts_what_a_stash_takes_along() {
+        let entries =
+            parse_status("M  src/lib.rs\0 M README.md\0R  new.rs\0old.rs\0?? build/out.bin\0");
+        assert_eq!(
+            entries,
+            [
+                ("M ", "src/lib.rs"),
+                (" M", "README.md"),
+                ("R ", "new.rs"),
+                ("??", "build/out.bin")
+            ]
+        );
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(2_147_483_648), "2.1 GB");
+        let preview = StashPreview {
+            files: 4,
+            staged: 2,
+            modified: 1,
+            untracked: 1,
+            new_bytes: 2_147_483_648,
+            diffstat: "3 files changed, 40 insertions(+)".to_string(),
+            ..StashPreview::default()
+        };
+        assert_eq!(
+            preview.describe(),
+            "2 staged, 1 modified, 1 untracked (2.1 GB); 3 files changed, 40 insertions(+)"
+        );
+        assert_eq!(StashPreview::default().describe(), "clean");
+    }
+
+    #[test]
+    fn names_the_largest_paths_above_the_limit() {
+        let preview = StashPreview {
+            files: 3,
+            untracked: 3,
+            new_bytes: 250_000_000,
+            largest: vec![
+                LargeEntry {
+                    path: "node_modules/".to_string(),
+                    bytes: 240_000_000,
+                },
+                LargeEntry {
+                    path: "out.log".to_string(),
+                    bytes: 10_000_000,
+                },
+            ],
+            ..StashPreview::default()
+        };
+        let clean = StashPreview::default();
+        let sides = [("../review", &preview), (".", &clean)];
+        assert!(ensure_within_limit(&sides, None, false).is_ok());
+        assert!(ensure_within_limit(&sides, Some(300_000_000), false).is_ok());
+        assert!(ensure_within_limit(&sides, Some(200_000_000), true).is_ok());
+        let err = ensure_within_limit(&sides, Some(200_000_000), false)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains(
+            "  ../review: 250.0 MB\n    node_modules/  240.0 MB\n    out.log        10.0 MB\n"
+        ));
+    }
+}
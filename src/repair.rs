@@ -0,0 +1,145 @@
+use std::error::Error;
+use std::fs;
+use std::io::{self, IsTerminal as _};
+use std::path::{Path, PathBuf};
+
+use tracing::{debug, info};
+
+use crate::paths::{self, normalize_path};
+use crate::snapshot::RepoSnapshot;
+use crate::{confirm, git_args, logging, run_git_success, SwapOptions, WorktreeEntry};
+
+/// Updates the record of `branch`'s worktree when git still lists it at a path that is
+/// gone, but its directory turns up next to that path or next to `dest_dir`: moved by hand
+/// instead of with `git worktree move`. Runs `git worktree repair` with `--repair`, asks
+/// first when someone can answer, and fails naming the new location otherwise. Re-reads
+/// `snapshot` after a repair. Does nothing when there is nothing to repair.
+pub(crate) fn offer(
+    dest_dir: &Path,
+    snapshot: &mut RepoSnapshot,
+    branch: &str,
+    options: &SwapOptions,
+) -> Result<(), Box<dyn Error>> {
+    let Some(entry) = snapshot
+        .worktree_entry(branch)
+        .filter(|entry| entry.is_stale())
+    else {
+        return Ok(());
+    };
+    let Some(moved) = moved_location(&snapshot.common_dir, entry, dest_dir) else {
+        return Ok(());
+    };
+    let recorded = entry.path.clone();
+    if !options.repair {
+        if options.yes || logging::ndjson() || !io::stdin().is_terminal() {
+            return Err(format!(
+                "The worktree of '{branch}' is recorded at '{}', but was moved to '{}'.\nPass --repair to update the record with `git worktree repair`.",
+                recorded.display(),
+                moved.display()
+            )
+            .into());
+        }
+        eprintln!(
+            "The worktree of '{branch}' is recorded at '{}', but was moved to '{}'.",
+            recorded.display(),
+            moved.display()
+        );
+        if !confirm("Update the record with `git worktree repair`?") {
+            return Ok(());
+        }
+    }
+    run_git_success(
+        Some(dest_dir),
+        git_args!["worktree", "repair", &moved],
+        "Failed to repair the worktree record.",
+    )?;
+    info!(
+        event = "worktree-repaired",
+        branch,
+        from = recorded.to_str(),
+        to = moved.to_str(),
+        "Repaired the record of '{}': now at '{}'.",
+        recorded.display(),
+        moved.display()
+    );
+    *snapshot = RepoSnapshot::read(dest_dir)?;
+    Ok(())
+}
+
+/// Where the worktree recorded at `entry.path` went: a directory next to its old path or
+/// next to `dest_dir` whose `.git` file still points at the worktree's admin directory.
+fn moved_location(common_dir: &Path, entry: &WorktreeEntry, dest_dir: &Path) -> Option<PathBuf> {
+    let admin = admin_dir(common_dir, &entry.path)?;
+    debug!(
+        "Looking for the moved worktree of '{}' ({})...",
+        entry.path.display(),
+        admin.display()
+    );
+    let parents = [entry.path.parent(), dest_dir.parent()];
+    parents.into_iter().flatten().find_map(|parent| {
+        fs::read_dir(parent)
+            .ok()?
+            .flatten()
+            .map(|child| child.path())
+            .find(|dir| {
+                let Ok(contents) = fs::read_to_string(dir.join(".git")) else {
+                    return false;
+                };
+                gitdir_target(&contents)
+                    .is_some_and(|target| paths::same_path(&normalize_path(dir, target), &admin))
+            })
+    })
+}
+
+/// The directory under `<common_dir>/worktrees` whose `gitdir` file names `path`.
+fn admin_dir(common_dir: &Path, path: &Path) -> Option<PathBuf> {
+    fs::read_dir(common_dir.join("worktrees"))
+        .ok()?
+        .flatten()
+        .map(|admin| admin.path())
+        .find(|admin| {
+            let Ok(gitdir) = fs::read_to_string(admin.join("gitdir")) else {
+                return false;
+            };
+            let recorded = normalize_path(admin, gitdir.trim());
+            recorded
+                .parent()
+                .is_some_and(|recorded| paths::same_path(recorded, path))
+        })
+}
+
+/// The path in the `.git` file of a linked worktree, which reads `gitdir: <path>`.
+fn gitdir_target(contents: &str) -> Option<&str> {
+    contents
+        .lines()
+        .next()?
+        .strip_prefix("gitdir:")
+        .map(str::trim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{admin_dir, gitdir_target};
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn finds_the_admin_dir_of_a_recorded_path() {
+        assert_eq!(
+            gitdir_target("gitdir: /repos/main/.git/worktrees/review\n"),
+            Some("/repos/main/.git/worktrees/review")
+        );
+        assert_eq!(gitdir_target("ref: refs/heads/main\n"), None);
+
+        let common_dir =
+            std::env::temp_dir().join(format!("swap-worktree-repair-{}", std::process::id()));
+        let admin = common_dir.join("worktrees").join("review");
+        fs::create_dir_all(&admin).unwrap();
+        fs::write(admin.join("gitdir"), "/gone/review/.git\n").unwrap();
+        let found = admin_dir(&common_dir, Path::new("/gone/review"));
+        let missing = admin_dir(&common_dir, Path::new("/gone/other"));
+        fs::remove_dir_all(&common_dir).unwrap();
+        assert_eq!(found, Some(admin));
+        assert_eq!(missing, None);
+    }
+}
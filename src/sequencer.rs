@@ -0,0 +1,164 @@
+use std::error::Error;
+use std::ffi::OsString;
+use std::fmt::Write as _;
+use std::path::Path;
+
+use tracing::warn;
+
+use crate::paths::normalize_path;
+use crate::{git_args, run_git_success};
+
+/// A multi-step git operation whose state lives in the worktree's git dir and would be
+/// corrupted by stashing and switching branches underneath it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Operation {
+    Rebase,
+    Am,
+    Merge,
+    CherryPick,
+    Revert,
+    Bisect,
+}
+
+impl Operation {
+    pub(crate) fn description(self) -> &'static str {
+        match self {
+            Self::Rebase => "a rebase is in progress",
+            Self::Am => "a `git am` session is in progress",
+            Self::Merge => "a merge is in progress",
+            Self::CherryPick => "a cherry-pick is in progress",
+            Self::Revert => "a revert is in progress",
+            Self::Bisect => "a bisect is in progress",
+        }
+    }
+
+    pub(crate) fn resolution(self) -> &'static str {
+        match self {
+            Self::Rebase => "git rebase --continue or git rebase --abort",
+            Self::Am => "git am --continue or git am --abort",
+            Self::Merge => "git merge --continue or git merge --abort",
+            Self::CherryPick => "git cherry-pick --continue or git cherry-pick --abort",
+            Self::Revert => "git revert --continue or git revert --abort",
+            Self::Bisect => "git bisect reset",
+        }
+    }
+}
+
+/// Marker files under the worktree's git dir, in the order they are checked.
+const MARKERS: [&str; 7] = [
+    "rebase-merge",
+    "rebase-apply/applying",
+    "rebase-apply",
+    "MERGE_HEAD",
+    "CHERRY_PICK_HEAD",
+    "REVERT_HEAD",
+    "BISECT_LOG",
+];
+
+pub(crate) fn operations_in_progress(dir: &Path) -> Result<Vec<Operation>, Box<dyn Error>> {
+    let mut args = git_args!["rev-parse"];
+    args.extend(marker_args());
+    let output = run_git_success(Some(dir), args, "Failed to inspect worktree state.")?;
+    let paths: Vec<&str> = output.stdout.lines().collect();
+    Ok(operations_at(dir, &paths))
+}
+
+/// `git rev-parse` arguments that print the path of every marker, one per line, so that
+/// the check can ride along with other `rev-parse` queries.
+pub(crate) fn marker_args() -> Vec<OsString> {
+    MARKERS
+        .iter()
+        .flat_map(|marker| git_args!["--git-path", marker])
+        .collect()
+}
+
+/// The operations in progress in `dir`, given the lines `marker_args` printed there.
+pub(crate) fn operations_at(dir: &Path, paths: &[&str]) -> Vec<Operation> {
+    let present: Vec<bool> = paths
+        .iter()
+        .map(|path| normalize_path(dir, path).exists())
+        .collect();
+    operations_from_markers(&present)
+}
+
+fn operations_from_markers(present: &[bool]) -> Vec<Operation> {
+    let marker = |index: usize| present.get(index).copied().unwrap_or(false);
+    let mut operations = Vec::new();
+    if marker(0) {
+        operations.push(Operation::Rebase);
+    } else if marker(1) {
+        operations.push(Operation::Am);
+    } else if marker(2) {
+        operations.push(Operation::Rebase);
+    }
+    for (index, operation) in [
+        (3, Operation::Merge),
+        (4, Operation::CherryPick),
+        (5, Operation::Revert),
+        (6, Operation::Bisect),
+    ] {
+        if marker(index) {
+            operations.push(operation);
+        }
+    }
+    operations
+}
+
+/// Refuses to continue while any of the worktrees in `found` is in the middle of a rebase,
+/// merge, cherry-pick, revert, `am`, or bisect, unless `force` is set.
+pub(crate) fn ensure_no_operations_in_progress(
+    found: &[(&Path, &[Operation])],
+    force: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut report = String::new();
+    for (dir, operations) in found {
+        for operation in *operations {
+            writeln!(
+                &mut report,
+                "  '{}': {} (finish it with {})",
+                dir.display(),
+                operation.description(),
+                operation.resolution()
+            )
+            .ok();
+        }
+    }
+    if report.is_empty() {
+        return Ok(());
+    }
+    if force {
+        warn!(
+            "Continuing despite in-progress operations (--force):\n{}",
+            report.trim_end()
+        );
+        return Ok(());
+    }
+    Err(format!(
+        "Refusing to swap while git operations are in progress:\n{}\nStashing and switching now would destroy their state. Pass --force to swap anyway.",
+        report.trim_end()
+    )
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{operations_from_markers, Operation};
+
+    #[test]
+    fn distinguishes_am_from_rebase_apply() {
+        let am = [false, true, true, false, false, false, false];
+        assert_eq!(operations_from_markers(&am), vec![Operation::Am]);
+        let rebase = [false, false, true, false, false, false, false];
+        assert_eq!(operations_from_markers(&rebase), vec![Operation::Rebase]);
+    }
+
+    #[test]
+    fn reports_every_marker_present() {
+        let markers = [true, false, false, true, false, false, true];
+        assert_eq!(
+            operations_from_markers(&markers),
+            vec![Operation::Rebase, Operation::Merge, Operation::Bisect]
+        );
+        assert!(operations_from_markers(&[false; 7]).is_empty());
+    }
+}
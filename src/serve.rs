@@ -0,0 +1,337 @@
+use std::error::Error;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use clap::Parser;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::logging::ProgressFormat;
+use crate::preview::StashPreview;
+use crate::snapshot::RepoSnapshot;
+use crate::status::leftovers;
+use crate::style::ColorChoice;
+use crate::{
+    canonicalize_dir, doctor, ensure_git_worktree, interrupt, list, logging, paths, run_swap, Cli,
+};
+
+/// Standard JSON-RPC error codes.
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// The request was understood, but git or the swap failed.
+const FAILED: i64 = 1;
+/// Any request but `abort` while a swap is still running.
+const BUSY: i64 = 2;
+
+#[derive(Deserialize)]
+struct Request {
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct DirParams {
+    dir: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SwapParams {
+    destination: String,
+    branch: String,
+    /// Further command-line flags for `executeSwap`, e.g. `["--keep-stash"]`.
+    #[serde(default)]
+    args: Vec<String>,
+}
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl From<Box<dyn Error>> for RpcError {
+    fn from(err: Box<dyn Error>) -> Self {
+        Self {
+            code: FAILED,
+            message: err.to_string(),
+        }
+    }
+}
+
+/// Answers requests for the repository containing `dir` until stdin is closed.
+struct Server {
+    dir: PathBuf,
+    /// Set while `executeSwap` runs on its own thread, so `abort` can reach it.
+    running: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+/// Reads one JSON-RPC 2.0 request per line from stdin and writes one response per line to
+/// stdout, along with `progress` notifications while a swap runs. Paths in requests are
+/// relative to `dir`.
+pub(crate) fn run(dir: &Path) -> Result<(), Box<dyn Error>> {
+    interrupt::install()?;
+    let mut server = Server {
+        dir: dir.to_path_buf(),
+        running: Arc::new(AtomicBool::new(false)),
+        worker: None,
+    };
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            server.handle(&line);
+        }
+    }
+    // Let a running swap finish instead of leaving both worktrees half-swapped.
+    if let Some(worker) = server.worker.take() {
+        let _ = worker.join();
+    }
+    Ok(())
+}
+
+impl Server {
+    fn handle(&mut self, line: &str) {
+        let request: Request = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(err) => {
+                let code = if err.is_syntax() || err.is_eof() {
+                    PARSE_ERROR
+                } else {
+                    INVALID_REQUEST
+                };
+                let message = format!("Invalid request: {err}");
+                return respond(&Value::Null, Err(RpcError { code, message }));
+            }
+        };
+        // The swap sets the git timeout, hook policy and lock retries of its own command line
+        // for every git command in the process, so nothing else runs git until it is done.
+        if request.method != "abort" && self.running.load(Ordering::SeqCst) {
+            let message = "A swap is already running; wait for it or send abort.".to_string();
+            if let Some(id) = request.id {
+                respond(
+                    &id,
+                    Err(RpcError {
+                        code: BUSY,
+                        message,
+                    }),
+                );
+            }
+            return;
+        }
+        let result = match request.method.as_str() {
+            "listWorktrees" => self.list_worktrees(request.params),
+            "planSwap" => self.plan_swap(request.params),
+            "executeSwap" => {
+                return self.execute_swap(request.id.unwrap_or(Value::Null), request.params)
+            }
+            "abort" => Ok(json!({ "aborting": self.abort() })),
+            method => Err(RpcError {
+                code: METHOD_NOT_FOUND,
+                message: format!("Unknown method '{method}'."),
+            }),
+        };
+        // Requests without an id are notifications and get no response.
+        if let Some(id) = request.id {
+            respond(&id, result);
+        }
+    }
+
+    /// The worktrees of the repository, as `list --json` prints them.
+    fn list_worktrees(&self, params: Value) -> Result<Value, RpcError> {
+        let params: DirParams = parse_params(params)?;
+        let dir = match params.dir {
+            Some(dir) => self.resolve(&dir)?,
+            None => self.dir.clone(),
+        };
+        let rows = list::rows(&dir, true)?;
+        Ok(json!(rows))
+    }
+
+    /// Works out which worktrees a swap would touch and what would stop it, without
+    /// changing anything.
+    fn plan_swap(&self, params: Value) -> Result<Value, RpcError> {
+        let params: SwapParams = parse_params(params)?;
+        let dest_dir = self.resolve(&params.destination)?;
+        let snapshot = RepoSnapshot::read(&dest_dir)?;
+        let dest_branch = snapshot.current_branch()?;
+        let src_dir = snapshot.worktree_for_branch(&params.branch)?;
+        let mut problems = Vec::new();
+        for entry in &snapshot.worktrees {
+            if [&dest_dir, &src_dir]
+                .iter()
+                .any(|dir| paths::same_path(&entry.path, dir))
+            {
+                problems.extend(doctor::check_worktree(entry)?);
+            }
+        }
+        problems.extend(leftovers(&snapshot.common_dir));
+        let flag = |name: &str| params.args.iter().any(|arg| arg == name);
+        let (untracked, ignored) = (!flag("--ignore-untracked"), flag("--include-ignored"));
+        Ok(json!({
+            "dest_dir": dest_dir,
+            "dest_branch": dest_branch,
+            "src_dir": src_dir,
+            "src_branch": params.branch,
+            "stash": {
+                "dest": StashPreview::read(&dest_dir, untracked, ignored)?,
+                "src": StashPreview::read(&src_dir, untracked, ignored)?,
+            },
+            "problems": problems,
+        }))
+    }
+
+    /// Starts the swap on its own thread and answers once it is done; progress arrives as
+    /// notifications in the meantime.
+    fn execute_swap(&mut self, id: Value, params: Value) {
+        self.running.store(true, Ordering::SeqCst);
+        let cli = parse_params(params).and_then(|params: SwapParams| self.swap_cli(params));
+        let cli = match cli {
+            Ok(cli) => cli,
+            Err(err) => {
+                self.running.store(false, Ordering::SeqCst);
+                return respond(&id, Err(err));
+            }
+        };
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+        interrupt::reset();
+        let running = Arc::clone(&self.running);
+        self.worker = Some(thread::spawn(move || {
+            let result = run_swap(cli)
+                .map(|()| json!({ "swapped": true }))
+                .map_err(RpcError::from);
+            running.store(false, Ordering::SeqCst);
+            respond(&id, result);
+        }));
+    }
+
+    /// Asks the running swap to unwind, as Ctrl-C would. Returns whether one was running.
+    fn abort(&self) -> bool {
+        let running = self.running.load(Ordering::SeqCst);
+        if running {
+            interrupt::request();
+        }
+        running
+    }
+
+    /// Parses the swap as if it had been given on the command line.
+    fn swap_cli(&self, params: SwapParams) -> Result<Cli, RpcError> {
+        let destination = self.resolve(&params.destination)?;
+        let mut argv = vec!["swap-worktree".to_string()];
+        argv.extend(params.args);
+        argv.push("--".to_string());
+        argv.push(destination.display().to_string());
+        argv.push(params.branch);
+        let cli = Cli::try_parse_from(argv).map_err(|err| RpcError {
+            code: INVALID_PARAMS,
+            message: err.to_string().trim_end().to_string(),
+        })?;
+        let flags = server_flags(&cli);
+        if !flags.is_empty() {
+            return Err(RpcError {
+                code: INVALID_PARAMS,
+                message: format!(
+                    "{} apply to the whole server; pass them to `swap-worktree serve` instead.",
+                    flags.join(", ")
+                ),
+            });
+        }
+        Ok(cli)
+    }
+
+    fn resolve(&self, dir: &str) -> Result<PathBuf, RpcError> {
+        let dir = canonicalize_dir(self.dir.join(dir))?;
+        ensure_git_worktree(&dir)?;
+        Ok(dir)
+    }
+}
+
+/// Flags that `main` applies once, before the server starts: the git program and repository,
+/// and how output is written. A swap's own arguments cannot change them.
+fn server_flags(cli: &Cli) -> Vec<&'static str> {
+    [
+        (cli.git_cmd.is_some(), "--git-cmd"),
+        (cli.git_dir.is_some(), "--git-dir"),
+        (cli.work_tree.is_some(), "--work-tree"),
+        (cli.color != ColorChoice::default(), "--color"),
+        (cli.log_file.is_some(), "--log-file"),
+        (cli.porcelain, "--porcelain"),
+        (
+            cli.progress_format != ProgressFormat::default(),
+            "--progress-format",
+        ),
+        (cli.verbose > 0, "--verbose"),
+        (cli.quiet, "--quiet"),
+    ]
+    .into_iter()
+    .filter_map(|(given, flag)| given.then_some(flag))
+    .collect()
+}
+
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    // A request without params is the same as one with an empty object.
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params).map_err(|err| RpcError {
+        code: INVALID_PARAMS,
+        message: format!("Invalid params: {err}"),
+    })
+}
+
+fn respond(id: &Value, result: Result<Value, RpcError>) {
+    logging::write_line(&response(id, result).to_string());
+}
+
+fn response(id: &Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(err) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": err.code, "message": err.message },
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{response, server_flags, RpcError, BUSY};
+    use crate::Cli;
+    use clap::Parser;
+    use serde_json::json;
+
+    #[test]
+    fn builds_results_and_errors() {
+        assert_eq!(
+            response(&json!(7), Ok(json!({ "swapped": true }))),
+            json!({ "jsonrpc": "2.0", "id": 7, "result": { "swapped": true } })
+        );
+        let busy = RpcError {
+            code: BUSY,
+            message: "busy".to_string(),
+        };
+        assert_eq!(
+            response(&json!("a"), Err(busy)),
+            json!({ "jsonrpc": "2.0", "id": "a", "error": { "code": 2, "message": "busy" } })
+        );
+    }
+
+    #[test]
+    fn spots_flags_that_apply_to_the_whole_server() {
+        let cli = |args: &[&str]| {
+            Cli::try_parse_from(["swap-worktree"].iter().chain(args).chain(&["dest", "b"])).unwrap()
+        };
+        assert!(server_flags(&cli(&["--keep-stash", "--yes"])).is_empty());
+        assert_eq!(
+            server_flags(&cli(&["--git-cmd", "/opt/git", "-v", "--porcelain"])),
+            ["--git-cmd", "--porcelain", "--verbose"]
+        );
+    }
+}
@@ -0,0 +1,60 @@
+use clap::ValueEnum;
+
+/// Shells that `init` can print a wrapper function for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// Bash and zsh share one function. `status` is read-only in zsh, hence `exit_code`.
+const POSIX_FUNCTION: &str = r#"swap-worktree() {
+    local cd_file exit_code
+    cd_file="$(mktemp)" || return
+    command swap-worktree --cd-file "$cd_file" "$@"
+    exit_code=$?
+    if [ "$exit_code" -eq 0 ] && [ -s "$cd_file" ]; then
+        cd -- "$(cat -- "$cd_file")" || exit_code=$?
+    fi
+    rm -f -- "$cd_file"
+    return "$exit_code"
+}
+"#;
+
+const FISH_FUNCTION: &str = r#"function swap-worktree --wraps swap-worktree --description 'Swap worktrees, then cd into the destination'
+    set -l cd_file (mktemp); or return
+    command swap-worktree --cd-file $cd_file $argv
+    set -l exit_code $status
+    if test $exit_code -eq 0; and test -s $cd_file
+        cd (cat $cd_file); or set exit_code $status
+    end
+    rm -f $cd_file
+    return $exit_code
+end
+"#;
+
+/// The shell function that wraps the binary and, after a successful swap, changes into the
+/// worktree that now holds the requested branch.
+pub(crate) fn function(shell: Shell) -> &'static str {
+    match shell {
+        Shell::Bash | Shell::Zsh => POSIX_FUNCTION,
+        Shell::Fish => FISH_FUNCTION,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{function, Shell};
+
+    #[test]
+    fn every_wrapper_calls_the_binary_with_a_cd_file() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let script = function(shell);
+            assert!(
+                script.contains("command swap-worktree --cd-file"),
+                "{shell:?}"
+            );
+        }
+    }
+}
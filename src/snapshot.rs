@@ -0,0 +1,214 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use crate::paths::{self, normalize_path};
+use crate::sequencer::{self, Operation};
+use crate::{
+    checked_worktree_path, completion_cache, current_branch, git_args, lists_bare_repository,
+    parse_worktrees, run_git_success, WorktreeEntry,
+};
+
+/// What a swap needs to know about the repository before it changes anything: read with
+/// three git commands up front instead of one per question, which is what makes startup
+/// slow on network file systems.
+pub(crate) struct RepoSnapshot {
+    pub(crate) common_dir: PathBuf,
+    /// Top level of the worktree the snapshot was read from.
+    pub(crate) toplevel: PathBuf,
+    /// Operations in progress in that worktree.
+    pub(crate) operations: Vec<Operation>,
+    /// The worktrees in git's order: the main worktree first, unless the repository is bare.
+    pub(crate) worktrees: Vec<WorktreeEntry>,
+    /// The common dir is a bare repository: every worktree is a linked one.
+    pub(crate) bare: bool,
+    pub(crate) local_branches: Vec<String>,
+    /// Remote branches as `<remote>/<branch>`.
+    pub(crate) remote_branches: Vec<String>,
+}
+
+impl RepoSnapshot {
+    /// Reads the repository containing `dir`, failing when `dir` is not inside a worktree.
+    pub(crate) fn read(dir: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut args = git_args![
+            "rev-parse",
+            "--is-inside-work-tree",
+            "--git-common-dir",
+            "--show-toplevel"
+        ];
+        args.extend(sequencer::marker_args());
+        let output = run_git_success(
+            Some(dir),
+            args,
+            "Failed to determine whether destination is a git worktree.",
+        )?;
+        let lines: Vec<&str> = output.stdout.lines().collect();
+        let [inside, common_dir, toplevel, markers @ ..] = lines.as_slice() else {
+            return Err(format!("'{}' is not inside a git worktree.", dir.display()).into());
+        };
+        if inside.trim() != "true" {
+            return Err(format!("'{}' is not inside a git worktree.", dir.display()).into());
+        }
+        let toplevel = normalize_path(dir, toplevel.trim());
+        let worktrees = run_git_success(
+            Some(dir),
+            git_args!["worktree", "list", "--porcelain"],
+            "Failed to list worktrees.",
+        )?;
+        let (local_branches, remote_branches) = list_branches(dir)?;
+        Ok(Self {
+            common_dir: normalize_path(dir, common_dir.trim()),
+            operations: sequencer::operations_at(dir, markers),
+            worktrees: resolve_paths(parse_worktrees(dir, &worktrees.stdout)),
+            bare: lists_bare_repository(&worktrees.stdout),
+            toplevel,
+            local_branches,
+            remote_branches,
+        })
+    }
+
+    /// The worktrees for shell completion, from the completion cache. Branches without a
+    /// worktree are only listed with `refs`, and operations in progress are never read.
+    pub(crate) fn for_completion(dir: &Path, refs: bool) -> Result<Self, Box<dyn Error>> {
+        let (common_dir, porcelain) = completion_cache::worktree_porcelain(dir)?;
+        let (local_branches, remote_branches) = if refs {
+            list_branches(dir)?
+        } else {
+            (Vec::new(), Vec::new())
+        };
+        Ok(Self {
+            common_dir,
+            toplevel: dir.to_path_buf(),
+            operations: Vec::new(),
+            worktrees: parse_worktrees(dir, &porcelain),
+            bare: lists_bare_repository(&porcelain),
+            local_branches,
+            remote_branches,
+        })
+    }
+
+    /// Where the repository lives: the parent of its common git dir, or the common dir
+    /// itself for a bare repository such as `project.git`.
+    pub(crate) fn repo_root(&self) -> &Path {
+        if self.bare {
+            return &self.common_dir;
+        }
+        self.common_dir.parent().unwrap_or(&self.toplevel)
+    }
+
+    /// The worktree whose git dir is the common dir, which git cannot move or remove.
+    /// Bare repositories have none.
+    pub(crate) fn main_worktree(&self) -> Option<&WorktreeEntry> {
+        if self.bare {
+            return None;
+        }
+        self.worktrees.first()
+    }
+
+    /// The branch checked out in the worktree the snapshot was read from. Asks git again
+    /// only when the worktree list has none, so that a detached HEAD gets git's own error.
+    pub(crate) fn current_branch(&self) -> Result<String, Box<dyn Error>> {
+        let entry = self
+            .worktrees
+            .iter()
+            .find(|entry| paths::same_path(&entry.path, &self.toplevel));
+        match entry.and_then(|entry| entry.branch.clone()) {
+            Some(branch) => Ok(branch),
+            None => current_branch(&self.toplevel),
+        }
+    }
+
+    /// The worktree that has `branch` checked out, if any.
+    pub(crate) fn worktree_entry(&self, branch: &str) -> Option<&WorktreeEntry> {
+        self.worktrees
+            .iter()
+            .find(|entry| entry.branch.as_deref() == Some(branch))
+    }
+
+    /// The path of the usable worktree that has `branch` checked out.
+    pub(crate) fn worktree_for_branch(&self, branch: &str) -> Result<PathBuf, Box<dyn Error>> {
+        match self.worktree_entry(branch) {
+            Some(entry) => checked_worktree_path(entry, branch),
+            None if !self.local_branches.iter().any(|name| name == branch) => {
+                Err(format!("There is no branch named '{branch}'.").into())
+            }
+            None => Err(format!("Could not find worktree for branch '{branch}'.").into()),
+        }
+    }
+}
+
+/// Replaces the paths git recorded, which keep whatever spelling the worktree was added
+/// with (a symlink, say), by their real paths, so they display and compare like the
+/// canonicalized directories given on the command line.
+fn resolve_paths(mut worktrees: Vec<WorktreeEntry>) -> Vec<WorktreeEntry> {
+    for entry in &mut worktrees {
+        entry.path = paths::resolve(&entry.path);
+    }
+    worktrees
+}
+
+/// Local and remote branch names, from one `git for-each-ref`.
+fn list_branches(dir: &Path) -> Result<(Vec<String>, Vec<String>), Box<dyn Error>> {
+    let output = run_git_success(
+        Some(dir),
+        git_args![
+            "for-each-ref",
+            "--format=%(refname)",
+            "refs/heads/",
+            "refs/remotes/"
+        ],
+        "Failed to list branches.",
+    )?;
+    Ok(split_refs(&output.stdout))
+}
+
+fn split_refs(refs: &str) -> (Vec<String>, Vec<String>) {
+    let mut local = Vec::new();
+    let mut remote = Vec::new();
+    for name in refs.lines() {
+        if let Some(branch) = name.strip_prefix("refs/heads/") {
+            local.push(branch.to_string());
+        } else if let Some(branch) = name.strip_prefix("refs/remotes/") {
+            remote.push(branch.to_string());
+        }
+    }
+    (local, remote)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{split_refs, RepoSnapshot};
+    use crate::parse_worktrees;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn splits_local_and_remote_branches() {
+        let (local, remote) = split_refs(
+            "refs/heads/main\nrefs/heads/feature/a\nrefs/remotes/origin/HEAD\nrefs/remotes/origin/main\n",
+        );
+        assert_eq!(local, ["main", "feature/a"]);
+        assert_eq!(remote, ["origin/HEAD", "origin/main"]);
+    }
+
+    #[test]
+    fn finds_the_root_of_bare_repositories() {
+        let porcelain = "worktree /repos/project.git\nbare\n\nworktree /repos/main\nHEAD e1e1b70\nbranch refs/heads/main\n";
+        let mut snapshot = RepoSnapshot {
+            common_dir: PathBuf::from("/repos/project.git"),
+            toplevel: PathBuf::from("/repos/main"),
+            operations: Vec::new(),
+            worktrees: parse_worktrees(Path::new("/repos/main"), porcelain),
+            bare: true,
+            local_branches: Vec::new(),
+            remote_branches: Vec::new(),
+        };
+        assert_eq!(snapshot.repo_root(), Path::new("/repos/project.git"));
+        assert!(snapshot.main_worktree().is_none());
+        snapshot.common_dir = PathBuf::from("/repos/main/.git");
+        snapshot.bare = false;
+        assert_eq!(snapshot.repo_root(), Path::new("/repos/main"));
+        assert_eq!(
+            snapshot.main_worktree().map(|entry| entry.path.as_path()),
+            Some(Path::new("/repos/main"))
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use std::error::Error;
+use std::ffi::OsString;
+use std::path::Path;
+
+use clap::ValueEnum;
+
+use tracing::debug;
+
+use crate::{git_args, run_git, run_git_success};
+
+/// Where a worktree's sparse-checkout patterns belong during a swap.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum SparsePolicy {
+    /// Patterns move with the branch to the other worktree
+    FollowBranch,
+    /// Patterns stay with the directory and are re-applied to the incoming branch
+    #[default]
+    KeepDir,
+}
+
+/// The sparse-checkout configuration of one worktree.
+pub(crate) struct SparseProfile {
+    cone: bool,
+    patterns: Vec<String>,
+}
+
+/// Reads the sparse-checkout patterns of `dir`, or `None` when the worktree is not sparse.
+pub(crate) fn read_profile(dir: &Path) -> Result<Option<SparseProfile>, Box<dyn Error>> {
+    if !config_enabled(dir, "core.sparseCheckout")? {
+        return Ok(None);
+    }
+    let cone = config_enabled(dir, "core.sparseCheckoutCone")?;
+    let output = run_git_success(
+        Some(dir),
+        git_args!["sparse-checkout", "list"],
+        "Failed to read sparse-checkout patterns.",
+    )?;
+    let patterns = output
+        .stdout
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(str::to_string)
+        .collect();
+    Ok(Some(SparseProfile { cone, patterns }))
+}
+
+/// Re-applies sparse-checkout patterns after both worktrees switched branches and returns
+/// one line per worktree describing the result. Does nothing when neither side is sparse.
+pub(crate) fn restore(
+    dest_dir: &Path,
+    dest_profile: Option<SparseProfile>,
+    src_dir: &Path,
+    src_profile: Option<SparseProfile>,
+    policy: SparsePolicy,
+) -> Vec<String> {
+    if dest_profile.is_none() && src_profile.is_none() {
+        return Vec::new();
+    }
+    let targets = match policy {
+        SparsePolicy::FollowBranch => [(dest_dir, src_profile), (src_dir, dest_profile)],
+        SparsePolicy::KeepDir => [(dest_dir, dest_profile), (src_dir, src_profile)],
+    };
+    targets
+        .into_iter()
+        .map(
+            |(dir, profile)| match apply_profile(dir, profile.as_ref(), policy) {
+                Ok(summary) => format!("'{}': {summary}", dir.display()),
+                Err(err) => format!(
+                    "'{}': failed to apply sparse-checkout patterns: {err}",
+                    dir.display()
+                ),
+            },
+        )
+        .collect()
+}
+
+fn apply_profile(
+    dir: &Path,
+    profile: Option<&SparseProfile>,
+    policy: SparsePolicy,
+) -> Result<String, Box<dyn Error>> {
+    let Some(profile) = profile else {
+        if policy == SparsePolicy::KeepDir {
+            return Ok("not sparse".to_string());
+        }
+        debug!("Disabling sparse-checkout in '{}'...", dir.display());
+        run_git_success(
+            Some(dir),
+            git_args!["sparse-checkout", "disable"],
+            "Failed to disable sparse-checkout.",
+        )?;
+        return Ok("sparse-checkout disabled (full checkout)".to_string());
+    };
+    if policy == SparsePolicy::KeepDir {
+        debug!(
+            "Re-applying sparse-checkout patterns in '{}'...",
+            dir.display()
+        );
+        run_git_success(
+            Some(dir),
+            git_args!["sparse-checkout", "reapply"],
+            "Failed to re-apply sparse-checkout patterns.",
+        )?;
+    } else {
+        debug!(
+            "Setting {} sparse-checkout pattern(s) in '{}'...",
+            profile.patterns.len(),
+            dir.display()
+        );
+        run_git_success(
+            Some(dir),
+            set_args(profile),
+            "Failed to set sparse-checkout patterns.",
+        )?;
+    }
+    Ok(format!(
+        "{} sparse-checkout pattern(s) applied ({} mode)",
+        profile.patterns.len(),
+        if profile.cone { "cone" } else { "non-cone" }
+    ))
+}
+
+/// Builds the `git sparse-checkout set` arguments that recreate `profile`.
+fn set_args(profile: &SparseProfile) -> Vec<OsString> {
+    let mode = if profile.cone { "--cone" } else { "--no-cone" };
+    let mut args = git_args!["sparse-checkout", "set", mode];
+    if !profile.cone {
+        // Non-cone patterns may start with '!' or '/', so keep them away from option parsing.
+        args.extend(git_args!["--"]);
+    }
+    args.extend(profile.patterns.iter().map(Into::into));
+    args
+}
+
+fn config_enabled(dir: &Path, key: &str) -> Result<bool, Box<dyn Error>> {
+    let output = run_git(Some(dir), git_args!["config", "--bool", key])?;
+    Ok(output.status.success() && output.stdout.trim() == "true")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{set_args, SparseProfile};
+
+    #[test]
+    fn separates_non_cone_patterns_from_options() {
+        let cone = SparseProfile {
+            cone: true,
+            patterns: vec!["docs".to_string(), "src/app".to_string()],
+        };
+        assert_eq!(
+            set_args(&cone),
+            ["sparse-checkout", "set", "--cone", "docs", "src/app"]
+        );
+        let non_cone = SparseProfile {
+            cone: false,
+            patterns: vec!["/*".to_string(), "!/vendor/".to_string()],
+        };
+        assert_eq!(
+            set_args(&non_cone),
+            [
+                "sparse-checkout",
+                "set",
+                "--no-cone",
+                "--",
+                "/*",
+                "!/vendor/"
+            ]
+        );
+    }
+}
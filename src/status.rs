@@ -0,0 +1,352 @@
+use std::error::Error;
+use std::path::Path;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::history::format_timestamp;
+use crate::list::align_columns;
+use crate::{
+    display_relative, git_args, git_common_dir, journal, list_worktrees, lock, run_git,
+    run_git_success,
+};
+
+/// The message template used unless `--stash-message` or `stash_message` sets another.
+pub(crate) const DEFAULT_STASH_MESSAGE: &str = "swap-stash-{branch}";
+
+/// Start of the trailer every swap stash message ends with, `[swap-worktree <branch>]`, so
+/// swap stashes are recognized whatever the template made of the rest.
+const STASH_TRAILER: &str = " [swap-worktree ";
+
+/// Prefix of the stash messages written before the trailer existed.
+const LEGACY_STASH_PREFIX: &str = "swap-stash-";
+
+/// A stash created by a swap that is still in the stash list.
+pub(crate) struct SwapStash {
+    pub(crate) reference: String,
+    pub(crate) hash: String,
+    pub(crate) age: String,
+    pub(crate) branch: String,
+}
+
+impl SwapStash {
+    /// One listing line: reference, short hash, originating branch and age.
+    pub(crate) fn describe(&self) -> String {
+        format!(
+            "{}  {}  from '{}'  {}",
+            self.reference,
+            short_hash(&self.hash),
+            self.branch,
+            self.age
+        )
+    }
+}
+
+/// Prints every worktree of the repository containing `dir` with its branch and state, the
+/// swap stashes still in the stash list, and files left behind by interrupted swaps.
+pub(crate) fn show(dir: &Path) -> Result<(), Box<dyn Error>> {
+    println!("Worktrees:");
+    let mut rows = Vec::new();
+    for entry in list_worktrees(dir)? {
+        let head = match (&entry.branch, &entry.head) {
+            (Some(branch), _) => branch.clone(),
+            (None, Some(head)) => format!("(detached at {})", short_hash(head)),
+            (None, None) => "(bare)".to_string(),
+        };
+        let mut state = if let Some(reason) = &entry.prunable {
+            format!("prunable: {reason}")
+        } else if !entry.path.exists() {
+            "missing".to_string()
+        } else if entry.head.is_none() {
+            String::new()
+        } else {
+            worktree_state(&entry.path)?
+        };
+        match entry.locked.as_deref() {
+            Some("") => state.push_str(", locked"),
+            Some(reason) => state.push_str(&format!(", locked: {reason}")),
+            None => {}
+        }
+        rows.push([format!("  {}", display_relative(&entry.path)), head, state]);
+    }
+    print!("{}", align_columns(&rows));
+
+    let stashes = swap_stashes(dir)?;
+    if !stashes.is_empty() {
+        println!("Swap stashes (left by failed applies or --keep-stash):");
+        for stash in &stashes {
+            println!("  {}", stash.describe());
+        }
+    }
+
+    let leftovers = leftovers(&git_common_dir(dir)?);
+    if stashes.is_empty() && leftovers.is_empty() {
+        println!("No leftovers from previous swaps.");
+    }
+    if !leftovers.is_empty() {
+        println!("Leftovers from interrupted swaps:");
+        for line in leftovers {
+            println!("  {line}");
+        }
+    }
+    Ok(())
+}
+
+/// Describes files left behind by interrupted swaps in the repository with `common_dir`,
+/// each with what to do about it.
+pub(crate) fn leftovers(common_dir: &Path) -> Vec<String> {
+    let lock_path = lock::lock_path(common_dir);
+    let carry_dir = common_dir.join("swap-worktree").join("carry");
+    let journal_path = journal::journal_path(common_dir);
+    let mut leftovers = Vec::new();
+    if journal_path.exists() {
+        leftovers.push(format!(
+            "journal of an interrupted swap '{}'; run `swap-worktree recover` to finish or reverse it",
+            journal_path.display()
+        ));
+    }
+    if lock_path.exists() {
+        leftovers.push(format!(
+            "lock file '{}' ({}); delete it if no swap is running",
+            lock_path.display(),
+            lock::describe_holder(&lock_path)
+        ));
+    }
+    if carry_dir
+        .read_dir()
+        .is_ok_and(|mut entries| entries.next().is_some())
+    {
+        leftovers.push(format!(
+            "carried files waiting in '{}'; move them back into place",
+            carry_dir.display()
+        ));
+    }
+    leftovers
+}
+
+/// Lists the swap stashes of the repository containing `dir`, newest first.
+pub(crate) fn swap_stashes(dir: &Path) -> Result<Vec<SwapStash>, Box<dyn Error>> {
+    let output = run_git_success(
+        Some(dir),
+        git_args!["stash", "list", "--format=%gd%x00%H%x00%cr%x00%gs"],
+        "Failed to list stashes.",
+    )?;
+    Ok(output.stdout.lines().filter_map(parse_stash_line).collect())
+}
+
+/// The message for a stash of `dir` on `branch`: `template` with `{branch}`, `{dir}`,
+/// `{timestamp}` (UTC), and `{pid}` filled in, followed by the trailer. Newlines become
+/// spaces, since a stash subject is a single line.
+pub(crate) fn stash_message(template: &str, branch: &str, dir: &Path) -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    render_stash_message(
+        template,
+        branch,
+        &dir.display().to_string(),
+        &format_timestamp(timestamp),
+        process::id(),
+    )
+}
+
+fn render_stash_message(
+    template: &str,
+    branch: &str,
+    dir: &str,
+    timestamp: &str,
+    pid: u32,
+) -> String {
+    let message = template
+        .replace("{branch}", branch)
+        .replace("{dir}", dir)
+        .replace("{timestamp}", timestamp)
+        .replace("{pid}", &pid.to_string())
+        .replace(['\r', '\n'], " ");
+    format!("{}{STASH_TRAILER}{branch}]", message.trim())
+}
+
+/// The originating branch of a swap stash message, from its trailer or the legacy prefix.
+fn stash_branch(message: &str) -> Option<&str> {
+    match message.rsplit_once(STASH_TRAILER) {
+        Some((_, trailer)) => trailer.strip_suffix(']'),
+        None => message.strip_prefix(LEGACY_STASH_PREFIX),
+    }
+}
+
+fn parse_stash_line(line: &str) -> Option<SwapStash> {
+    let mut fields = line.splitn(4, '\0');
+    let (reference, hash, age, subject) = (
+        fields.next()?,
+        fields.next()?,
+        fields.next()?,
+        fields.next()?,
+    );
+    let (_, message) = subject.split_once(": ")?;
+    let branch = stash_branch(message)?;
+    Some(SwapStash {
+        reference: reference.to_string(),
+        hash: hash.to_string(),
+        age: age.to_string(),
+        branch: branch.to_string(),
+    })
+}
+
+/// Summarizes `git status` of a worktree as "clean" or "dirty (N uncommitted files)".
+fn worktree_state(dir: &Path) -> Result<String, Box<dyn Error>> {
+    let output = run_git(Some(dir), git_args!["status", "--porcelain"])?;
+    if !output.status.success() {
+        return Ok("status unavailable".to_string());
+    }
+    Ok(match output.stdout.lines().count() {
+        0 => "clean".to_string(),
+        1 => "dirty (1 uncommitted file)".to_string(),
+        count => format!("dirty ({count} uncommitted files)"),
+    })
+}
+
+/// What `git status --porcelain=v2 --branch` says about a worktree right after a swap.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct SwapResult {
+    branch: Option<String>,
+    upstream: Option<String>,
+    ahead: u32,
+    behind: u32,
+    /// Uncommitted files other than conflicts; after a switch they all came from the stash.
+    uncommitted: usize,
+    conflicts: Vec<String>,
+}
+
+/// One `--stat` line per worktree: its branch, where it stands against its upstream, how
+/// many uncommitted files it has (those the stash restored), and which paths are left
+/// conflicted.
+pub(crate) fn after_swap(dirs: &[&Path]) -> Vec<String> {
+    dirs.iter()
+        .map(|dir| {
+            let result = run_git_success(
+                Some(dir),
+                git_args!["status", "--porcelain=v2", "--branch"],
+                "Failed to read the worktree status.",
+            );
+            match result {
+                Ok(output) => format!(
+                    "{}: {}",
+                    display_relative(dir),
+                    parse_swap_result(&output.stdout).describe()
+                ),
+                Err(err) => format!("{}: status unavailable: {err}", display_relative(dir)),
+            }
+        })
+        .collect()
+}
+
+impl SwapResult {
+    fn describe(&self) -> String {
+        let mut parts = vec![self
+            .branch
+            .clone()
+            .unwrap_or_else(|| "(detached)".to_string())];
+        parts.push(match (&self.upstream, self.ahead, self.behind) {
+            (None, ..) if self.branch.is_some() => "no upstream".to_string(),
+            (None, ..) => String::new(),
+            (Some(upstream), 0, 0) => format!("up to date with {upstream}"),
+            (Some(upstream), ahead, 0) => format!("{ahead} ahead of {upstream}"),
+            (Some(upstream), 0, behind) => format!("{behind} behind {upstream}"),
+            (Some(upstream), ahead, behind) => {
+                format!("{ahead} ahead and {behind} behind {upstream}")
+            }
+        });
+        parts.push(match self.uncommitted {
+            0 => "clean".to_string(),
+            1 => "1 uncommitted file".to_string(),
+            files => format!("{files} uncommitted files"),
+        });
+        if !self.conflicts.is_empty() {
+            parts.push(format!("conflicts: {}", self.conflicts.join(", ")));
+        }
+        parts.retain(|part| !part.is_empty());
+        parts.join(", ")
+    }
+}
+
+fn parse_swap_result(porcelain: &str) -> SwapResult {
+    let mut result = SwapResult::default();
+    for line in porcelain.lines() {
+        if let Some(head) = line.strip_prefix("# branch.head ") {
+            result.branch = (head != "(detached)").then(|| head.to_string());
+        } else if let Some(upstream) = line.strip_prefix("# branch.upstream ") {
+            result.upstream = Some(upstream.to_string());
+        } else if let Some(counts) = line.strip_prefix("# branch.ab ") {
+            for count in counts.split_whitespace() {
+                if let Some(ahead) = count.strip_prefix('+') {
+                    result.ahead = ahead.parse().unwrap_or_default();
+                } else if let Some(behind) = count.strip_prefix('-') {
+                    result.behind = behind.parse().unwrap_or_default();
+                }
+            }
+        } else if let Some(unmerged) = line.strip_prefix("u ") {
+            // XY, submodule state, three modes and three object names precede the path.
+            if let Some(path) = unmerged.splitn(10, ' ').nth(9) {
+                result.conflicts.push(path.to_string());
+            }
+        } else if ["1 ", "2 ", "? "]
+            .iter()
+            .any(|prefix| line.starts_with(prefix))
+        {
+            result.uncommitted += 1;
+        }
+    }
+    result
+}
+
+pub(crate) fn short_hash(hash: &str) -> &str {
+    hash.get(..7).unwrap_or(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_stash_line, parse_swap_result, render_stash_message};
+
+    #[test]
+    fn recognizes_swap_stashes_only() {
+        let stash = parse_stash_line(
+            "stash@{1}\x00e1e1b70d2e8c133c96ab8050cc582f88aa83ef77\x002 days ago\x00On feature/a: swap-stash-feature/a",
+        )
+        .unwrap();
+        assert_eq!(stash.reference, "stash@{1}");
+        assert_eq!(stash.branch, "feature/a");
+        assert_eq!(stash.age, "2 days ago");
+        assert!(parse_stash_line(
+            "stash@{0}\x001c1cdd9c68b3bd55a72efa87c67fd03c4b5aa20c\x005 minutes ago\x00WIP on main: 1c1cdd9 fix"
+        )
+        .is_none());
+        let message = render_stash_message(
+            "wip {branch} ({dir}) at {timestamp}\n#{pid}",
+            "feature/a",
+            "/work/feature",
+            "2026-10-12 13:45 UTC",
+            4242,
+        );
+        assert_eq!(
+            message,
+            "wip feature/a (/work/feature) at 2026-10-12 13:45 UTC #4242 [swap-worktree feature/a]"
+        );
+        let custom = parse_stash_line(&format!(
+            "stash@{{0}}\x001c1cdd9c68b3bd55a72efa87c67fd03c4b5aa20c\x00now\x00On feature/a: {message}"
+        ))
+        .unwrap();
+        assert_eq!(custom.branch, "feature/a");
+    }
+
+    #[test]
+    fn describes_a_worktree_after_a_swap() {
+        let porcelain = "# branch.oid 8a2f1c3e\n# branch.head feature/a\n# branch.upstream origin/feature/a\n# branch.ab +2 -1\n1 .M N... 100644 100644 100644 3b18e51 3b18e51 src/main.rs\nu UU N... 100644 100644 100644 100644 1a2b3c4 5d6e7f8 9a0b1c2 README.md\n? notes.txt\n";
+        assert_eq!(
+            parse_swap_result(porcelain).describe(),
+            "feature/a, 2 ahead and 1 behind origin/feature/a, 2 uncommitted files, conflicts: README.md"
+        );
+        assert_eq!(
+            parse_swap_result("# branch.oid 8a2f1c3e\n# branch.head (detached)\n").describe(),
+            "(detached), clean"
+        );
+    }
+}
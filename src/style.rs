@@ -0,0 +1,114 @@
+use std::env;
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use clap::ValueEnum;
+
+/// When to color terminal output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum ColorChoice {
+    /// Color output that goes to a terminal, unless NO_COLOR is set
+    #[default]
+    Auto,
+    /// Always color, even when piped
+    Always,
+    /// Never color
+    Never,
+}
+
+/// Whether stdout and stderr get colors. Set once from `--color` before anything is printed.
+static STDOUT_COLOR: AtomicBool = AtomicBool::new(false);
+static STDERR_COLOR: AtomicBool = AtomicBool::new(false);
+
+const BOLD: &str = "1";
+const CYAN: &str = "1;36";
+const GREEN: &str = "32";
+const YELLOW: &str = "1;33";
+const DIM: &str = "2";
+
+/// Decides once per process whether stdout and stderr are colored.
+pub(crate) fn init(choice: ColorChoice) {
+    let (stdout, stderr) = match choice {
+        ColorChoice::Always => (true, true),
+        ColorChoice::Never => (false, false),
+        // https://no-color.org: any non-empty value disables colors.
+        ColorChoice::Auto if env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) => {
+            (false, false)
+        }
+        ColorChoice::Auto => (io::stdout().is_terminal(), io::stderr().is_terminal()),
+    };
+    STDOUT_COLOR.store(stdout, Ordering::Relaxed);
+    STDERR_COLOR.store(stderr, Ordering::Relaxed);
+}
+
+/// A step of the swap, e.g. "Step 3: Stashing changes...".
+pub(crate) fn header(text: &str) -> String {
+    paint(text, CYAN, &STDOUT_COLOR)
+}
+
+/// A branch name in stdout output.
+pub(crate) fn branch(text: &str) -> String {
+    paint(text, GREEN, &STDOUT_COLOR)
+}
+
+/// The title of a report block in stdout output.
+pub(crate) fn title(text: &str) -> String {
+    paint(text, BOLD, &STDOUT_COLOR)
+}
+
+/// The "Warning:" label of a message on stderr.
+pub(crate) fn warning(text: &str) -> String {
+    paint(text, YELLOW, &STDERR_COLOR)
+}
+
+fn paint(text: &str, code: &str, enabled: &AtomicBool) -> String {
+    if enabled.load(Ordering::Relaxed) {
+        format!("\x1b[{code}m{text}\x1b[0m")
+    } else {
+        text.to_string()
+    }
+}
+
+/// Renders the result of a swap as one aligned line per worktree, "path  before → after",
+/// with the branch names colored.
+pub(crate) fn swap_table(rows: &[(String, &str, &str)]) -> Vec<String> {
+    let width = |cell: &str| cell.chars().count();
+    let path_width = rows.iter().map(|(path, _, _)| width(path)).max();
+    let before_width = rows.iter().map(|(_, before, _)| width(before)).max();
+    let (Some(path_width), Some(before_width)) = (path_width, before_width) else {
+        return Vec::new();
+    };
+    rows.iter()
+        .map(|(path, before, after)| {
+            // Pad before coloring so escape codes do not count towards the width.
+            let padding = " ".repeat(before_width - width(before));
+            format!(
+                "{path:path_width$}  {}{padding} {} {}",
+                paint(before, DIM, &STDOUT_COLOR),
+                paint("→", DIM, &STDOUT_COLOR),
+                branch(after)
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::swap_table;
+
+    #[test]
+    fn aligns_the_swap_table() {
+        let rows = [
+            ("../review".to_string(), "main", "feature/long-name"),
+            (".".to_string(), "feature/long-name", "main"),
+        ];
+        assert_eq!(
+            swap_table(&rows),
+            [
+                "../review  main              → feature/long-name",
+                ".          feature/long-name → main",
+            ]
+        );
+        assert!(swap_table(&[]).is_empty());
+    }
+}
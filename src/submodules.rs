@@ -0,0 +1,211 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use tracing::{debug, warn};
+
+use crate::clones::{cleanup_temporary_refs, transfer_stash};
+use crate::{
+    apply_stash, finish_stash, git_args, run_git_success, stash_worktree, StashRecord, SwapOptions,
+    SwapPlan,
+};
+
+/// Changes stashed inside one submodule of a worktree, keyed by the submodule's path
+/// relative to the worktree root so they can be restored on the other side.
+pub(crate) struct SubmoduleStash {
+    path: String,
+    stash: StashRecord,
+}
+
+/// Stashes the submodules of both worktrees of `plan`. When the second worktree fails, the
+/// first one gets its submodule changes back.
+pub(crate) fn stash_both(
+    plan: &SwapPlan,
+    options: &SwapOptions,
+) -> Result<(Vec<SubmoduleStash>, Vec<SubmoduleStash>), Box<dyn Error>> {
+    let dest = stash_submodules(&plan.dest_dir, &plan.dest_branch, options)?;
+    match stash_submodules(&plan.src_dir, &plan.src_branch, options) {
+        Ok(src) => Ok((dest, src)),
+        Err(err) => {
+            restore_original(&dest, options);
+            Err(err)
+        }
+    }
+}
+
+/// Stashes every initialized submodule (recursively) of `dir` that has local changes. When
+/// one fails, the submodules stashed before it get their changes back.
+fn stash_submodules(
+    dir: &Path,
+    branch: &str,
+    options: &SwapOptions,
+) -> Result<Vec<SubmoduleStash>, Box<dyn Error>> {
+    let mut stashes = Vec::new();
+    for path in submodule_paths(dir)? {
+        let label = format!("{branch}:{path}");
+        match stash_worktree(&dir.join(&path), &label, options) {
+            Ok(Some(stash)) => stashes.push(SubmoduleStash { path, stash }),
+            Ok(None) => {}
+            Err(err) => {
+                restore_original(&stashes, options);
+                return Err(err);
+            }
+        }
+    }
+    Ok(stashes)
+}
+
+/// Re-applies submodule stashes in the submodules they were taken from, after a swap that
+/// did not happen. A stash that does not apply stays in its submodule's stash list.
+pub(crate) fn restore_original(stashes: &[SubmoduleStash], options: &SwapOptions) {
+    for submodule in stashes {
+        let stash = &submodule.stash;
+        match apply_stash(&stash.origin, &stash.branch, Some(stash)) {
+            Ok(()) => finish_stash(stash, options),
+            Err(detail) => warn!(
+                "Could not restore the changes of submodule '{}': {detail}\nThey stay in its stash list ({}).",
+                stash.origin.display(),
+                stash.hash
+            ),
+        }
+    }
+}
+
+/// Checks out the submodule commits recorded by each worktree's new branch, then moves the
+/// submodule stashes over. Returns one human-readable line per submodule result.
+pub(crate) fn restore_submodules(
+    plan: &SwapPlan,
+    dest_stashes: Vec<SubmoduleStash>,
+    src_stashes: Vec<SubmoduleStash>,
+    options: &SwapOptions,
+) -> Vec<String> {
+    let mut report = Vec::new();
+    for dir in [&plan.dest_dir, &plan.src_dir] {
+        report.extend(update_submodules(dir));
+    }
+    for (target_dir, stashes) in [(&plan.dest_dir, src_stashes), (&plan.src_dir, dest_stashes)] {
+        for submodule in stashes {
+            report.push(move_submodule_stash(target_dir, &submodule, options));
+        }
+    }
+    report
+}
+
+fn submodule_paths(dir: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let output = run_git_success(
+        Some(dir),
+        git_args![
+            "submodule",
+            "foreach",
+            "--quiet",
+            "--recursive",
+            "echo \"$displaypath\""
+        ],
+        "Failed to list submodules.",
+    )?;
+    Ok(output
+        .stdout
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+fn update_submodules(dir: &Path) -> Vec<String> {
+    debug!("Updating submodules in '{}'...", dir.display());
+    if let Err(err) = run_git_success(
+        Some(dir),
+        git_args!["submodule", "update", "--init", "--recursive"],
+        "Failed to update submodules.",
+    ) {
+        return vec![format!(
+            "'{}': submodule update failed: {err}",
+            dir.display()
+        )];
+    }
+    match run_git_success(
+        Some(dir),
+        git_args!["submodule", "status", "--recursive"],
+        "Failed to read submodule status.",
+    ) {
+        Ok(output) => output
+            .stdout
+            .lines()
+            .filter_map(parse_status_line)
+            .map(|(path, commit, note)| format!("'{}': {path} at {commit}{note}", dir.display()))
+            .collect(),
+        Err(err) => vec![format!("'{}': {err}", dir.display())],
+    }
+}
+
+/// Parses one `git submodule status` line into path, short commit, and a note for
+/// submodules that are not at the commit the superproject records.
+fn parse_status_line(line: &str) -> Option<(String, String, &'static str)> {
+    let flag = line.chars().next()?;
+    let mut fields = line[flag.len_utf8()..].split_whitespace();
+    let commit = fields.next()?;
+    let path = fields.next()?;
+    let note = match flag {
+        '-' => " (not initialized)",
+        '+' => " (differs from the recorded commit)",
+        'U' => " (merge conflicts)",
+        _ => "",
+    };
+    Some((path.to_string(), commit.chars().take(7).collect(), note))
+}
+
+fn move_submodule_stash(
+    target_dir: &Path,
+    submodule: &SubmoduleStash,
+    options: &SwapOptions,
+) -> String {
+    let path = &submodule.path;
+    let target: PathBuf = target_dir.join(path);
+    let origin = &submodule.stash.origin;
+    if !target.join(".git").exists() {
+        return format!(
+            "'{}': {path} is not checked out here; its changes stay in the stash of '{}' ({})",
+            target_dir.display(),
+            origin.display(),
+            submodule.stash.hash
+        );
+    }
+    let result = transfer_stash(origin, &target, &submodule.stash)
+        .map_err(|err| err.to_string())
+        .and_then(|()| apply_stash(&target, &submodule.stash.branch, Some(&submodule.stash)));
+    cleanup_temporary_refs(origin);
+    cleanup_temporary_refs(&target);
+    match result {
+        Ok(()) => {
+            finish_stash(&submodule.stash, options);
+            format!("'{}': {path} changes restored", target_dir.display())
+        }
+        Err(detail) => format!(
+            "'{}': {path} changes could not be applied, the stash was kept in '{}' ({}): {detail}",
+            target_dir.display(),
+            origin.display(),
+            submodule.stash.hash
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_status_line;
+
+    #[test]
+    fn parses_submodule_status_lines() {
+        assert_eq!(
+            parse_status_line(" 3f2a9c1d5e6b7a8c9d0e1f2a3b4c5d6e7f8a9b0c libs/core (v1.2.0)"),
+            Some(("libs/core".to_string(), "3f2a9c1".to_string(), ""))
+        );
+        assert_eq!(
+            parse_status_line("+3f2a9c1d5e6b7a8c9d0e1f2a3b4c5d6e7f8a9b0c vendor/ui (heads/main)"),
+            Some((
+                "vendor/ui".to_string(),
+                "3f2a9c1".to_string(),
+                " (differs from the recorded commit)"
+            ))
+        );
+        assert_eq!(parse_status_line(""), None);
+    }
+}
@@ -0,0 +1,202 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing::info;
+
+use crate::{describe_args, display_relative, step_label, Logger};
+
+/// Git commands that change a worktree; everything before the first of them is discovery.
+const SWAPPING_COMMANDS: [&str; 3] = ["stash push", "switch", "worktree move"];
+
+/// One git command of the swap.
+struct Timed {
+    /// Since [`start`].
+    offset: Duration,
+    elapsed: Duration,
+    label: String,
+    command: String,
+    dir: Option<PathBuf>,
+}
+
+/// The git commands of the current swap, collected from `--timings` on.
+struct Timings {
+    started: Instant,
+    commands: Vec<Timed>,
+}
+
+static TIMINGS: Mutex<Option<Timings>> = Mutex::new(None);
+
+/// Starts collecting the duration of every git command.
+pub(crate) fn start() {
+    if let Ok(mut timings) = TIMINGS.lock() {
+        *timings = Some(Timings {
+            started: Instant::now(),
+            commands: Vec::new(),
+        });
+    }
+}
+
+/// Notes a git command that took `elapsed`, when [`start`] was called.
+pub(crate) fn record(dir: Option<&Path>, args: &[OsString], elapsed: Duration) {
+    let Ok(mut timings) = TIMINGS.lock() else {
+        return;
+    };
+    let Some(timings) = timings.as_mut() else {
+        return;
+    };
+    timings.commands.push(Timed {
+        offset: timings.started.elapsed().saturating_sub(elapsed),
+        elapsed,
+        label: step_label(args),
+        command: describe_args(args),
+        dir: dir.map(Path::to_path_buf),
+    });
+}
+
+/// Prints the time per phase and the list of git commands, and emits both as `timing`
+/// events. Does nothing without `--timings`.
+pub(crate) fn report(logger: &Logger) {
+    let Ok(timings) = TIMINGS.lock() else {
+        return;
+    };
+    let Some(timings) = timings.as_ref() else {
+        return;
+    };
+    let location = |dir: &Option<PathBuf>| dir.as_deref().map(display_relative).unwrap_or_default();
+    let mut phases = phases(&timings.commands);
+    phases.push(("total".to_string(), None, timings.started.elapsed()));
+    let label_width = phases
+        .iter()
+        .map(|(label, _, _)| label.chars().count())
+        .max()
+        .unwrap_or_default();
+    let dir_width = phases
+        .iter()
+        .map(|(_, dir, _)| location(dir).chars().count())
+        .max()
+        .unwrap_or_default();
+    let phase_lines: Vec<String> = phases
+        .iter()
+        .map(|(label, dir, elapsed)| {
+            info!(
+                event = "timing",
+                kind = "phase",
+                phase = label,
+                worktree = dir.as_deref().and_then(Path::to_str),
+                ms = millis(*elapsed),
+                "{label}: {}",
+                seconds(*elapsed)
+            );
+            format!(
+                "{label:label_width$}  {:dir_width$}  {:>8}",
+                location(dir),
+                seconds(*elapsed)
+            )
+        })
+        .collect();
+    let command_lines: Vec<String> = timings
+        .commands
+        .iter()
+        .map(|timed| {
+            let dir = location(&timed.dir);
+            info!(
+                event = "timing",
+                kind = "command",
+                command = timed.command,
+                worktree = timed.dir.as_deref().and_then(Path::to_str),
+                start_ms = millis(timed.offset),
+                ms = millis(timed.elapsed),
+                "git {}: {}",
+                timed.command,
+                seconds(timed.elapsed)
+            );
+            format!(
+                "+{}  {}  {dir}  git {}",
+                seconds(timed.offset),
+                seconds(timed.elapsed),
+                timed.command
+            )
+        })
+        .collect();
+    logger.report("Timings", &phase_lines);
+    logger.report("Git commands", &command_lines);
+}
+
+/// Splits the commands into discovery, up to the first command that changes a worktree,
+/// and the time spent per git command and worktree after it, in order of first use.
+fn phases(commands: &[Timed]) -> Vec<(String, Option<PathBuf>, Duration)> {
+    let first_change = commands
+        .iter()
+        .position(|timed| SWAPPING_COMMANDS.contains(&timed.label.as_str()))
+        .unwrap_or(commands.len());
+    let discovery = commands
+        .get(first_change)
+        .map(|timed| timed.offset)
+        .or_else(|| commands.last().map(|timed| timed.offset + timed.elapsed))
+        .unwrap_or_default();
+    let mut phases = vec![("discovery".to_string(), None, discovery)];
+    for timed in &commands[first_change..] {
+        match phases
+            .iter_mut()
+            .find(|(label, dir, _)| *label == timed.label && *dir == timed.dir)
+        {
+            Some((_, _, elapsed)) => *elapsed += timed.elapsed,
+            None => phases.push((timed.label.clone(), timed.dir.clone(), timed.elapsed)),
+        }
+    }
+    phases
+}
+
+fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+/// Seconds with millisecond precision, e.g. "1.204s".
+fn seconds(duration: Duration) -> String {
+    format!("{:.3}s", duration.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{phases, Timed};
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    #[test]
+    fn groups_commands_into_phases() {
+        let timed = |offset: u64, elapsed: u64, label: &str, dir: &str| Timed {
+            offset: Duration::from_millis(offset),
+            elapsed: Duration::from_millis(elapsed),
+            label: label.to_string(),
+            command: label.to_string(),
+            dir: Some(PathBuf::from(dir)),
+        };
+        let commands = [
+            timed(0, 40, "rev-parse", "/work/app"),
+            timed(50, 30, "worktree list", "/work/app"),
+            timed(120, 300, "stash push", "/work/review"),
+            timed(120, 200, "stash push", "/work/app"),
+            timed(430, 900, "switch", "/work/review"),
+            timed(1330, 10, "stash list", "/work/review"),
+            timed(1340, 20, "stash list", "/work/review"),
+        ];
+        let ms = Duration::from_millis;
+        let review = Some(PathBuf::from("/work/review"));
+        assert_eq!(
+            phases(&commands),
+            [
+                ("discovery".to_string(), None, ms(120)),
+                ("stash push".to_string(), review.clone(), ms(300)),
+                (
+                    "stash push".to_string(),
+                    Some(PathBuf::from("/work/app")),
+                    ms(200)
+                ),
+                ("switch".to_string(), review.clone(), ms(900)),
+                ("stash list".to_string(), review, ms(30)),
+            ]
+        );
+    }
+}
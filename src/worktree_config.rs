@@ -0,0 +1,210 @@
+use std::error::Error;
+use std::path::Path;
+
+use clap::ValueEnum;
+
+use tracing::debug;
+
+use crate::{git_args, run_git, run_git_success};
+
+/// Where per-worktree config (`config.worktree`) belongs during a swap.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum WorktreeConfigPolicy {
+    /// Every key moves with the branch to the other worktree
+    FollowBranch,
+    /// Keys stay with the directory unless listed with --carry-config
+    #[default]
+    KeepDir,
+}
+
+/// Keys owned by `--sparse`, which are never moved by this module.
+const SPARSE_KEYS: [&str; 2] = ["core.sparsecheckout", "core.sparsecheckoutcone"];
+
+/// One `key = value` line of a worktree's `config.worktree`. Multi-valued keys appear once
+/// per value, in file order.
+#[derive(Debug, PartialEq, Eq)]
+struct Entry {
+    key: String,
+    value: Option<String>,
+}
+
+/// The per-worktree settings of both worktrees, read before the swap.
+pub(crate) struct Snapshot {
+    dest: Vec<Entry>,
+    src: Vec<Entry>,
+}
+
+/// Reads `config.worktree` of both worktrees, or `None` when the repository does not have
+/// `extensions.worktreeConfig` enabled.
+pub(crate) fn read(dest_dir: &Path, src_dir: &Path) -> Result<Option<Snapshot>, Box<dyn Error>> {
+    let output = run_git(
+        Some(dest_dir),
+        git_args!["config", "--bool", "extensions.worktreeConfig"],
+    )?;
+    if !output.status.success() || output.stdout.trim() != "true" {
+        return Ok(None);
+    }
+    Ok(Some(Snapshot {
+        dest: read_entries(dest_dir)?,
+        src: read_entries(src_dir)?,
+    }))
+}
+
+/// Exchanges the keys selected by `policy` and `carry` between the worktrees after both
+/// switched branches, and returns one line per worktree describing what moved.
+pub(crate) fn swap(
+    dest_dir: &Path,
+    src_dir: &Path,
+    snapshot: Snapshot,
+    policy: WorktreeConfigPolicy,
+    carry: &[String],
+) -> Vec<String> {
+    let mut keys: Vec<&str> = snapshot
+        .dest
+        .iter()
+        .chain(&snapshot.src)
+        .map(|entry| entry.key.as_str())
+        .filter(|key| follows_branch(key, policy, carry))
+        .collect();
+    keys.sort_unstable();
+    keys.dedup();
+    if keys.is_empty() {
+        return Vec::new();
+    }
+    [
+        (dest_dir, &snapshot.dest, &snapshot.src),
+        (src_dir, &snapshot.src, &snapshot.dest),
+    ]
+    .into_iter()
+    .map(
+        |(dir, own, incoming)| match replace_keys(dir, &keys, own, incoming) {
+            Ok(()) => format!(
+                "'{}': {} key(s) moved with the branch ({})",
+                dir.display(),
+                keys.len(),
+                keys.join(", ")
+            ),
+            Err(err) => format!(
+                "'{}': failed to move per-worktree config: {err}",
+                dir.display()
+            ),
+        },
+    )
+    .collect()
+}
+
+fn replace_keys(
+    dir: &Path,
+    keys: &[&str],
+    own: &[Entry],
+    incoming: &[Entry],
+) -> Result<(), Box<dyn Error>> {
+    debug!(
+        "Moving {} per-worktree config key(s) into '{}'...",
+        keys.len(),
+        dir.display()
+    );
+    for key in keys {
+        if own.iter().any(|entry| entry.key == *key) {
+            run_git_success(
+                Some(dir),
+                git_args!["config", "--worktree", "--unset-all", key],
+                "Failed to unset per-worktree config.",
+            )?;
+        }
+        for entry in incoming.iter().filter(|entry| entry.key == *key) {
+            let mut args = git_args!["config", "--worktree", "--add", key];
+            // A key without a value is boolean true; git stores it as "key = true" either way.
+            args.extend(git_args![entry.value.as_deref().unwrap_or("true")]);
+            run_git_success(Some(dir), args, "Failed to set per-worktree config.")?;
+        }
+    }
+    Ok(())
+}
+
+fn read_entries(dir: &Path) -> Result<Vec<Entry>, Box<dyn Error>> {
+    let output = run_git(
+        Some(dir),
+        git_args!["config", "--worktree", "--list", "--null"],
+    )?;
+    // A worktree without a config.worktree file has nothing to list.
+    if !output.status.success() {
+        return Ok(Vec::new());
+    }
+    Ok(parse_entries(&output.stdout))
+}
+
+/// Parses `git config --list --null` output, where each entry is `key\nvalue\0` or just
+/// `key\0` for a key without a value.
+fn parse_entries(listing: &str) -> Vec<Entry> {
+    listing
+        .split('\0')
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| match entry.split_once('\n') {
+            Some((key, value)) => Entry {
+                key: key.to_string(),
+                value: Some(value.to_string()),
+            },
+            None => Entry {
+                key: entry.to_string(),
+                value: None,
+            },
+        })
+        .collect()
+}
+
+/// Whether `key` moves with the branch. `carry` lists full keys or whole sections
+/// (`user` matches `user.email`); git reports keys with lowercase section and name.
+fn follows_branch(key: &str, policy: WorktreeConfigPolicy, carry: &[String]) -> bool {
+    if SPARSE_KEYS.contains(&key) {
+        return false;
+    }
+    policy == WorktreeConfigPolicy::FollowBranch
+        || carry.iter().any(|pattern| {
+            let pattern = pattern.to_ascii_lowercase();
+            key.to_ascii_lowercase() == pattern
+                || key.to_ascii_lowercase().starts_with(&format!("{pattern}."))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{follows_branch, parse_entries, Entry, WorktreeConfigPolicy};
+
+    #[test]
+    fn parses_null_separated_entries() {
+        assert_eq!(
+            parse_entries("user.email\nme@example.com\0core.bare\0"),
+            vec![
+                Entry {
+                    key: "user.email".to_string(),
+                    value: Some("me@example.com".to_string()),
+                },
+                Entry {
+                    key: "core.bare".to_string(),
+                    value: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn carries_listed_keys_and_sections_only() {
+        let carry = vec!["User".to_string(), "core.autocrlf".to_string()];
+        let keep = WorktreeConfigPolicy::KeepDir;
+        assert!(follows_branch("user.email", keep, &carry));
+        assert!(follows_branch("core.autocrlf", keep, &carry));
+        assert!(!follows_branch("core.filemode", keep, &carry));
+        assert!(!follows_branch("username.x", keep, &carry));
+        assert!(follows_branch(
+            "core.filemode",
+            WorktreeConfigPolicy::FollowBranch,
+            &[]
+        ));
+        assert!(!follows_branch(
+            "core.sparsecheckout",
+            WorktreeConfigPolicy::FollowBranch,
+            &[]
+        ));
+    }
+}
@@ -0,0 +1,244 @@
+use std::error::Error;
+use std::io::{self, IsTerminal as _};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use clap::ValueEnum;
+use tracing::debug;
+
+use crate::hooks::Stage;
+use crate::snapshot::RepoSnapshot;
+use crate::{
+    confirm, display_relative, exec, git_args, history, journal, logging, paths, run_git_success,
+    status, timings, Logger, SwapOptions, SwapPlan, WorktreeEntry,
+};
+
+/// How the two worktrees trade branches.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum SwapMode {
+    /// Switch each worktree to the other branch, stashing uncommitted changes
+    #[default]
+    Switch,
+    /// Trade the directories with `git worktree move`; each branch keeps its files
+    Move,
+}
+
+/// Swaps the worktrees of `plan` by moving their directories instead of switching branches:
+/// the destination path ends up holding the source branch with every file of its worktree,
+/// untracked and ignored ones included, and the other way round. No branch ref, index or
+/// stash is touched.
+pub(crate) fn run(
+    plan: &SwapPlan,
+    snapshot: &RepoSnapshot,
+    options: &SwapOptions,
+    logger: &Logger,
+) -> Result<(), Box<dyn Error>> {
+    let common_dir = snapshot.common_dir.as_path();
+    // The destination may be given as one of its subdirectories; the whole worktree moves.
+    let plan = &SwapPlan {
+        dest_dir: snapshot.toplevel.clone(),
+        dest_branch: plan.dest_branch.clone(),
+        src_dir: plan.src_dir.clone(),
+        src_branch: plan.src_branch.clone(),
+    };
+    ensure_movable(snapshot.main_worktree(), plan)?;
+    if !options.yes && !logging::ndjson() && io::stdin().is_terminal() {
+        eprintln!(
+            "{} {} ⇄ {} {}; the directories trade places with everything in them",
+            display_relative(&plan.dest_dir),
+            plan.dest_branch,
+            plan.src_branch,
+            display_relative(&plan.src_dir)
+        );
+        if !confirm("Swap?") {
+            return Err(
+                "Swap cancelled; nothing was changed. Pass --yes to swap without asking.".into(),
+            );
+        }
+    }
+    let _journal = journal::Journal::begin(common_dir, plan)?;
+    options.hooks.run(Stage::PreSwap, plan)?;
+    let _step = logging::step(3, "Moving the worktree directories...");
+    if let Err(err) = move_directories(common_dir, plan, options.ignore_locks) {
+        let first_line = err
+            .to_string()
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        history::append(
+            common_dir,
+            &history::HistoryEntry::new(plan, &[], format!("failed: {first_line}")),
+        );
+        return Err(err);
+    }
+    journal::finish();
+    history::append(
+        common_dir,
+        &history::HistoryEntry::new(plan, &[], "swapped".to_string()),
+    );
+    let exec_report = exec::run_in_worktrees(
+        &options.exec,
+        &[&plan.dest_dir, &plan.src_dir],
+        options.serial,
+    );
+    let stat_report = if options.stat {
+        status::after_swap(&[&plan.dest_dir, &plan.src_dir])
+    } else {
+        Vec::new()
+    };
+    logger.summary(plan, false);
+    logger.report("Exec", &exec_report);
+    logger.report("Worktree state", &stat_report);
+    timings::report(logger);
+    options.hooks.run(Stage::PostSwap, plan)
+}
+
+/// Puts a directory swap that was interrupted in `plan` back in order, using the parking
+/// path recorded in the journal: finished when the source had already moved into the
+/// destination path, reversed otherwise. Returns which of the two happened.
+pub(crate) fn recover(
+    common_dir: &Path,
+    plan: &SwapPlan,
+    parking: &Path,
+) -> Result<&'static str, Box<dyn Error>> {
+    if !parking.exists() {
+        return Ok("nothing was left parked");
+    }
+    if plan.dest_dir.exists() && !plan.src_dir.exists() {
+        move_worktree(common_dir, parking, &plan.src_dir, true)?;
+        Ok("finished")
+    } else {
+        move_worktree(common_dir, parking, &plan.dest_dir, true)?;
+        Ok("reversed")
+    }
+}
+
+/// Fails when either worktree of `plan` is the main worktree or has submodules, which
+/// `git worktree move` refuses to move.
+fn ensure_movable(main: Option<&WorktreeEntry>, plan: &SwapPlan) -> Result<(), Box<dyn Error>> {
+    for dir in [&plan.dest_dir, &plan.src_dir] {
+        if main.is_some_and(|main| paths::same_path(&main.path, dir)) {
+            return Err(format!(
+                "'{}' is the main worktree, which `git worktree move` cannot move.\nSwap it without --mode move.",
+                dir.display()
+            )
+            .into());
+        }
+        let index = run_git_success(
+            Some(dir),
+            git_args!["ls-files", "--stage", "-z"],
+            "Failed to list the index.",
+        )?;
+        if has_submodules(&index.stdout) {
+            return Err(format!(
+                "'{}' contains submodules, which `git worktree move` cannot move.\nSwap it without --mode move.",
+                dir.display()
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Whether `git ls-files --stage -z` lists a submodule (a gitlink, mode 160000).
+fn has_submodules(index: &str) -> bool {
+    index.split('\0').any(|entry| entry.starts_with("160000 "))
+}
+
+/// Moves the destination aside, the source into the destination path, and the parked
+/// destination into the source path. A failed move is undone before the error is returned.
+fn move_directories(common_dir: &Path, plan: &SwapPlan, force: bool) -> Result<(), Box<dyn Error>> {
+    let SwapPlan {
+        dest_dir, src_dir, ..
+    } = plan;
+    let parking = parking_path(dest_dir);
+    if parking.exists() {
+        return Err(format!(
+            "Cannot park '{}': '{}' already exists.",
+            dest_dir.display(),
+            parking.display()
+        )
+        .into());
+    }
+    journal::record("parking", &parking.display().to_string());
+    move_worktree(common_dir, dest_dir, &parking, force)?;
+    journal::record("step", "moved-dest");
+    if let Err(err) = move_worktree(common_dir, src_dir, dest_dir, force) {
+        return Err(undo(
+            err,
+            move_worktree(common_dir, &parking, dest_dir, true),
+        ));
+    }
+    journal::record("step", "moved-src");
+    if let Err(err) = move_worktree(common_dir, &parking, src_dir, force) {
+        let undone = move_worktree(common_dir, dest_dir, src_dir, true)
+            .and_then(|()| move_worktree(common_dir, &parking, dest_dir, true));
+        return Err(undo(err, undone));
+    }
+    Ok(())
+}
+
+/// Reports a failed move together with whether putting the directories back worked.
+fn undo(err: Box<dyn Error>, undone: Result<(), Box<dyn Error>>) -> Box<dyn Error> {
+    match undone {
+        Ok(()) => format!("{err}\nBoth worktrees were moved back; nothing was swapped.").into(),
+        Err(undo_err) => format!(
+            "{err}\nMoving the worktrees back failed as well: {undo_err}\nRun `swap-worktree recover` to put them in order."
+        )
+        .into(),
+    }
+}
+
+/// A free name next to `dir` for holding it while the other worktree moves into its place.
+/// It stays on the same file system, so every move is a rename.
+fn parking_path(dir: &Path) -> PathBuf {
+    let name = dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    dir.with_file_name(format!(".{name}.swap-worktree-{}", process::id()))
+}
+
+/// Runs `git worktree move` from the common dir, so that neither worktree is git's working
+/// directory while it moves. `force` also moves a locked worktree.
+fn move_worktree(
+    common_dir: &Path,
+    from: &Path,
+    to: &Path,
+    force: bool,
+) -> Result<(), Box<dyn Error>> {
+    debug!("Moving '{}' to '{}'...", from.display(), to.display());
+    let mut args = git_args!["worktree", "move"];
+    if force {
+        args.extend(git_args!["--force", "--force"]);
+    }
+    args.extend(git_args![from, to]);
+    run_git_success(Some(common_dir), args, "Failed to move worktree.")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{has_submodules, parking_path};
+    use std::path::Path;
+
+    #[test]
+    fn parks_next_to_the_worktree() {
+        let parking = parking_path(Path::new("/work/myrepo-review"));
+        assert_eq!(parking.parent(), Some(Path::new("/work")));
+        assert_eq!(
+            parking.file_name().unwrap().to_string_lossy(),
+            format!(".myrepo-review.swap-worktree-{}", std::process::id())
+        );
+    }
+
+    #[test]
+    fn spots_submodules_in_the_index() {
+        let index = "100644 e69de29bb2d1d6434b8b29ae775ad8c2e48c5391 0\tREADME.md\0";
+        assert!(!has_submodules(index));
+        let index =
+            format!("{index}160000 4b825dc642cb6eb9a060e54bf8d69288fbee4904 0\tvendor/lib\0");
+        assert!(has_submodules(&index));
+    }
+}